@@ -20,6 +20,72 @@ impl Universe {
         self.cells[index] = Cell::Alive;
     }
 
+    /// Parses a pattern in the Run Length Encoded format, e.g.
+    /// "x = 3, y = 3\nbob$2bo$3o!" for a glider.
+    pub fn from_rle(rle: &str) -> Result<Self, String> {
+        let mut lines = rle
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        let header = lines.next().ok_or("missing header")?;
+        let (mut width, mut height) = (0, 0);
+        for field in header.split(',') {
+            let mut pair = field.splitn(2, '=').map(str::trim);
+            match (pair.next(), pair.next()) {
+                (Some("x"), Some(value)) => width = value.parse().unwrap_or(0),
+                (Some("y"), Some(value)) => height = value.parse().unwrap_or(0),
+                (Some("rule"), Some(_)) => (),
+                _ => return Err(format!("invalid header field '{}'", field.trim())),
+            }
+        }
+        if width == 0 || height == 0 {
+            return Err(String::from("invalid dimensions"));
+        }
+
+        let mut u = Universe::new(width, height);
+        let (mut x, mut y) = (0, 0);
+        let mut count = String::new();
+
+        for c in lines.flat_map(str::chars) {
+            if c.is_ascii_digit() {
+                count.push(c);
+                continue;
+            }
+            if c.is_whitespace() {
+                continue;
+            }
+
+            let n = match count.is_empty() {
+                true => 1,
+                false => count.parse::<usize>().map_err(|e| e.to_string())?,
+            };
+            count.clear();
+
+            match c {
+                'b' | 'o' => {
+                    if x + n > width || y >= height {
+                        return Err(String::from("pattern exceeds its dimensions"));
+                    }
+                    if c == 'o' {
+                        for i in x..x + n {
+                            u.set_alive(i, y);
+                        }
+                    }
+                    x += n;
+                }
+                '$' => {
+                    x = 0;
+                    y += n;
+                }
+                '!' => return Ok(u),
+                _ => return Err(format!("unknown token '{}'", c)),
+            }
+        }
+
+        Err(String::from("missing '!' at the end of the pattern"))
+    }
+
     pub fn next_gen(&mut self) {
         let mut cells = vec![Cell::Dead; self.cells.len()];
 
@@ -28,7 +94,7 @@ impl Universe {
             let num_neighbors = self.num_alive_neighbors(x, y);
 
             match cell {
-                Cell::Alive if num_neighbors < 2 || num_neighbors > 3 => {
+                Cell::Alive if !(2..=3).contains(&num_neighbors) => {
                     cells[i] = Cell::Dead;
                 }
                 Cell::Dead if num_neighbors == 3 => {
@@ -81,7 +147,7 @@ impl Universe {
     ) -> (usize, usize) {
         match dir {
             Direction::North => match (x, y) {
-                (_, y) if y == 0 => (x, self.height - 1),
+                (_, 0) => (x, self.height - 1),
                 (_, _) => (x, y - 1),
             },
             Direction::NorthEast => match (x, y) {
@@ -156,7 +222,7 @@ impl std::fmt::Display for Universe {
             }
         }
 
-        writeln!(f, "")
+        writeln!(f)
     }
 }
 
@@ -209,6 +275,28 @@ mod tests {
         assert_eq!(Cell::Alive, u.cells[10]);
     }
 
+    #[test]
+    fn parse_rle_glider() {
+        let u = Universe::from_rle("#N Glider\nx = 3, y = 3, rule = B3/S23\nbob$2bo$3o!")
+            .unwrap();
+
+        let alive = u
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| **cell == Cell::Alive)
+            .map(|(i, _)| u.get_position(i))
+            .collect::<Vec<_>>();
+
+        assert_eq!((3, 3), (u.width, u.height));
+        assert_eq!(vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)], alive);
+
+        assert!(Universe::from_rle("x = 2, y = 3\nbob$2bo$3o!").is_err());
+        assert!(Universe::from_rle("x = 3, y = 3\nbxb$2bo$3o!").is_err());
+        assert!(Universe::from_rle("x = 3, y = 0\n!").is_err());
+        assert!(Universe::from_rle("x = 3, y = 3\nbob$2bo$3o").is_err());
+    }
+
     #[test]
     fn simple_universe() {
         let mut u = Universe::new(5, 5);
@@ -37,6 +37,12 @@ enum Subcommand {
 
     #[structopt(name = "glider_gun", about = "Glider gun")]
     GliderGun,
+
+    #[structopt(name = "rle", about = "Pattern from a Run Length Encoded file")]
+    Rle {
+        #[structopt(parse(from_os_str))]
+        path: std::path::PathBuf,
+    },
 }
 fn main() {
     let args = Args::from_args();
@@ -44,6 +50,10 @@ fn main() {
         Subcommand::Random => random(),
         Subcommand::Carlos => carlos(),
         Subcommand::GliderGun => glider_gun(),
+        Subcommand::Rle { path } => {
+            let rle = std::fs::read_to_string(path).expect("error reading the pattern");
+            Universe::from_rle(&rle).expect("error parsing the pattern")
+        }
     };
     run_gol(universe);
 }
@@ -59,8 +69,8 @@ fn run_gol(mut u: Universe) {
 
     for i in 0.. {
         println!(
-            "width={} height={} {}{}ms",
-            u.width, u.height, "\u{2764}", UPDATE_INTERVAL
+            "width={} height={} \u{2764}{}ms",
+            u.width, u.height, UPDATE_INTERVAL
         );
         print!("{}", u);
 
@@ -74,9 +84,8 @@ fn run_gol(mut u: Universe) {
         u.next_gen();
         let stop = start.elapsed();
         println!(
-            "#{} {}{:02}s:{:09}ns",
+            "#{} \u{23f1}{:02}s:{:09}ns",
             i,
-            "\u{23f1}",
             stop.as_secs(),
             stop.subsec_nanos()
         );
@@ -1,24 +1,99 @@
 //! Game-of-Life EEE implementation
+#![allow(dead_code)]
+
+mod common;
 
 use common::gol::*;
 
-use ::reee::eee::effect::Effect;
-use ::reee::eee::entity::EntityCore;
-use ::reee::supervisor::Supervisor;
+use ::reee::eee::Effect;
+use ::reee::eee::Entity;
+use ::reee::node::Node;
+
+use std::time::{Duration, Instant};
+
+const UPDATE_INTERVAL: u64 = 50;
+const NUM_GENERATIONS: u64 = 100;
+const WIDTH: usize = 40;
+const HEIGHT: usize = 20;
+
+/// Computes the next generation of its universe for every generation number
+/// broadcast in 'cur_gen', and emits it into 'new_gen'.
+struct Evolver(Universe);
+
+impl Entity for Evolver {
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        match effect {
+            Effect::U64(generation) => {
+                let start = Instant::now();
+                self.0.next_gen();
+                let stop = start.elapsed();
 
-use std::time::Instant;
+                Effect::from(format!(
+                    "#{} \u{23f1}{:02}s:{:09}ns\n{}",
+                    generation,
+                    stop.as_secs(),
+                    stop.subsec_nanos(),
+                    self.0
+                ))
+            }
+            _ => Effect::Empty,
+        }
+    }
+}
+
+/// Prints every generation broadcast in 'new_gen'.
+struct Printer;
+
+impl Entity for Printer {
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        if let Effect::Utf8(generation) = effect {
+            print!("{}", generation);
+        }
+        Effect::Empty
+    }
+}
 
 fn main() {
     println!("Running Game-Of-Life EEE implementation...");
 
-    let mut sv = Supervisor::new().expect("couldn't create supervisor");
+    let mut node = Node::new().expect("couldn't create node");
+    node.init();
 
-    let x = sv.create_environment("cur_gen").expect("error creating 'cur_gen' env.");
-    let y = sv.create_environment("new_gen").expect("error creating 'new_gen' env.");
+    let x = node.create_environment("cur_gen").expect("error creating 'cur_gen' env.");
+    let y = node.create_environment("new_gen").expect("error creating 'new_gen' env.");
 
-    let mut a = sv.create_entity().expect("error creating entity");
+    let mut a = node.create_entity().expect("error creating entity");
+    a.inject_core(Box::new(Evolver(glider())));
 
-    sv.join_environments(&mut a, vec![&x.name()]).expect("error joining 'cur_gen' env.");
-    sv.affect_environments(&mut a, vec![&y.name()])
+    node.join_environments(&mut a, vec![&x.name()])
+        .expect("error joining 'cur_gen' env.");
+    node.affect_environments(&mut a, vec![&y.name()])
         .expect("error affecting 'new_gen' env");
+
+    let mut b = node.create_entity().expect("error creating entity");
+    b.inject_core(Box::new(Printer));
+
+    node.join_environments(&mut b, vec![&y.name()])
+        .expect("error joining 'new_gen' env.");
+
+    for generation in 0..NUM_GENERATIONS {
+        node.submit_and_wait(Effect::U64(generation), x.name(), Duration::from_secs(1))
+            .expect("error submitting the next generation");
+
+        std::thread::sleep(Duration::from_millis(UPDATE_INTERVAL));
+    }
+
+    node.shutdown().expect("error shutting down the node");
+}
+
+fn glider() -> Universe {
+    let mut u = Universe::new(WIDTH, HEIGHT);
+
+    u.set_alive(1, 0);
+    u.set_alive(2, 1);
+    u.set_alive(0, 2);
+    u.set_alive(1, 2);
+    u.set_alive(2, 2);
+
+    u
 }
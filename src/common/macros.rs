@@ -1,6 +1,7 @@
 //! Common macros
 
 #[cfg(test)]
+#[allow(unused_macros)]
 macro_rules! sleep {
     ($duration:expr) => {
         std::thread::sleep(std::time::Duration::from_millis($duration));
@@ -8,6 +9,7 @@ macro_rules! sleep {
 }
 
 #[cfg(test)]
+#[allow(unused_macros)]
 macro_rules! sleep_ns {
     ($duration:expr) => {
         std::thread::sleep(std::time::Duration::from_nanos($duration));
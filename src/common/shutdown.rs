@@ -7,21 +7,33 @@ use super::trigger::{
     TriggerHandle,
 };
 
+use std::sync::{Arc, Mutex};
+
 use tokio::prelude::*;
 use tokio::runtime::current_thread;
 use tokio_signal::ctrl_c;
 
-/// A graceful shutdown abstraction.
-pub(crate) struct GracefulShutdown {
-    trigger: Trigger,
+/// A graceful shutdown abstraction. Clones share the termination signal, so any of
+/// them can send it.
+#[derive(Clone)]
+pub struct GracefulShutdown {
+    trigger: Arc<Mutex<Trigger>>,
+}
+
+impl Default for GracefulShutdown {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GracefulShutdown {
+    /// Creates a shutdown that hasn't been signaled yet.
     pub fn new() -> Self {
-        Self { trigger: Trigger::new() }
+        Self { trigger: shared_mut!(Trigger::new()) }
     }
 
     /// Blocks the current thread until CTRL-C is observed
+    #[cfg(not(all(unix, feature = "unix-signals")))]
     pub fn wait_for_ctrl_c(&self) {
         // Create a future, that completes when the first CTRL-C is observed
         let ctrl_c = ctrl_c().flatten_stream().take(1).for_each(|_| Ok(()));
@@ -30,13 +42,51 @@ impl GracefulShutdown {
         current_thread::block_on_all(ctrl_c).unwrap();
     }
 
+    /// Blocks the current thread until CTRL-C is observed, or SIGTERM if `sigterm` is
+    /// set. Every SIGHUP observed in the meantime calls `on_sighup`, if given.
+    #[cfg(all(unix, feature = "unix-signals"))]
+    pub fn wait_for_signals(&self, sigterm: bool, on_sighup: Option<&(dyn Fn() + Send)>) {
+        use tokio_signal::unix::{Signal, SIGHUP, SIGINT, SIGTERM};
+        use tokio_signal::IoStream;
+
+        let mut signals: IoStream<i32> =
+            Box::new(ctrl_c().flatten_stream().map(|_| SIGINT));
+        if sigterm {
+            signals = Box::new(signals.select(Signal::new(SIGTERM).flatten_stream()));
+        }
+        if on_sighup.is_some() {
+            signals = Box::new(signals.select(Signal::new(SIGHUP).flatten_stream()));
+        }
+
+        // Create a future, that completes when the first signal other than SIGHUP is
+        // observed
+        let shutdown = signals
+            .filter(|signal| match (*signal, on_sighup) {
+                (SIGHUP, Some(on_sighup)) => {
+                    on_sighup();
+                    false
+                }
+                _ => true,
+            })
+            .take(1)
+            .for_each(|_| Ok(()));
+
+        // Block the current thread until the 'shutdown' future completes
+        current_thread::block_on_all(shutdown).unwrap();
+    }
+
     /// Sends a termination signal to all holders of a handle.
-    pub fn send_sig_term(&mut self) -> Result<(), Error> {
-        self.trigger.pull()
+    pub fn send_sig_term(&self) -> Result<(), Error> {
+        unlock!(self.trigger).pull()
+    }
+
+    /// Returns true, if the termination signal has been sent.
+    pub fn is_shutting_down(&self) -> bool {
+        unlock!(self.trigger).is_pulled()
     }
 
     /// Returns a shutdown listener.
     pub fn get_listener(&self) -> TriggerHandle {
-        self.trigger.get_handle()
+        unlock!(self.trigger).get_handle()
     }
 }
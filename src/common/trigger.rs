@@ -7,8 +7,16 @@ use tokio::sync::watch::{
     Sender,
 };
 
+/// Listens for a trigger to be pulled, e.g. a shutdown signal.
 pub struct TriggerHandle(pub Receiver<bool>);
 
+impl TriggerHandle {
+    /// Returns true, if the trigger has been pulled.
+    pub fn is_pulled(&self) -> bool {
+        *self.0.get_ref()
+    }
+}
+
 pub(crate) struct Trigger {
     trigger: Sender<bool>,
     handle: Receiver<bool>,
@@ -24,6 +32,10 @@ impl Trigger {
         TriggerHandle(self.handle.clone())
     }
 
+    pub fn is_pulled(&self) -> bool {
+        *self.handle.get_ref()
+    }
+
     pub fn pull(&mut self) -> Result<(), Error> {
         Ok(self.trigger.broadcast(true)?)
     }
@@ -1,20 +1,57 @@
 //! Watcher
 
 use futures::task::AtomicTask;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 
 pub struct Watcher {
     pub task: Arc<AtomicTask>,
+
+    /// Whether the task was notified since it last started polling
+    pending: Arc<AtomicBool>,
+
+    /// The number of notifications that reached the task
+    num_notifies: Arc<AtomicU64>,
 }
 
 impl Watcher {
     pub fn new() -> Self {
-        Watcher { task: Arc::new(AtomicTask::new()) }
+        Watcher {
+            task: Arc::new(AtomicTask::new()),
+            pending: Arc::new(AtomicBool::new(false)),
+            num_notifies: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Registers the current task to be woken up and lets the next notification
+    /// through. Must be called at the top of a poll, before looking for work, so
+    /// work added after that is always followed by a notification.
+    pub fn register(&self) {
+        self.task.register();
+        self.pending.store(false, Ordering::SeqCst);
+    }
+
+    /// Wakes up the task, unless it was notified already and hasn't started polling
+    /// since. A burst of notifications only wakes it up once.
+    pub fn notify(&self) {
+        if !self.pending.swap(true, Ordering::SeqCst) {
+            self.num_notifies.fetch_add(1, Ordering::Relaxed);
+            self.task.notify();
+        }
+    }
+
+    /// Returns the number of notifications that reached the task.
+    pub fn num_notifies(&self) -> u64 {
+        self.num_notifies.load(Ordering::Relaxed)
     }
 }
 
 impl Clone for Watcher {
     fn clone(&self) -> Self {
-        Self { task: Arc::clone(&self.task) }
+        Self {
+            task: Arc::clone(&self.task),
+            pending: Arc::clone(&self.pending),
+            num_notifies: Arc::clone(&self.num_notifies),
+        }
     }
 }
@@ -1,3 +1,6 @@
 //! Constants
 
 pub const BROADCAST_BUFFER_SIZE: usize = 10;
+pub const TIMER_RESOLUTION_MS: u64 = 10;
+pub const MAX_UNBATCH_DEPTH: usize = 2;
+pub const DEFAULT_MAX_HOPS: u32 = 64;
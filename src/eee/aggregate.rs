@@ -0,0 +1,244 @@
+//! Aggregate
+
+use super::effect::Effect;
+use super::entity::{BatchLimits, Entity};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How the numbers of a window are aggregated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AggKind {
+    /// The sum of all numbers
+    Sum,
+    /// The average of all numbers
+    Avg,
+    /// The largest number
+    Max,
+}
+
+/// A core that aggregates the numbers it receives, as integers or as text, over a
+/// time window, and emits the aggregate as an ASCII effect when the window closes.
+///
+/// A window starts with the first effect received after the last window closed.
+/// Effects that aren't numbers are skipped, and a window without any number emits
+/// nothing.
+///
+/// # Example
+/// ```
+/// use reee::eee::{AggKind, AggregateCore, Effect, Entity};
+/// use std::time::Duration;
+///
+/// let mut core = AggregateCore::new(AggKind::Max, Duration::from_secs(1));
+/// let window = vec![Effect::from("4"), Effect::from("7"), Effect::from("2")];
+///
+/// assert_eq!(vec![Effect::ascii("7").unwrap()], core.process_batch(window));
+/// ```
+pub struct AggregateCore {
+    kind: AggKind,
+    window: Duration,
+    num_skipped: usize,
+}
+
+impl AggregateCore {
+    /// Creates a core aggregating the numbers received within each window.
+    pub fn new(kind: AggKind, window: Duration) -> Self {
+        Self { kind, window, num_skipped: 0 }
+    }
+
+    /// Returns the number of effects skipped so far, because they weren't numbers.
+    pub fn num_skipped(&self) -> usize {
+        self.num_skipped
+    }
+
+    /// Returns the aggregate of some numbers, if there are any.
+    fn aggregate(&self, numbers: &[f64]) -> Option<f64> {
+        if numbers.is_empty() {
+            return None;
+        }
+
+        let sum = numbers.iter().sum::<f64>();
+        Some(match self.kind {
+            AggKind::Sum => sum,
+            AggKind::Avg => sum / numbers.len() as f64,
+            AggKind::Max => numbers.iter().cloned().fold(f64::MIN, f64::max),
+        })
+    }
+}
+
+impl Entity for AggregateCore {
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        self.process_batch(vec![effect]).pop().unwrap_or(Effect::Empty)
+    }
+
+    fn batch_limits(&self) -> Option<BatchLimits> {
+        Some(BatchLimits { max_size: usize::MAX, window: self.window })
+    }
+
+    fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+        let mut numbers = vec![];
+        for effect in effects.iter() {
+            match number(effect) {
+                Some(number) => numbers.push(number),
+                None => self.num_skipped += 1,
+            }
+        }
+
+        match self.aggregate(&numbers) {
+            Some(aggregate) => {
+                vec![Effect::ascii(aggregate.to_string()).expect("numbers are ASCII")]
+            }
+            None => vec![],
+        }
+    }
+}
+
+/// A core that emits statistics about the numbers it receives, as integers or as
+/// text, over a time window. When the window closes it emits a map effect with the
+/// keys `min`, `max`, `mean` and `count`.
+///
+/// Windows start like those of an [`AggregateCore`]. Effects that aren't numbers are
+/// skipped, and a window without any number emits nothing.
+///
+/// # Example
+/// ```
+/// use reee::eee::{Effect, Entity, StatsCore};
+/// use std::time::Duration;
+///
+/// let mut core = StatsCore::new(Duration::from_secs(1));
+/// let stats = core.process_batch(vec![Effect::from(4_u8), Effect::from("2")]);
+///
+/// assert_eq!(Some("3"), stats[0].get("mean"));
+/// ```
+pub struct StatsCore {
+    window: Duration,
+    num_skipped: usize,
+}
+
+impl StatsCore {
+    /// Creates a core emitting the statistics of each window.
+    pub fn new(window: Duration) -> Self {
+        Self { window, num_skipped: 0 }
+    }
+
+    /// Returns the number of effects skipped so far, because they weren't numbers.
+    pub fn num_skipped(&self) -> usize {
+        self.num_skipped
+    }
+}
+
+impl Entity for StatsCore {
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        self.process_batch(vec![effect]).pop().unwrap_or(Effect::Empty)
+    }
+
+    fn batch_limits(&self) -> Option<BatchLimits> {
+        Some(BatchLimits { max_size: usize::MAX, window: self.window })
+    }
+
+    fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+        let mut numbers = vec![];
+        for effect in effects.iter() {
+            match number(effect) {
+                Some(number) => numbers.push(number),
+                None => self.num_skipped += 1,
+            }
+        }
+
+        if numbers.is_empty() {
+            return vec![];
+        }
+
+        let min = numbers.iter().cloned().fold(f64::MAX, f64::min);
+        let max = numbers.iter().cloned().fold(f64::MIN, f64::max);
+        let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+
+        let mut stats = BTreeMap::new();
+        stats.insert(String::from("min"), min.to_string());
+        stats.insert(String::from("max"), max.to_string());
+        stats.insert(String::from("mean"), mean.to_string());
+        stats.insert(String::from("count"), numbers.len().to_string());
+
+        vec![Effect::from(stats)]
+    }
+}
+
+/// Returns the number an integer effect, or a text effect parsing as a number,
+/// stands for.
+fn number(effect: &Effect) -> Option<f64> {
+    Some(match effect {
+        Effect::U8(n) => f64::from(*n),
+        Effect::U16(n) => f64::from(*n),
+        Effect::U32(n) => f64::from(*n),
+        Effect::U64(n) => *n as f64,
+        Effect::I8(n) => f64::from(*n),
+        Effect::I16(n) => f64::from(*n),
+        Effect::I32(n) => f64::from(*n),
+        Effect::I64(n) => *n as f64,
+        _ => return effect.as_str().and_then(|s| s.trim().parse::<f64>().ok()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(inputs: &[&str]) -> Vec<Effect> {
+        inputs.iter().map(|input| Effect::from(*input)).collect()
+    }
+
+    #[test]
+    fn aggregate_numbers_of_a_window() {
+        let mut sum = AggregateCore::new(AggKind::Sum, Duration::from_secs(1));
+        assert_eq!(
+            vec![Effect::ascii("6").unwrap()],
+            sum.process_batch(window(&["1", "2", "3"]))
+        );
+
+        let mut avg = AggregateCore::new(AggKind::Avg, Duration::from_secs(1));
+        assert_eq!(
+            vec![Effect::ascii("2.5").unwrap()],
+            avg.process_batch(window(&["2", "3"]))
+        );
+    }
+
+    #[test]
+    fn aggregate_integers_and_skip_non_numeric_inputs() {
+        let mut sum = AggregateCore::new(AggKind::Sum, Duration::from_secs(1));
+
+        let outputs = sum.process_batch(vec![
+            Effect::from("1"),
+            Effect::from("one"),
+            Effect::from(2_u8),
+            Effect::from(" 2 "),
+        ]);
+        assert_eq!(vec![Effect::ascii("5").unwrap()], outputs);
+        assert_eq!(1, sum.num_skipped());
+
+        assert!(sum.process_batch(vec![Effect::from("x"), Effect::Empty]).is_empty());
+        assert_eq!(3, sum.num_skipped());
+    }
+
+    #[test]
+    fn emit_stats_of_a_window() {
+        let mut stats = StatsCore::new(Duration::from_secs(1));
+
+        let outputs = stats.process_batch(vec![
+            Effect::from(4_u8),
+            Effect::from("-2"),
+            Effect::from("two"),
+            Effect::from(10_i64),
+            Effect::from(" 0.5 "),
+        ]);
+        assert_eq!(1, outputs.len());
+        assert_eq!(Some("-2"), outputs[0].get("min"));
+        assert_eq!(Some("10"), outputs[0].get("max"));
+        assert_eq!(Some("3.125"), outputs[0].get("mean"));
+        assert_eq!(Some("4"), outputs[0].get("count"));
+        assert_eq!(1, stats.num_skipped());
+
+        assert!(stats.process_batch(window(&["x"])).is_empty());
+        assert_eq!(Effect::Empty, stats.process_effect(Effect::Empty, "X"));
+        assert_eq!(3, stats.num_skipped());
+    }
+}
@@ -1,10 +1,20 @@
 //! Effect
 
+use crate::errors::{Error, Result};
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::{Read, Write};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Represents an Effect in the EEE model.
+///
+/// Effects are totally ordered by variant in the order they are declared, and
+/// effects of the same variant by payload. Numbers compare by value, texts and bytes
+/// lexicographically, maps by their sorted entries and batches by their effects.
 #[allow(missing_docs)]
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Effect {
     Empty,
     U8(u8),
@@ -17,8 +27,424 @@ pub enum Effect {
     I64(i64),
     Bool(bool),
     Char(char),
-    String(Arc<String>),
+    Ascii(Arc<String>),
+    Utf8(Arc<String>),
     Bytes(Arc<Vec<u8>>),
+    Map(Arc<BTreeMap<String, String>>),
+    Barrier(u64),
+    Batch(Arc<Vec<Effect>>),
+}
+
+/// What is known about an effect besides its payload when it leaves the node.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Meta {
+    /// The time the effect left the node in milliseconds since the Unix epoch
+    pub ts: u64,
+}
+
+impl Meta {
+    /// Creates the meta data of an effect leaving the node right now.
+    pub fn now() -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+
+        Meta { ts }
+    }
+}
+
+/// The byte order of a number stored in a byte effect.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Endian {
+    /// Least significant byte first
+    Little,
+    /// Most significant byte first
+    Big,
+}
+
+/// The kind of an effect with a raw payload, i.e. all but maps and barriers. Text
+/// and byte payloads are read with the given length in bytes.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EffectKind {
+    Empty,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    Bool,
+    Char,
+    Ascii(usize),
+    Utf8(usize),
+    Bytes(usize),
+}
+
+macro_rules! read_le {
+    ($reader:expr, $type:ty) => {{
+        let mut bytes = [0; std::mem::size_of::<$type>()];
+        $reader.read_exact(&mut bytes)?;
+        <$type>::from_le_bytes(bytes)
+    }};
+}
+
+macro_rules! impl_read {
+    ($name:ident, $type:ty) => {
+        /// Reads a number at the given byte offset of a byte effect. Returns None if
+        /// this isn't a byte effect or if it is too short.
+        pub fn $name(&self, offset: usize, endian: Endian) -> Option<$type> {
+            const SIZE: usize = std::mem::size_of::<$type>();
+
+            let bytes: [u8; SIZE] = match self {
+                Effect::Bytes(b) => {
+                    b.get(offset..offset.checked_add(SIZE)?)?.try_into().ok()?
+                }
+                _ => return None,
+            };
+
+            Some(match endian {
+                Endian::Little => <$type>::from_le_bytes(bytes),
+                Endian::Big => <$type>::from_be_bytes(bytes),
+            })
+        }
+    };
+}
+
+impl Effect {
+    impl_read!(read_u16, u16);
+    impl_read!(read_u32, u32);
+    impl_read!(read_u64, u64);
+
+    /// Interprets a byte effect of exactly two bytes as a little-endian u16.
+    pub fn as_u16_le(&self) -> Option<u16> {
+        match self {
+            Effect::Bytes(b) if b.len() == 2 => self.read_u16(0, Endian::Little),
+            _ => None,
+        }
+    }
+
+    /// Reads the raw payload of an effect of the given kind. Numbers are expected in
+    /// little-endian byte order. Fails if the reader ends before the payload does, or
+    /// if the payload isn't a valid value of that kind.
+    pub fn from_reader<R: Read>(mut reader: R, kind: EffectKind) -> Result<Effect> {
+        Ok(match kind {
+            EffectKind::Empty => Effect::Empty,
+            EffectKind::U8 => Effect::U8(read_le!(reader, u8)),
+            EffectKind::U16 => Effect::U16(read_le!(reader, u16)),
+            EffectKind::U32 => Effect::U32(read_le!(reader, u32)),
+            EffectKind::U64 => Effect::U64(read_le!(reader, u64)),
+            EffectKind::I8 => Effect::I8(read_le!(reader, i8)),
+            EffectKind::I16 => Effect::I16(read_le!(reader, i16)),
+            EffectKind::I32 => Effect::I32(read_le!(reader, i32)),
+            EffectKind::I64 => Effect::I64(read_le!(reader, i64)),
+            EffectKind::Bool => match read_le!(reader, u8) {
+                0 => Effect::Bool(false),
+                1 => Effect::Bool(true),
+                _ => return Err(Error::App("Invalid bool payload")),
+            },
+            EffectKind::Char => {
+                let c = std::char::from_u32(read_le!(reader, u32));
+                Effect::Char(c.ok_or("Invalid char payload")?)
+            }
+            EffectKind::Ascii(len) => {
+                let mut bytes = vec![0; len];
+                reader.read_exact(&mut bytes)?;
+                Effect::ascii(String::from_utf8(bytes).map_err(|_| "Non-ASCII text")?)?
+            }
+            EffectKind::Utf8(len) => {
+                let mut bytes = vec![0; len];
+                reader.read_exact(&mut bytes)?;
+                Effect::text(
+                    String::from_utf8(bytes).map_err(|_| "Invalid UTF-8 payload")?,
+                )
+            }
+            EffectKind::Bytes(len) => {
+                let mut bytes = vec![0; len];
+                reader.read_exact(&mut bytes)?;
+                Effect::from(bytes)
+            }
+        })
+    }
+
+    /// Writes the raw payload of the effect, numbers in little-endian byte order.
+    /// Fails for maps and barriers, which have no raw payload.
+    pub fn to_writer<W: Write>(&self, mut writer: W) -> Result<()> {
+        match self {
+            Effect::Empty => (),
+            Effect::U8(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::U16(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::U32(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::U64(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::I8(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::I16(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::I32(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::I64(n) => writer.write_all(&n.to_le_bytes())?,
+            Effect::Bool(b) => writer.write_all(&[u8::from(*b)])?,
+            Effect::Char(c) => writer.write_all(&u32::from(*c).to_le_bytes())?,
+            Effect::Ascii(s) | Effect::Utf8(s) => writer.write_all(s.as_bytes())?,
+            Effect::Bytes(b) => writer.write_all(b)?,
+            Effect::Map(_) | Effect::Barrier(_) | Effect::Batch(_) => {
+                return Err(Error::App("This effect has no raw payload"))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the kind of the effect, if it has a raw payload.
+    pub fn kind(&self) -> Option<EffectKind> {
+        Some(match self {
+            Effect::Empty => EffectKind::Empty,
+            Effect::U8(_) => EffectKind::U8,
+            Effect::U16(_) => EffectKind::U16,
+            Effect::U32(_) => EffectKind::U32,
+            Effect::U64(_) => EffectKind::U64,
+            Effect::I8(_) => EffectKind::I8,
+            Effect::I16(_) => EffectKind::I16,
+            Effect::I32(_) => EffectKind::I32,
+            Effect::I64(_) => EffectKind::I64,
+            Effect::Bool(_) => EffectKind::Bool,
+            Effect::Char(_) => EffectKind::Char,
+            Effect::Ascii(s) => EffectKind::Ascii(s.len()),
+            Effect::Utf8(s) => EffectKind::Utf8(s.len()),
+            Effect::Bytes(b) => EffectKind::Bytes(b.len()),
+            Effect::Map(_) | Effect::Barrier(_) | Effect::Batch(_) => return None,
+        })
+    }
+
+    /// Returns the name of the effect's variant.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Effect::Empty => "Empty",
+            Effect::U8(_) => "U8",
+            Effect::U16(_) => "U16",
+            Effect::U32(_) => "U32",
+            Effect::U64(_) => "U64",
+            Effect::I8(_) => "I8",
+            Effect::I16(_) => "I16",
+            Effect::I32(_) => "I32",
+            Effect::I64(_) => "I64",
+            Effect::Bool(_) => "Bool",
+            Effect::Char(_) => "Char",
+            Effect::Ascii(_) => "Ascii",
+            Effect::Utf8(_) => "Utf8",
+            Effect::Bytes(_) => "Bytes",
+            Effect::Map(_) => "Map",
+            Effect::Barrier(_) => "Barrier",
+            Effect::Batch(_) => "Batch",
+        }
+    }
+
+    /// Returns the value of a key of a map effect. Returns None if this isn't a map
+    /// effect or if it doesn't contain the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self {
+            Effect::Map(m) => m.get(key).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of the effect's payload in bytes.
+    pub fn size(&self) -> usize {
+        use std::mem::size_of;
+
+        match self {
+            Effect::Empty | Effect::Barrier(_) => 0,
+            Effect::U8(_) | Effect::I8(_) | Effect::Bool(_) => 1,
+            Effect::U16(_) | Effect::I16(_) => 2,
+            Effect::U32(_) | Effect::I32(_) => 4,
+            Effect::U64(_) | Effect::I64(_) => 8,
+            Effect::Char(_) => size_of::<char>(),
+            Effect::Ascii(s) | Effect::Utf8(s) => s.len(),
+            Effect::Bytes(b) => b.len(),
+            Effect::Map(m) => m.iter().map(|(k, v)| k.len() + v.len()).sum(),
+            Effect::Batch(effects) => effects.iter().map(Effect::size).sum(),
+        }
+    }
+
+    /// Creates an effect carrying many effects at once.
+    pub fn batch(effects: Vec<Effect>) -> Effect {
+        Effect::Batch(Arc::new(effects))
+    }
+
+    /// Returns the effects contained in a batch effect. Nested batches are
+    /// flattened as well, until `depth` levels of batches were taken apart. Any
+    /// other effect, or any effect if `depth` is 0, is returned as it is.
+    pub fn unbatch(&self, depth: usize) -> Vec<Effect> {
+        match self {
+            Effect::Batch(effects) if depth > 0 => {
+                effects.iter().flat_map(|effect| effect.unbatch(depth - 1)).collect()
+            }
+            _ => vec![self.clone()],
+        }
+    }
+
+    /// Renders the effect as JSON: numbers, booleans and texts as themselves, a char
+    /// as a string, bytes as an array of numbers, a map as an object, a barrier as
+    /// `{"barrier": id}`, a batch as an array of its effects, and an empty effect as
+    /// null.
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_value(&self) -> serde_json::Value {
+        use serde_json::Value;
+
+        match self {
+            Effect::Empty => Value::Null,
+            Effect::U8(n) => Value::from(*n),
+            Effect::U16(n) => Value::from(*n),
+            Effect::U32(n) => Value::from(*n),
+            Effect::U64(n) => Value::from(*n),
+            Effect::I8(n) => Value::from(*n),
+            Effect::I16(n) => Value::from(*n),
+            Effect::I32(n) => Value::from(*n),
+            Effect::I64(n) => Value::from(*n),
+            Effect::Bool(b) => Value::from(*b),
+            Effect::Char(c) => Value::from(c.to_string()),
+            Effect::Ascii(s) | Effect::Utf8(s) => Value::from(s.as_str()),
+            Effect::Bytes(b) => Value::from(b.as_slice()),
+            Effect::Map(m) => Value::Object(
+                m.iter().map(|(k, v)| (k.clone(), Value::from(v.as_str()))).collect(),
+            ),
+            Effect::Barrier(id) => serde_json::json!({ "barrier": id }),
+            Effect::Batch(effects) => {
+                Value::Array(effects.iter().map(Effect::to_json_value).collect())
+            }
+        }
+    }
+
+    /// Renders the effect as a line of newline-delimited JSON, i.e. an object
+    /// `{"kind": .., "payload": .., "ts": ..}` followed by a newline, with the variant
+    /// as the kind and the payload as rendered by `to_json_value`.
+    #[cfg(feature = "serde_json")]
+    pub fn to_jsonl(&self, meta: &Meta) -> String {
+        let object = serde_json::json!({
+            "kind": self.variant(),
+            "payload": self.to_json_value(),
+            "ts": meta.ts,
+        });
+
+        format!("{}\n", object)
+    }
+
+    /// Creates a text effect from any UTF-8 text.
+    pub fn text<S: Into<String>>(text: S) -> Effect {
+        Effect::Utf8(Arc::new(text.into()))
+    }
+
+    /// Creates a text effect from ASCII text. Fails if the text isn't ASCII.
+    pub fn ascii<S: Into<String>>(text: S) -> Result<Effect> {
+        let text = text.into();
+        if !text.is_ascii() {
+            return Err(Error::App("Non-ASCII text"));
+        }
+
+        Ok(Effect::Ascii(Arc::new(text)))
+    }
+
+    /// Returns the text of a text effect, whether ASCII or UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Effect::Ascii(s) | Effect::Utf8(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Splits a text effect into text effects of at most `n` characters each.
+    /// Splits happen on char boundaries only, so each chunk is valid UTF-8. Any
+    /// other effect is returned as a single chunk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    pub fn chunk_chars(&self, n: usize) -> Vec<Effect> {
+        assert!(n != 0, "chunk size must be non-zero");
+
+        let (s, variant): (_, fn(Arc<String>) -> Effect) = match self {
+            Effect::Ascii(s) => (s, Effect::Ascii),
+            Effect::Utf8(s) => (s, Effect::Utf8),
+            _ => return vec![self.clone()],
+        };
+
+        let mut chunks = vec![];
+        let mut start = 0;
+
+        for (i, (offset, _)) in s.char_indices().enumerate() {
+            if i > 0 && i % n == 0 {
+                chunks.push(variant(Arc::new(s[start..offset].into())));
+                start = offset;
+            }
+        }
+        if start < s.len() {
+            chunks.push(variant(Arc::new(s[start..].into())));
+        }
+
+        chunks
+    }
+
+    /// Computes a sparse diff turning `prev` into `self`. Both must be byte effects.
+    ///
+    /// The delta is itself a byte effect: the length of `self` followed by runs of
+    /// changed bytes, each prefixed by its offset and length (all `u32` little
+    /// endian).
+    pub fn delta(&self, prev: &Effect) -> Result<Effect> {
+        let (next, prev) = match (self, prev) {
+            (Effect::Bytes(next), Effect::Bytes(prev)) => (next, prev),
+            _ => return Err(Error::App("Delta requires two bytes effects")),
+        };
+
+        let mut delta = vec![];
+        delta.extend_from_slice(&(next.len() as u32).to_le_bytes());
+
+        let mut i = 0;
+        while i < next.len() {
+            if prev.get(i) == Some(&next[i]) {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < next.len() && prev.get(i) != Some(&next[i]) {
+                i += 1;
+            }
+
+            delta.extend_from_slice(&(start as u32).to_le_bytes());
+            delta.extend_from_slice(&((i - start) as u32).to_le_bytes());
+            delta.extend_from_slice(&next[start..i]);
+        }
+
+        Ok(Effect::from(delta))
+    }
+
+    /// Reconstructs the next frame from `self` as the previous frame and a delta
+    /// produced by [`delta`](Effect::delta).
+    pub fn apply_delta(&self, delta: &Effect) -> Result<Effect> {
+        let (prev, delta) = match (self, delta) {
+            (Effect::Bytes(prev), Effect::Bytes(delta)) => (prev, delta),
+            _ => return Err(Error::App("Delta requires two bytes effects")),
+        };
+
+        let mut reader = &delta[..];
+        let len = read_le!(reader, u32) as usize;
+
+        let mut next = prev[..len.min(prev.len())].to_vec();
+        next.resize(len, 0);
+
+        while !reader.is_empty() {
+            let offset = read_le!(reader, u32) as usize;
+            let run = read_le!(reader, u32) as usize;
+
+            if run > reader.len() || offset + run > len {
+                return Err(Error::App("Malformed delta"));
+            }
+
+            next[offset..offset + run].copy_from_slice(&reader[..run]);
+            reader = &reader[run..];
+        }
+
+        Ok(Effect::from(next))
+    }
 }
 
 macro_rules! impl_from_primitive {
@@ -52,12 +478,21 @@ macro_rules! from_unsized {
     };
 }
 
-from_unsized!(String, String);
+from_unsized!(String, Utf8);
 from_unsized!(Vec<u8>, Bytes);
+from_unsized!(BTreeMap<String, String>, Map);
+
+impl<K: Into<String>, V: Into<String>> std::iter::FromIterator<(K, V)> for Effect {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Self {
+        Effect::Map(Arc::new(
+            pairs.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+        ))
+    }
+}
 
 impl From<&str> for Effect {
     fn from(s: &str) -> Self {
-        Effect::String(Arc::new(String::from(s)))
+        Effect::Utf8(Arc::new(String::from(s)))
     }
 }
 
@@ -65,19 +500,258 @@ impl From<&str> for Effect {
 mod tests {
     use super::*;
 
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn to_json_value() {
+        use serde_json::json;
+
+        assert_eq!(json!("hello"), Effect::ascii("hello").unwrap().to_json_value());
+        assert_eq!(json!([1, 2]), Effect::from(vec![1_u8, 2]).to_json_value());
+        assert_eq!(json!(null), Effect::Empty.to_json_value());
+
+        let batch = Effect::Batch(Arc::new(vec![Effect::from(3_u8), Effect::Bool(true)]));
+        assert_eq!(json!([3, true]), batch.to_json_value());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn to_jsonl() {
+        use serde_json::{json, Value};
+
+        let meta = Meta { ts: 1_600_000_000_000 };
+        let line = Effect::ascii("hello \"world\"\n").unwrap().to_jsonl(&meta);
+
+        // A single line, whatever the payload contains
+        assert!(line.ends_with('\n'));
+        assert_eq!(1, line.lines().count());
+
+        let object: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json!("Ascii"), object["kind"]);
+        assert_eq!(json!("hello \"world\"\n"), object["payload"]);
+        assert_eq!(json!(1_600_000_000_000_u64), object["ts"]);
+    }
+
     #[test]
     fn from_u8() {
-        Effect::from(3_u8);
+        let _ = Effect::from(3_u8);
     }
 
     #[test]
     fn from_u16() {
-        Effect::from(3_u16);
+        let _ = Effect::from(3_u16);
     }
 
     #[test]
     fn from_string() {
-        Effect::from(String::from("hello"));
+        let _ = Effect::from(String::from("hello"));
+    }
+
+    #[test]
+    fn read_value_from_map() {
+        let eff =
+            vec![("level", "debug"), ("target", "net")].into_iter().collect::<Effect>();
+
+        assert_eq!(Some("debug"), eff.get("level"));
+        assert_eq!(Some("net"), eff.get("target"));
+        assert_eq!(None, eff.get("color"));
+        assert_eq!(19, eff.size());
+        assert_eq!(None, Effect::from("level").get("level"));
+
+        let mut map = BTreeMap::new();
+        map.insert(String::from("level"), String::from("debug"));
+        map.insert(String::from("target"), String::from("net"));
+        assert_eq!(eff, Effect::from(map));
+        assert_eq!(
+            "Map({\"level\": \"debug\", \"target\": \"net\"})",
+            format!("{:?}", eff)
+        );
+    }
+
+    #[test]
+    fn round_trip_through_a_buffer() {
+        let bytes54 = Effect::from((0..54).collect::<Vec<u8>>());
+
+        let mut buf = vec![];
+        bytes54.to_writer(&mut buf).unwrap();
+        assert_eq!(54, buf.len());
+
+        let kind = bytes54.kind().unwrap();
+        assert_eq!(EffectKind::Bytes(54), kind);
+        assert_eq!(bytes54, Effect::from_reader(&buf[..], kind).unwrap());
+
+        // A reader ending too early is an error
+        assert!(Effect::from_reader(&buf[..53], kind).is_err());
+        assert!(Effect::from_reader(&buf[..4], EffectKind::U32).is_ok());
+        assert!(Effect::from_reader(&buf[..7], EffectKind::U64).is_err());
+
+        for effect in
+            [Effect::from(-3_i16), Effect::from('ß'), Effect::from("café")].iter()
+        {
+            let mut buf = vec![];
+            effect.to_writer(&mut buf).unwrap();
+            let kind = effect.kind().unwrap();
+            assert_eq!(*effect, Effect::from_reader(&buf[..], kind).unwrap());
+        }
+
+        assert!(Effect::Barrier(1).to_writer(vec![]).is_err());
+        assert!(Effect::from_reader(&[2][..], EffectKind::Bool).is_err());
+    }
+
+    #[test]
+    fn payload_size() {
+        assert_eq!(0, Effect::Empty.size());
+        assert_eq!(4, Effect::from(3_u32).size());
+        assert_eq!(5, Effect::from("hello").size());
+        assert_eq!(3, Effect::from(vec![1, 2, 3]).size());
+    }
+
+    #[test]
+    fn chunk_chars_on_char_boundaries() {
+        let chunks = Effect::from("a naïve café").chunk_chars(3);
+
+        let strings = chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Effect::Utf8(s) => {
+                    assert!(std::str::from_utf8(s.as_bytes()).is_ok());
+                    s.as_str()
+                }
+                _ => panic!("expected a text chunk"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["a n", "aïv", "e c", "afé"], strings);
+        assert_eq!(vec![Effect::from(3_u8)], Effect::from(3_u8).chunk_chars(3));
+    }
+
+    #[test]
+    fn text_effects() {
+        let ascii = Effect::ascii("hello").unwrap();
+        assert_eq!(Effect::Ascii(Arc::new(String::from("hello"))), ascii);
+        assert_eq!(Some("hello"), ascii.as_str());
+
+        // Non-ASCII input takes the UTF-8 path
+        assert!(Effect::ascii("grüße").is_err());
+        let utf8 = Effect::text("grüße");
+        assert_eq!(Effect::from("grüße"), utf8);
+        assert_eq!(Some("grüße"), utf8.as_str());
+        assert_eq!(None, Effect::from(3_u8).as_str());
+
+        assert_eq!(
+            vec![Effect::ascii("hel").unwrap(), Effect::ascii("lo").unwrap()],
+            ascii.chunk_chars(3)
+        );
+
+        let mut buf = vec![];
+        utf8.to_writer(&mut buf).unwrap();
+        assert!(Effect::from_reader(&buf[..], EffectKind::Ascii(buf.len())).is_err());
+        assert_eq!(
+            utf8,
+            Effect::from_reader(&buf[..], EffectKind::Utf8(buf.len())).unwrap()
+        );
+    }
+
+    #[test]
+    fn delta_between_frames() {
+        let prev = Effect::from(vec![0_u8; 64]);
+        let mut frame = vec![0_u8; 64];
+        frame[3] = 1;
+        frame[4] = 1;
+        frame[40] = 7;
+        let next = Effect::from(frame);
+
+        let delta = next.delta(&prev).unwrap();
+        assert!(delta.size() < next.size());
+        assert_eq!(next, prev.apply_delta(&delta).unwrap());
+
+        // Frames of different lengths
+        let shorter = Effect::from(vec![0_u8, 2]);
+        assert_eq!(shorter, next.apply_delta(&shorter.delta(&next).unwrap()).unwrap());
+        assert_eq!(next, shorter.apply_delta(&next.delta(&shorter).unwrap()).unwrap());
+
+        assert!(next.delta(&Effect::from(1_u8)).is_err());
+        assert!(prev.apply_delta(&Effect::from(vec![1_u8])).is_err());
+    }
+
+    #[test]
+    fn unbatch_nested_batches() {
+        let inner = Effect::batch(vec![Effect::from(2_u8), Effect::from(3_u8)]);
+        let nested = Effect::batch(vec![Effect::from(4_u8)]);
+        let batch = Effect::batch(vec![
+            Effect::from(1_u8),
+            inner,
+            Effect::batch(vec![Effect::from(5_u8), nested.clone()]),
+        ]);
+
+        assert_eq!(5, batch.size());
+        assert_eq!(None, batch.kind());
+        assert_eq!(vec![batch.clone()], batch.unbatch(0));
+        assert_eq!(3, batch.unbatch(1).len());
+        assert_eq!(
+            vec![
+                Effect::from(1_u8),
+                Effect::from(2_u8),
+                Effect::from(3_u8),
+                Effect::from(5_u8),
+                nested
+            ],
+            batch.unbatch(2)
+        );
+        assert_eq!(vec![Effect::from(1_u8)], Effect::from(1_u8).unbatch(2));
+    }
+
+    #[test]
+    fn read_numbers_from_bytes() {
+        let eff = Effect::from(vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+
+        assert_eq!(Some(0x0201), eff.read_u16(0, Endian::Little));
+        assert_eq!(Some(0x0102), eff.read_u16(0, Endian::Big));
+        assert_eq!(Some(0x0605_0403), eff.read_u32(2, Endian::Little));
+        assert_eq!(Some(0x0304_0506), eff.read_u32(2, Endian::Big));
+        assert_eq!(None, eff.read_u32(3, Endian::Little));
+        assert_eq!(None, eff.read_u64(0, Endian::Big));
+        assert_eq!(None, eff.as_u16_le());
+
+        assert_eq!(Some(0x0201), Effect::from(vec![0x01, 0x02]).as_u16_le());
+        assert_eq!(None, Effect::from(513_u16).read_u16(0, Endian::Little));
+    }
+
+    #[test]
+    fn sort_mixed_effects() {
+        let mut effects = vec![
+            Effect::from("b"),
+            Effect::Barrier(1),
+            Effect::from(2_u8),
+            Effect::ascii("z").unwrap(),
+            Effect::Empty,
+            Effect::from(300_u16),
+            Effect::from("a"),
+            Effect::from(1_u8),
+            Effect::batch(vec![Effect::from(1_u8)]),
+            Effect::from(-1_i8),
+        ];
+        effects.sort();
+
+        assert_eq!(
+            vec![
+                Effect::Empty,
+                Effect::from(1_u8),
+                Effect::from(2_u8),
+                Effect::from(300_u16),
+                Effect::from(-1_i8),
+                Effect::ascii("z").unwrap(),
+                Effect::from("a"),
+                Effect::from("b"),
+                Effect::Barrier(1),
+                Effect::batch(vec![Effect::from(1_u8)]),
+            ],
+            effects
+        );
+
+        let mut sorted = effects.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(effects, sorted);
     }
 
     #[test]
@@ -1,30 +1,452 @@
 //! Entity
 
 use super::effect::Effect;
-use super::environment::AffectingEntity;
+use super::envelope::Envelope;
+use super::environment::{AffectingEntity, EnvInfo, Environment};
 
+use crate::clock::{Clock, SystemClock};
 use crate::common::trigger::Trigger;
 use crate::common::trigger::TriggerHandle;
 use crate::common::watcher::Watcher;
 use crate::constants::BROADCAST_BUFFER_SIZE;
 use crate::errors::Error;
+use crate::metrics::{PollCounters, PollStats};
+use crate::trace::{FlowTracer, TraceStep};
 
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use bus::Bus as Broadcaster;
 use bus::BusReader as BroadcastReceiver;
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
 use tokio::{io, prelude::*};
 use uuid::Uuid;
 
 /// Processes effects.
 pub trait Entity: Send {
-    ///
+    /// Processes an effect received from an environment and returns the resulting
+    /// effect.
     fn process_effect(&mut self, effect: Effect, environment: &str) -> Effect;
+
+    /// Processes an effect together with its metadata. By default the effect is
+    /// processed by `process_effect` and the result keeps the correlation id.
+    fn process_envelope(&mut self, envelope: Envelope, environment: &str) -> Envelope {
+        let correlation_id = envelope.correlation_id;
+        let effect = self.process_effect(envelope.effect, environment);
+
+        Envelope { correlation_id, ..Envelope::new(effect) }
+    }
+
+    /// Gets called once before the core processes anything, either when the hosting
+    /// entity is first polled or when the core was injected into a running entity.
+    /// If it fails, the hosting entity stops and keeps the error.
+    fn on_start(&mut self, _ctx: &StartContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Gets called when a barrier is received. All effects broadcast before the
+    /// barrier were processed by all entities that joined its environment.
+    fn on_barrier(&mut self, _id: u64) {}
+
+    /// Gets called once per tick interval of the hosting entity and returns the
+    /// effects to send to the affected environments.
+    fn on_tick(&mut self, _now: Instant) -> Vec<Effect> {
+        vec![]
+    }
+
+    /// Gets called when effects of a sequenced environment were missed.
+    fn on_gap(&mut self, _gap: &GapDetected) {}
+
+    /// Gets called once for every environment the hosting entity joined, after the
+    /// core was started or as soon as the join completed.
+    fn on_join(&mut self, _info: &EnvInfo) {}
+
+    /// Gets called when an environment the hosting entity affects was deleted. The
+    /// results of the core don't reach it anymore.
+    fn on_affect_lost(&mut self, _env: &str) {}
+
+    /// Returns the limits of a batch, if this core processes effects in batches
+    /// instead of one by one.
+    fn batch_limits(&self) -> Option<BatchLimits> {
+        None
+    }
+
+    /// Processes a batch of effects and returns the resulting effects. Only gets
+    /// called if `batch_limits` returns some limits. By default the effects are
+    /// returned unchanged.
+    fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+        effects
+    }
+
+    /// Returns the environments this core wants to join and affect, when its hosting
+    /// entity is created with `create_entity_auto`.
+    fn subscriptions(&self) -> CoreSubscriptions {
+        CoreSubscriptions::default()
+    }
+
+    /// Returns true, if this core folds all effects the hosting entity takes in
+    /// during one poll into a single effect with `reduce`, and only processes that.
+    fn is_reducing(&self) -> bool {
+        false
+    }
+
+    /// Folds the next effect into the effect reduced so far and returns the result.
+    /// Only gets called if `is_reducing` returns true. By default the next effect
+    /// replaces the one reduced so far.
+    fn reduce(&mut self, _acc: Effect, next: Effect) -> Effect {
+        next
+    }
+
+    /// Returns true, if the effect tells the hosting entity to end, e.g. a poison
+    /// pill. The effect isn't processed, the entity emits what it has pending, leaves
+    /// all environments and is removed from its supervisor.
+    fn should_terminate(&self, _effect: &Effect) -> bool {
+        false
+    }
+}
+
+/// The environments a core wants to join and affect.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CoreSubscriptions {
+    /// The names of the environments to join. A name ending in `*` stands for all
+    /// existing environments starting with what comes before it.
+    pub joins: Vec<String>,
+    /// The names of the environments to affect
+    pub affects: Vec<String>,
+}
+
+impl CoreSubscriptions {
+    /// Returns true, if a join pattern stands for the environment.
+    pub fn matches(pattern: &str, env_name: &str) -> bool {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => env_name.starts_with(prefix),
+            None => pattern == env_name,
+        }
+    }
+}
+
+/// When a batch of effects gets processed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BatchLimits {
+    /// The batch is processed as soon as it has this many effects
+    pub max_size: usize,
+    /// The batch is processed once this much time has passed since its first effect
+    pub window: Duration,
+}
+
+/// Lets a core process the effects it receives in batches.
+///
+/// # Example
+/// ```
+/// use reee::eee::entity::{BatchingCore, Entity};
+/// use reee::eee::Effect;
+/// use std::time::Duration;
+///
+/// struct Sum;
+///
+/// impl Entity for Sum {
+///     fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+///         effect
+///     }
+///
+///     fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+///         let sum = effects.iter().filter_map(|effect| match effect {
+///             Effect::U64(n) => Some(n),
+///             _ => None,
+///         });
+///         vec![Effect::from(sum.sum::<u64>())]
+///     }
+/// }
+///
+/// let core = BatchingCore::new(Box::new(Sum), 5, Duration::from_millis(10));
+/// ```
+pub struct BatchingCore {
+    core: Box<dyn Entity>,
+    limits: BatchLimits,
+}
+
+impl BatchingCore {
+    /// Wraps a core, so it processes batches of up to `max_size` effects, or all
+    /// effects received within `window` after the first effect of a batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is 0.
+    pub fn new(core: Box<dyn Entity>, max_size: usize, window: Duration) -> Self {
+        assert!(max_size != 0, "batch size must be non-zero");
+
+        Self { core, limits: BatchLimits { max_size, window } }
+    }
+}
+
+impl Entity for BatchingCore {
+    fn process_effect(&mut self, effect: Effect, environment: &str) -> Effect {
+        self.core.process_effect(effect, environment)
+    }
+
+    fn on_start(&mut self, ctx: &StartContext) -> Result<(), Error> {
+        self.core.on_start(ctx)
+    }
+
+    fn on_tick(&mut self, now: Instant) -> Vec<Effect> {
+        self.core.on_tick(now)
+    }
+
+    fn on_gap(&mut self, gap: &GapDetected) {
+        self.core.on_gap(gap)
+    }
+
+    fn on_barrier(&mut self, id: u64) {
+        self.core.on_barrier(id)
+    }
+
+    fn on_join(&mut self, info: &EnvInfo) {
+        self.core.on_join(info)
+    }
+
+    fn on_affect_lost(&mut self, env: &str) {
+        self.core.on_affect_lost(env)
+    }
+
+    fn subscriptions(&self) -> CoreSubscriptions {
+        self.core.subscriptions()
+    }
+
+    fn should_terminate(&self, effect: &Effect) -> bool {
+        self.core.should_terminate(effect)
+    }
+
+    fn batch_limits(&self) -> Option<BatchLimits> {
+        Some(self.limits)
+    }
+
+    fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+        self.core.process_batch(effects)
+    }
+}
+
+/// Lets a core fold the effects its hosting entity takes in during one poll into a
+/// single effect, and only process that one. Unlike a batch, which collects effects,
+/// the effects are folded one by one with `reduce` as they arrive.
+///
+/// # Example
+/// ```
+/// use reee::eee::entity::{Entity, ReduceCore};
+/// use reee::eee::Effect;
+///
+/// struct Sum;
+///
+/// impl Entity for Sum {
+///     fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+///         effect
+///     }
+///
+///     fn reduce(&mut self, acc: Effect, next: Effect) -> Effect {
+///         match (acc, next) {
+///             (Effect::U64(acc), Effect::U64(next)) => Effect::from(acc + next),
+///             (_, next) => next,
+///         }
+///     }
+/// }
+///
+/// let core = ReduceCore::new(Box::new(Sum));
+/// ```
+pub struct ReduceCore {
+    core: Box<dyn Entity>,
+}
+
+impl ReduceCore {
+    /// Wraps a core, so it reduces the effects taken in during one poll.
+    pub fn new(core: Box<dyn Entity>) -> Self {
+        Self { core }
+    }
+}
+
+impl Entity for ReduceCore {
+    fn process_effect(&mut self, effect: Effect, environment: &str) -> Effect {
+        self.core.process_effect(effect, environment)
+    }
+
+    fn on_start(&mut self, ctx: &StartContext) -> Result<(), Error> {
+        self.core.on_start(ctx)
+    }
+
+    fn on_tick(&mut self, now: Instant) -> Vec<Effect> {
+        self.core.on_tick(now)
+    }
+
+    fn on_gap(&mut self, gap: &GapDetected) {
+        self.core.on_gap(gap)
+    }
+
+    fn on_barrier(&mut self, id: u64) {
+        self.core.on_barrier(id)
+    }
+
+    fn on_join(&mut self, info: &EnvInfo) {
+        self.core.on_join(info)
+    }
+
+    fn on_affect_lost(&mut self, env: &str) {
+        self.core.on_affect_lost(env)
+    }
+
+    fn subscriptions(&self) -> CoreSubscriptions {
+        self.core.subscriptions()
+    }
+
+    fn should_terminate(&self, effect: &Effect) -> bool {
+        self.core.should_terminate(effect)
+    }
+
+    fn is_reducing(&self) -> bool {
+        true
+    }
+
+    fn reduce(&mut self, acc: Effect, next: Effect) -> Effect {
+        self.core.reduce(acc, next)
+    }
+}
+
+/// A range of sequence numbers of a sequenced environment an entity never received.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GapDetected {
+    /// The name of the sequenced environment
+    pub env: String,
+    /// The first missed sequence number
+    pub from: u64,
+    /// The last missed sequence number
+    pub to: u64,
+}
+
+/// What a core gets to know about its hosting entity when it is started.
+#[derive(Clone, Debug)]
+pub struct StartContext {
+    uuid: String,
+    joined_environments: Vec<String>,
+    affected_environments: Vec<String>,
+    env_infos: Vec<EnvInfo>,
+    cancellation_token: CancellationToken,
+}
+
+impl StartContext {
+    /// Creates the context of a core started without a hosting entity, e.g. to check
+    /// that it starts at all.
+    pub(crate) fn detached(
+        joined_environments: Vec<String>,
+        affected_environments: Vec<String>,
+    ) -> Self {
+        Self {
+            uuid: Uuid::new_v4().to_string(),
+            joined_environments,
+            affected_environments,
+            env_infos: vec![],
+            cancellation_token: CancellationToken::default(),
+        }
+    }
+
+    /// Returns the uuid of the hosting entity.
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// Returns the names of the environments the hosting entity has joined.
+    pub fn joined_environments(&self) -> &[String] {
+        &self.joined_environments
+    }
+
+    /// Returns the names of the environments the hosting entity affects.
+    pub fn affected_environments(&self) -> &[String] {
+        &self.affected_environments
+    }
+
+    /// Returns what the hosting entity knows about a joined environment.
+    pub fn env_info(&self, env_name: &str) -> Option<&EnvInfo> {
+        self.env_infos.iter().find(|info| info.name == env_name)
+    }
+
+    /// Returns a token that is cancelled when a long-running invocation of the core
+    /// should bail out early.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+}
+
+/// Tells a long-running core to bail out early. The hosting entity cancels it when
+/// the node shuts down, the entity is deleted or drained, or an invocation exceeds
+/// the processing timeout. A token cancelled for pausing or a timeout is reset once
+/// the entity goes on.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    /// Returns true, if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *unlock!(self.cancelled.0)
+    }
+
+    /// Blocks until the token is cancelled.
+    pub fn wait(&self) {
+        let (cancelled, cvar) = &*self.cancelled;
+        let mut cancelled = unlock!(cancelled);
+
+        while !*cancelled {
+            cancelled = cvar.wait(cancelled).expect("error taking the lock");
+        }
+    }
+
+    /// Blocks until the token is cancelled, or until the timeout has passed. Returns
+    /// true, if the token was cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (cancelled, cvar) = &*self.cancelled;
+        let cancelled = unlock!(cancelled);
+
+        let (cancelled, _) = cvar
+            .wait_timeout_while(cancelled, timeout, |cancelled| !*cancelled)
+            .expect("error taking the lock");
+        *cancelled
+    }
+
+    /// Cancels the token and wakes up everyone waiting for it.
+    pub(crate) fn cancel(&self) {
+        let (cancelled, cvar) = &*self.cancelled;
+        *unlock!(cancelled) = true;
+        cvar.notify_all();
+    }
+
+    /// Lets the token be cancelled again.
+    pub(crate) fn reset(&self) {
+        *unlock!(self.cancelled.0) = false;
+    }
+}
+
+/// Where an entity dispatches the effects it receives from a joined environment.
+pub enum RouteTarget {
+    /// The effects are processed by the core
+    Core,
+    /// The effects are passed to a callback and don't produce any output
+    Callback(Box<dyn FnMut(Effect) + Send>),
+    /// The effects are only counted
+    Ignore,
+}
+
+/// What an entity does when its core fails to start.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RestartPolicy {
+    /// The entity ends right away and is removed from its supervisor
+    #[default]
+    Never,
+    /// The core is started again on the next timer ticks, up to the given number of
+    /// times, before the entity ends
+    Retry(usize),
 }
 
 type Name = String;
+type Inbox = (Sender<Effect>, Receiver<Effect>);
 
 /// An entity in the EEE model.
 pub struct EntityHost {
@@ -34,9 +456,14 @@ pub struct EntityHost {
     joined_environments: Arc<Mutex<HashMap<Name, JoinedEnvironment>>>,
     /// The environments this entity affects.
     affected_environments: Arc<Mutex<HashMap<Name, AffectedEnvironment>>>,
+    /// Affected environments that were deleted, which the entity didn't forget yet
+    lost_environments: Arc<Mutex<Vec<LostEnvironment>>>,
     /// Sender half of the outgoing broadcast channel for affecting
     /// environments.
-    out_chan: Arc<Mutex<Broadcaster<Effect>>>,
+    out_chan: Arc<Mutex<Broadcaster<Envelope>>>,
+    /// Output effects that couldn't be broadcast yet, because the
+    /// broadcast buffer was full.
+    pending_outputs: Arc<Mutex<VecDeque<Envelope>>>,
     /// A notifier that signals the end of this entity to affected environments
     drop_notifier: Arc<Mutex<Trigger>>,
     /// A handle to signal supervisor shutdown
@@ -45,15 +472,221 @@ pub struct EntityHost {
     waker: Watcher,
     /// The number of received effects.
     num_received_effects: Arc<AtomicUsize>,
+    /// The number of effects emitted to the affected environments.
+    num_emitted_effects: Arc<AtomicUsize>,
+    /// The last sequence number received from each sequenced environment, kept
+    /// beyond leaving it
+    last_sequences: Arc<Mutex<HashMap<Name, u64>>>,
+    /// The number of detected gaps in the sequences of sequenced environments
+    num_gaps_detected: Arc<AtomicUsize>,
+    /// The number of effects discarded from the mailboxes of joined environments
+    num_discarded_effects: Arc<AtomicUsize>,
+    /// The number of received effects dropped for exceeding their deadline
+    num_expired_effects: Arc<AtomicUsize>,
+    /// The number of polls and the effects received by each of them
+    poll_counters: Arc<PollCounters>,
     /// The entity core
     entity: Arc<Mutex<Option<Box<dyn Entity>>>>,
+    /// The clock driving the ticks of the entity core
+    clock: Arc<dyn Clock>,
+    /// The tick interval and the instant of the next tick, if the core wants ticks
+    ticker: Arc<Mutex<Option<Ticker>>>,
+    /// Records the hops of correlated effects
+    tracer: Arc<FlowTracer>,
+    /// Where effects from each joined environment are dispatched, if not to the core
+    routes: Arc<Mutex<HashMap<Name, RouteTarget>>>,
+    /// Whether the entity is currently polled
+    processing: Arc<AtomicBool>,
+    /// The instant the entity was last polled or finished a poll
+    last_active: Arc<Mutex<Instant>>,
+    /// The number of effects to take from each joined environment before intake
+    /// pauses, if draining
+    intake_limits: Arc<Mutex<Option<HashMap<Name, u64>>>>,
+    /// Whether the injected core was started
+    core_started: Arc<AtomicBool>,
+    /// The error of a core that failed to start
+    start_error: Arc<Mutex<Option<Error>>>,
+    /// Whether a core that failed to start is started again
+    restart_policy: Arc<Mutex<RestartPolicy>>,
+    /// The number of times the injected core failed to start
+    num_failed_starts: Arc<AtomicUsize>,
+    /// Where errors are reported to the supervisor, if the entity has one
+    error_sink: Arc<Mutex<Option<Sender<Error>>>>,
+    /// The longest a single invocation of the core may take before it's reported
+    processing_timeout: Arc<Mutex<Option<Duration>>>,
+    /// The invocations of the core that took longer than the processing timeout
+    processing_timeouts: Arc<Mutex<Vec<Error>>>,
+    /// Effects collected for the next batch, if the core processes batches
+    batch: Arc<Mutex<Batch>>,
+    /// Received effects waiting to be pulled by `recv`, if they are buffered instead
+    /// of processed
+    inbox: Arc<Mutex<Option<Inbox>>>,
+    /// The last effect processed by the core and its output, if observed
+    observation: Arc<Mutex<Option<Observation>>>,
+    /// Outputs emitted before the entity affected any environment, if they are kept
+    output_buffer: Arc<Mutex<Option<OutputBuffer>>>,
+    /// Tells long-running invocations of the core to bail out early
+    cancellation_token: CancellationToken,
+    /// Whether the token was cancelled, because an invocation exceeded the
+    /// processing timeout
+    cancelled_for_timeout: Arc<AtomicBool>,
+    /// Whether the core decided to end this entity
+    terminating: Arc<AtomicBool>,
+    /// Whether this entity ended, because its core decided so or failed to start
+    terminated: Arc<AtomicBool>,
+    /// Whether this entity only emits effects on ticks without joining anything
+    source: bool,
+}
+
+#[derive(Default)]
+struct Batch {
+    /// The collected effects
+    effects: Vec<Effect>,
+    /// The instant the first effect was collected
+    started: Option<Instant>,
+    /// The lineage of the collected effects
+    lineage: Lineage,
+}
+
+impl Batch {
+    /// Adds an effect to the batch.
+    fn push(&mut self, envelope: Envelope, now: Instant) {
+        self.started.get_or_insert(now);
+        self.lineage.add(&envelope);
+        self.effects.push(envelope.effect);
+    }
+
+    /// Returns true, if the batch has to be processed.
+    fn is_due(&self, limits: &BatchLimits, now: Instant) -> bool {
+        match self.started {
+            Some(started) => {
+                self.effects.len() >= limits.max_size || now >= started + limits.window
+            }
+            None => false,
+        }
+    }
+
+    /// Empties the batch and returns its effects and their lineage.
+    fn take(&mut self) -> (Vec<Effect>, Lineage) {
+        self.started = None;
+        (std::mem::take(&mut self.effects), std::mem::take(&mut self.lineage))
+    }
+}
+
+/// What the outputs of a core inherit from several inputs, e.g. a batch, so they
+/// can't escape the hop limit of a feedback loop.
+#[derive(Clone, Copy, Debug, Default)]
+struct Lineage {
+    /// The most hops any of the inputs took
+    hops: u32,
+    /// The correlation id of the first correlated input
+    correlation_id: Option<u64>,
+}
+
+impl Lineage {
+    /// Adds an input.
+    fn add(&mut self, envelope: &Envelope) {
+        self.hops = self.hops.max(envelope.hops);
+        self.correlation_id = self.correlation_id.or(envelope.correlation_id);
+    }
+
+    /// Adds all inputs of another lineage.
+    fn merge(&mut self, other: Lineage) {
+        self.hops = self.hops.max(other.hops);
+        self.correlation_id = self.correlation_id.or(other.correlation_id);
+    }
+
+    /// Wraps an output, so it carries on from the inputs.
+    fn wrap(&self, effect: Effect) -> Envelope {
+        Envelope {
+            correlation_id: self.correlation_id,
+            hops: self.hops,
+            ..Envelope::new(effect)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Observation {
+    /// The last effect processed by the core and the environment it came from
+    input: Option<(Name, Effect)>,
+    /// The effect the core returned for it
+    output: Option<Effect>,
+}
+
+struct OutputBuffer {
+    /// The most outputs kept
+    capacity: usize,
+    /// The kept outputs, oldest first
+    outputs: VecDeque<Envelope>,
+    /// The number of outputs dropped because the buffer was full
+    num_dropped: usize,
+}
+
+impl OutputBuffer {
+    /// Keeps an output, or drops it if the buffer is full.
+    fn push(&mut self, output: Envelope) {
+        match self.outputs.len() < self.capacity {
+            true => self.outputs.push_back(output),
+            false => self.num_dropped += 1,
+        }
+    }
+}
+
+struct Ticker {
+    /// Time between two ticks
+    interval: Duration,
+    /// The instant of the next tick
+    next: Instant,
+    /// The lineage of the effects taken in since the last tick
+    lineage: Lineage,
+}
+
+/// What an environment hands over to an entity joining it.
+pub(crate) struct EnvironmentChannels {
+    /// Environment effect receiver
+    pub env_rx: BroadcastReceiver<Envelope>,
+    /// Environment drop signal receiver
+    pub env_drop_rx: TriggerHandle,
+    /// A waker to wake the joined environment's task/future
+    pub env_waker: Watcher,
+    /// The number of effects acknowledged to the joined environment
+    pub acked: Arc<AtomicU64>,
+    /// The joined environment, to tell how many effects are still to come
+    pub environment: Environment,
+    /// The most recent effects kept in the history, taken in first
+    pub backfill: VecDeque<Envelope>,
 }
 
 struct JoinedEnvironment {
     /// Environment effect receiver
-    pub env_rx: BroadcastReceiver<Effect>,
+    pub env_rx: BroadcastReceiver<Envelope>,
+    /// Receiver of the effects the environment hands over directly
+    pub direct_rx: Receiver<Envelope>,
+    /// Effects backfilled on joining or put back into the mailbox by
+    /// `discard_pending`, they come before anything handed over since
+    pub kept: VecDeque<Envelope>,
     /// Environment drop signal receiver
     pub env_drop_rx: TriggerHandle,
+    /// A waker to wake the joined environment's task/future
+    pub env_waker: Watcher,
+    /// The number of effects acknowledged to the joined environment
+    pub acked: Arc<AtomicU64>,
+    /// The joined environment, to tell how many effects are still to come
+    pub environment: Environment,
+    /// What the entity knows about the joined environment
+    pub info: EnvInfo,
+    /// Whether the core was told about joining the environment
+    pub announced: bool,
+}
+
+/// Acknowledges a received effect to its environment once the effect was handled.
+struct Ack<'a>(&'a AtomicU64);
+
+impl Drop for Ack<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::AcqRel);
+    }
 }
 
 struct AffectedEnvironment {
@@ -61,6 +694,13 @@ struct AffectedEnvironment {
     pub env_waker: Watcher,
 }
 
+struct LostEnvironment {
+    /// The name of the deleted environment
+    pub name: Name,
+    /// Whether it's still registered as affected, i.e. it wasn't affected again since
+    pub stale: bool,
+}
+
 impl EntityHost {
     /// Creates a new entity.
     pub(crate) fn new(shutdown_listener: TriggerHandle) -> Self {
@@ -68,39 +708,283 @@ impl EntityHost {
             uuid: Uuid::new_v4().to_string(),
             joined_environments: shared_mut!(HashMap::new()),
             affected_environments: shared_mut!(HashMap::new()),
+            lost_environments: shared_mut!(vec![]),
             out_chan: shared_mut!(Broadcaster::new(BROADCAST_BUFFER_SIZE)),
+            pending_outputs: shared_mut!(VecDeque::new()),
             drop_notifier: shared_mut!(Trigger::new()),
             shutdown_listener: shared_mut!(shutdown_listener),
             waker: Watcher::new(),
             num_received_effects: shared!(AtomicUsize::new(0)),
+            num_emitted_effects: shared!(AtomicUsize::new(0)),
+            last_sequences: shared_mut!(HashMap::new()),
+            num_gaps_detected: shared!(AtomicUsize::new(0)),
+            num_discarded_effects: shared!(AtomicUsize::new(0)),
+            num_expired_effects: shared!(AtomicUsize::new(0)),
+            poll_counters: shared!(PollCounters::default()),
             entity: shared_mut!(None),
+            clock: shared!(SystemClock),
+            ticker: shared_mut!(None),
+            tracer: shared!(FlowTracer::default()),
+            routes: shared_mut!(HashMap::new()),
+            processing: shared!(AtomicBool::new(false)),
+            last_active: shared_mut!(Instant::now()),
+            intake_limits: shared_mut!(None),
+            core_started: shared!(AtomicBool::new(false)),
+            start_error: shared_mut!(None),
+            restart_policy: shared_mut!(RestartPolicy::default()),
+            num_failed_starts: shared!(AtomicUsize::new(0)),
+            error_sink: shared_mut!(None),
+            processing_timeout: shared_mut!(None),
+            processing_timeouts: shared_mut!(Vec::new()),
+            batch: shared_mut!(Batch::default()),
+            inbox: shared_mut!(None),
+            observation: shared_mut!(None),
+            output_buffer: shared_mut!(None),
+            cancellation_token: CancellationToken::default(),
+            cancelled_for_timeout: shared!(AtomicBool::new(false)),
+            terminating: shared!(AtomicBool::new(false)),
+            terminated: shared!(AtomicBool::new(false)),
+            source: false,
         }
     }
 
+    /// Turns this entity into a source entity.
+    pub(crate) fn into_source(mut self) -> Self {
+        self.source = true;
+        self
+    }
+
+    /// Lets this entity read the time from the given clock.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        *unlock!(self.last_active) = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Lets this entity record the hops of correlated effects.
+    pub(crate) fn with_flow_tracer(mut self, tracer: Arc<FlowTracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
     /// Injects an entity.
+    ///
+    /// The core is started on the next poll of this entity.
     pub fn inject_core(&mut self, entity: Box<dyn Entity>) {
         let mut core = unlock!(self.entity);
         core.replace(entity);
+
+        self.core_started.store(false, Ordering::Release);
+        self.num_failed_starts.store(0, Ordering::Release);
+        self.wake();
+    }
+
+    /// Sets where effects received from an environment are dispatched. By default
+    /// all effects are processed by the core.
+    pub fn route(&mut self, env_name: &str, target: RouteTarget) {
+        let mut routes = unlock!(self.routes);
+
+        match target {
+            RouteTarget::Core => routes.remove(env_name),
+            target => routes.insert(env_name.into(), target),
+        };
+    }
+
+    /// Takes the error of a core that failed to start, if any.
+    pub fn take_start_error(&self) -> Option<Error> {
+        unlock!(self.start_error).take()
+    }
+
+    /// Sets whether a core that fails to start is started again. By default the
+    /// entity ends right away.
+    pub fn set_restart_policy(&mut self, policy: RestartPolicy) {
+        *unlock!(self.restart_policy) = policy;
+    }
+
+    /// Returns the number of times the injected core failed to start.
+    pub fn num_failed_starts(&self) -> usize {
+        self.num_failed_starts.load(Ordering::Acquire)
+    }
+
+    /// Lets this entity report errors, e.g. a core failing to start, to its
+    /// supervisor.
+    pub(crate) fn report_errors_to(&self, sink: Sender<Error>) {
+        unlock!(self.error_sink).replace(sink);
+    }
+
+    /// Reports an error to the supervisor, if the entity has one.
+    fn report(&self, error: Error) {
+        if let Some(sink) = unlock!(self.error_sink).as_ref() {
+            let _ = sink.send(error);
+        }
+    }
+
+    /// Returns true, if a core that failed to start waits to be started again.
+    fn is_restarting(&self) -> bool {
+        !self.core_started.load(Ordering::Acquire)
+            && self.num_failed_starts.load(Ordering::Acquire) > 0
+            && !self.terminated.load(Ordering::Acquire)
+    }
+
+    /// Reports every invocation of the core that takes longer than the timeout.
+    ///
+    /// Cores are invoked on the async runtime, so an overlong invocation can't be
+    /// abandoned. It is reported by `is_timed_out` while it runs, and by
+    /// `take_processing_timeouts` once it returned.
+    pub fn set_processing_timeout(&mut self, timeout: Duration) {
+        unlock!(self.processing_timeout).replace(timeout);
+    }
+
+    /// Returns the processing timeout of this entity, if it has one.
+    pub fn processing_timeout(&self) -> Option<Duration> {
+        *unlock!(self.processing_timeout)
+    }
+
+    /// Returns true, if the core has been running for longer than the processing
+    /// timeout.
+    pub fn is_timed_out(&self) -> bool {
+        self.processing_timeout().is_some_and(|timeout| self.is_stalled(timeout))
+    }
+
+    /// Cancels the token of a core invocation exceeding the processing timeout right
+    /// now.
+    pub(crate) fn cancel_if_timed_out(&self) {
+        if self.is_timed_out() && !self.cancelled_for_timeout.swap(true, Ordering::AcqRel)
+        {
+            self.cancellation_token.cancel();
+        }
+    }
+
+    /// Tells long-running invocations of the core to bail out early, because the
+    /// entity is about to end.
+    pub(crate) fn cancel(&self) {
+        self.cancellation_token.cancel();
+    }
+
+    /// Takes the `ProcessingTimeout` errors of all invocations of the core that
+    /// took longer than the processing timeout so far.
+    pub fn take_processing_timeouts(&self) -> Vec<Error> {
+        unlock!(self.processing_timeouts).drain(..).collect()
     }
 
-    /// Registers an environment as joined by this entity.
+    /// Invokes the core and records a `ProcessingTimeout` error, if that took
+    /// longer than the processing timeout.
+    fn invoke_core<T, F: FnOnce() -> T>(&self, effect_id: Option<u64>, invoke: F) -> T {
+        let started = self.clock.now();
+
+        // The timeout is watched per invocation, not per poll
+        *unlock!(self.last_active) = started;
+        let result = invoke();
+
+        // Let the next invocation run, once the one that timed out returned
+        if self.cancelled_for_timeout.swap(false, Ordering::AcqRel) {
+            self.cancellation_token.reset();
+        }
+
+        if let Some(timeout) = self.processing_timeout() {
+            if self.clock.now().saturating_duration_since(started) > timeout {
+                println!("Ent. {} exceeded its processing timeout", &self.uuid[0..5]);
+
+                let uuid = self.uuid.clone();
+                unlock!(self.processing_timeouts)
+                    .push(Error::ProcessingTimeout { uuid, effect_id });
+            }
+        }
+
+        result
+    }
+
+    /// Lets the core process the current batch and returns the outputs, which carry
+    /// on from the batched effects.
+    fn run_batch(&self, core: &mut dyn Entity, batch: &mut Batch) -> Vec<Envelope> {
+        let (effects, lineage) = batch.take();
+        let outputs =
+            self.invoke_core(lineage.correlation_id, || core.process_batch(effects));
+
+        outputs.into_iter().map(|output| lineage.wrap(output)).collect()
+    }
+
+    /// Lets the entity core tick at the given interval starting from now.
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        let next = self.clock.now() + interval;
+        unlock!(self.ticker).replace(Ticker {
+            interval,
+            next,
+            lineage: Lineage::default(),
+        });
+    }
+
+    /// Returns the tick interval of this entity, if it ticks at all.
+    pub fn tick_interval(&self) -> Option<Duration> {
+        unlock!(self.ticker).as_ref().map(|ticker| ticker.interval)
+    }
+
+    /// Returns true, if this entity needs to be woken up regularly to tick or to
+    /// process a batch in time.
+    pub(crate) fn wants_timer(&self) -> bool {
+        self.tick_interval().is_some()
+            || unlock!(self.batch).started.is_some()
+            || self.is_restarting()
+    }
+
+    /// Wakes up this entity's task/future.
+    pub(crate) fn wake(&self) {
+        self.waker.notify();
+    }
+
+    /// Registers an environment as joined by this entity. The backfill is taken in
+    /// before anything the environment hands over or broadcasts.
     pub(crate) fn join_environment(
         &mut self,
         env_name: &str,
-        env_rx: BroadcastReceiver<Effect>,
-        env_drop_rx: TriggerHandle,
-    ) -> Result<Watcher, Error> {
-        //
+        channels: EnvironmentChannels,
+    ) -> Result<(Watcher, Sender<Envelope>), Error> {
+        let EnvironmentChannels {
+            env_rx,
+            env_drop_rx,
+            env_waker,
+            acked,
+            environment,
+            backfill,
+        } = channels;
         let mut joined = unlock!(self.joined_environments);
 
         if joined.contains_key(env_name) {
             return Err(Error::App("This entity already joined that environment"));
         }
 
+        // The environment may hand effects over directly instead of broadcasting them
+        let (direct_tx, direct_rx) = bounded(BROADCAST_BUFFER_SIZE);
+        let info = environment.info();
+
         // Store the name and an environment listener
-        joined.insert(env_name.into(), JoinedEnvironment { env_rx, env_drop_rx });
+        joined.insert(
+            env_name.into(),
+            JoinedEnvironment {
+                env_rx,
+                direct_rx,
+                kept: backfill,
+                env_drop_rx,
+                env_waker,
+                acked,
+                environment,
+                info,
+                announced: false,
+            },
+        );
+
+        // Let the core know about the joined environment
+        self.waker.notify();
 
-        Ok(self.waker.clone())
+        Ok((self.waker.clone(), direct_tx))
+    }
+
+    /// Deregisters an environment joined by this entity.
+    pub(crate) fn leave_environment(&mut self, env_name: &str) -> Result<(), Error> {
+        match unlock!(self.joined_environments).remove(env_name) {
+            Some(_) => Ok(()),
+            None => Err(Error::App("This entity hasn't joined that environment")),
+        }
     }
 
     /// Registers an environment as affected by this entity.
@@ -109,24 +993,70 @@ impl EntityHost {
         env_name: &str,
         env_waker: Watcher,
     ) -> Result<AffectingEntity, Error> {
-        //
         let mut affected = unlock!(self.affected_environments);
 
-        if affected.contains_key(env_name.into()) {
-            return Err(Error::App("This entity already affects that environment"));
+        // A deleted environment with that name may not be forgotten yet
+        let mut lost = unlock!(self.lost_environments);
+        match lost.iter().position(|lost| lost.stale && lost.name == env_name) {
+            // Replace its entry, the core is still told it lost the deleted one
+            Some(i) if affected.contains_key(env_name) => lost[i].stale = false,
+            // This entity didn't affect the deleted one, so there is nothing to tell
+            Some(i) => {
+                lost.remove(i);
+            }
+            None if affected.contains_key(env_name) => {
+                return Err(Error::App("This entity already affects that environment"));
+            }
+            None => (),
         }
+        drop(lost);
+
         // Store the name and the receiver handle of that environment
         affected.insert(env_name.into(), AffectedEnvironment { env_waker });
 
         let ent_uuid = self.uuid.clone();
         let ent_rx = unlock!(self.out_chan).add_rx();
         let ent_drop_rx = unlock!(self.drop_notifier).get_handle();
+        let ent_waker = self.waker.clone();
+
+        // Emit the outputs kept so far ahead of anything still held back, now that
+        // they have somewhere to go
+        let mut pending_outputs = unlock!(self.pending_outputs);
+        if let Some(buffer) = unlock!(self.output_buffer).as_mut() {
+            for output in buffer.outputs.drain(..).rev() {
+                pending_outputs.push_front(output);
+            }
+        }
+        drop(pending_outputs);
+        self.wake();
+
+        Ok(AffectingEntity { ent_uuid, ent_rx, ent_drop_rx, ent_waker })
+    }
 
-        Ok(AffectingEntity { ent_uuid, ent_rx, ent_drop_rx })
+    /// Lets this entity forget an affected environment that was deleted, and tell a
+    /// started core about it. Both happen during the next poll, so a busy core doesn't
+    /// hold up the caller.
+    pub(crate) fn lose_affected_environment(&self, env_name: &str) {
+        let mut lost = unlock!(self.lost_environments);
+        if !lost.iter().any(|lost| lost.name == env_name) {
+            lost.push(LostEnvironment { name: env_name.into(), stale: true });
+        }
+        drop(lost);
+
+        self.wake();
+    }
+
+    /// Returns true, if an affected environment was deleted, but this entity didn't
+    /// forget it yet.
+    fn is_lost(&self, env_name: &str) -> bool {
+        unlock!(self.lost_environments)
+            .iter()
+            .any(|lost| lost.stale && lost.name == env_name)
     }
 
     /// Notify affected environments, that this entity will be dropped.
     pub(crate) fn send_sig_term(&self) -> Result<(), Error> {
+        self.cancellation_token.cancel();
         unlock!(self.drop_notifier).pull()?;
 
         println!("Entity '{}' sent sig_term", self.uuid);
@@ -139,6 +1069,29 @@ impl EntityHost {
         &self.uuid
     }
 
+    /// Returns true, if this entity is a source entity, otherwise false.
+    pub fn is_source(&self) -> bool {
+        self.source
+    }
+
+    /// Returns the number of times this entity was woken up. Notifications arriving
+    /// before it started polling again are coalesced into one.
+    pub fn num_notifies(&self) -> u64 {
+        self.waker.num_notifies()
+    }
+
+    /// Returns how often this entity was woken up and polled, and how many effects
+    /// each poll received.
+    pub fn poll_stats(&self) -> PollStats {
+        self.poll_counters.stats(self.waker.num_notifies())
+    }
+
+    /// Returns true, if this entity ended, because its core decided so or failed to
+    /// start.
+    pub fn is_terminated(&self) -> bool {
+        self.terminated.load(Ordering::Acquire)
+    }
+
     /// Returns a list of all environments this entity has joined.
     pub fn joined_environments(&self) -> Vec<String> {
         unlock!(self.joined_environments)
@@ -149,10 +1102,17 @@ impl EntityHost {
 
     /// Returns a list of all environments this entity is affecting.
     pub fn affected_environments(&self) -> Vec<String> {
-        unlock!(self.affected_environments)
+        let affected = unlock!(self.affected_environments)
             .keys()
             .map(|key| key.to_string())
-            .collect::<Vec<String>>()
+            .collect::<Vec<String>>();
+
+        affected.into_iter().filter(|env| !self.is_lost(env)).collect()
+    }
+
+    /// Returns what this entity knows about a joined environment.
+    pub fn env_info(&self, env_name: &str) -> Option<EnvInfo> {
+        unlock!(self.joined_environments).get(env_name).map(|joined| joined.info.clone())
     }
 
     /// Returns true, if this entity has joined the specified environment,
@@ -161,10 +1121,11 @@ impl EntityHost {
         unlock!(self.joined_environments).contains_key(env_name)
     }
 
-    /// Returns true, if this entity has joined the specified environment,
-    /// otherwise false.
+    /// Returns true, if this entity affects the specified environment, otherwise
+    /// false.
     pub fn is_affecting(&self, env_name: &str) -> bool {
         unlock!(self.affected_environments).contains_key(env_name)
+            && !self.is_lost(env_name)
     }
 
     /// Returns the number of joined environments.
@@ -174,13 +1135,288 @@ impl EntityHost {
 
     /// Returns the number of affected environments.
     pub fn num_affected(&self) -> usize {
-        unlock!(self.affected_environments).len()
+        self.affected_environments().len()
     }
 
     /// Returns the number of effects that this entity has received.
     pub fn num_received_effects(&self) -> usize {
         self.num_received_effects.load(Ordering::Relaxed)
     }
+
+    /// Returns the number of effects that this entity has emitted to the environments
+    /// it affects.
+    pub fn num_emitted_effects(&self) -> usize {
+        self.num_emitted_effects.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of received effects dropped instead of processed, because
+    /// their deadline was exceeded.
+    pub fn num_expired_effects(&self) -> usize {
+        self.num_expired_effects.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of gaps detected in the sequences of sequenced
+    /// environments.
+    pub fn gaps_detected(&self) -> usize {
+        self.num_gaps_detected.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of effects this entity still has to deal with, i.e. the
+    /// effects queued in or broadcast by its joined environments and not yet handled,
+    /// its held back outputs and the effects of its current batch.
+    pub fn pending(&self) -> usize {
+        let joined = unlock!(self.joined_environments)
+            .values()
+            .map(|joined| (joined.environment.clone(), Arc::clone(&joined.acked)))
+            .collect::<Vec<_>>();
+
+        let inputs = joined
+            .iter()
+            .map(|(environment, acked)| {
+                let unacked = environment
+                    .num_broadcast()
+                    .saturating_sub(acked.load(Ordering::Acquire));
+                environment.num_queued() + unacked as usize
+            })
+            .sum::<usize>();
+
+        let buffered =
+            unlock!(self.inbox).as_ref().map_or(0, |(_, receiver)| receiver.len());
+
+        inputs
+            + buffered
+            + unlock!(self.pending_outputs).len()
+            + unlock!(self.batch).effects.len()
+    }
+
+    /// Returns the number of effects a joined environment has handed to this entity,
+    /// that it didn't take yet.
+    pub fn pending_count(&self, env_name: &str) -> Result<usize, Error> {
+        match unlock!(self.joined_environments).get(env_name) {
+            Some(JoinedEnvironment { environment, acked, .. }) => Ok(environment
+                .num_broadcast()
+                .saturating_sub(acked.load(Ordering::Acquire))
+                as usize),
+            None => Err(Error::App("This entity hasn't joined that environment")),
+        }
+    }
+
+    /// Discards the effects waiting in the mailbox of a joined environment that match
+    /// the predicate, keeps the others in order and returns the number of discarded
+    /// effects. Barriers are always kept.
+    ///
+    /// Only environments handing their effects directly to this entity fill a
+    /// mailbox of its own, so it fails for environments that broadcast them.
+    pub fn discard_pending<F>(&self, env_name: &str, predicate: F) -> Result<usize, Error>
+    where
+        F: Fn(&Effect) -> bool,
+    {
+        let mut joined = unlock!(self.joined_environments);
+
+        let JoinedEnvironment { direct_rx, kept, acked, environment, env_waker, .. } =
+            match joined.get_mut(env_name) {
+                Some(joined) => joined,
+                None => {
+                    return Err(Error::App("This entity hasn't joined that environment"))
+                }
+            };
+
+        if !environment.config().is_loopback_direct() {
+            return Err(Error::App(
+                "Only effects handed over directly can be discarded, that environment \
+                 broadcasts them.",
+            ));
+        }
+
+        let mut mailbox = std::mem::take(kept);
+        mailbox.extend(direct_rx.try_iter());
+
+        let mut num = 0;
+        for envelope in mailbox {
+            let discard = match envelope.effect {
+                Effect::Barrier(_) => false,
+                ref effect => predicate(effect),
+            };
+
+            match discard {
+                true => {
+                    let _ack = Ack(acked.as_ref());
+                    num += 1;
+                }
+                false => kept.push_back(envelope),
+            }
+        }
+
+        // The environment may hand over more effects now
+        if num > 0 {
+            println!(
+                "Ent. {} discarded {} effects from environment {}",
+                &self.uuid[0..5],
+                num,
+                env_name
+            );
+            env_waker.notify();
+        }
+
+        self.num_discarded_effects.fetch_add(num, Ordering::Relaxed);
+
+        Ok(num)
+    }
+
+    /// Returns the number of effects discarded from the mailboxes of joined
+    /// environments.
+    pub fn num_discarded_effects(&self) -> usize {
+        self.num_discarded_effects.load(Ordering::Relaxed)
+    }
+
+    /// Lets this entity buffer the effects it receives, so they can be pulled one at
+    /// a time with `recv` instead of being processed by the core. Disabling it drops
+    /// all effects that weren't pulled yet.
+    pub fn buffer_effects(&mut self, enabled: bool) {
+        let mut inbox = unlock!(self.inbox);
+        match enabled {
+            true if inbox.is_none() => *inbox = Some(unbounded()),
+            true => (),
+            false => *inbox = None,
+        }
+    }
+
+    /// Blocks until a buffered effect can be pulled, or until the timeout has passed,
+    /// and returns None in that case. Fails if this entity doesn't buffer effects.
+    pub fn recv(&self, timeout: Duration) -> Result<Option<Effect>, Error> {
+        // Don't hold the lock while waiting, the entity needs it to buffer effects
+        let receiver = match unlock!(self.inbox).as_ref() {
+            Some((_, receiver)) => receiver.clone(),
+            None => {
+                return Err(Error::App("This entity doesn't buffer received effects."))
+            }
+        };
+
+        match receiver.recv_timeout(timeout) {
+            Ok(effect) => Ok(Some(effect)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                Ok(None)
+            }
+        }
+    }
+
+    /// Lets this entity keep up to `capacity` outputs emitted while it doesn't affect
+    /// any environment yet, instead of losing them. They're emitted in order once it
+    /// affects its first environment. Outputs beyond the capacity are dropped and
+    /// counted.
+    pub fn buffer_outputs(&mut self, capacity: usize) {
+        let mut buffer = unlock!(self.output_buffer);
+        match buffer.as_mut() {
+            Some(buffer) => buffer.capacity = capacity,
+            None => {
+                buffer.replace(OutputBuffer {
+                    capacity,
+                    outputs: VecDeque::new(),
+                    num_dropped: 0,
+                });
+            }
+        }
+    }
+
+    /// Returns the number of outputs kept until this entity affects an environment.
+    pub fn num_buffered_outputs(&self) -> usize {
+        unlock!(self.output_buffer).as_ref().map_or(0, |buffer| buffer.outputs.len())
+    }
+
+    /// Returns the number of outputs dropped because the output buffer was full.
+    pub fn num_dropped_outputs(&self) -> usize {
+        unlock!(self.output_buffer).as_ref().map_or(0, |buffer| buffer.num_dropped)
+    }
+
+    /// Sets whether the last effect processed by the core and its output are kept
+    /// for inspection. Disabled by default, because it clones both.
+    pub fn observe(&mut self, enabled: bool) {
+        let mut observation = unlock!(self.observation);
+        match enabled {
+            true if observation.is_none() => *observation = Some(Observation::default()),
+            true => (),
+            false => *observation = None,
+        }
+    }
+
+    /// Returns the last effect processed by the core together with the name of its
+    /// environment, if observed.
+    pub fn last_input(&self) -> Option<(String, Effect)> {
+        unlock!(self.observation).as_ref().and_then(|observed| observed.input.clone())
+    }
+
+    /// Returns the effect the core returned for the last processed effect, if
+    /// observed.
+    pub fn last_output(&self) -> Option<Effect> {
+        unlock!(self.observation).as_ref().and_then(|observed| observed.output.clone())
+    }
+
+    /// Returns the instant this entity was last polled or finished a poll.
+    pub fn last_active(&self) -> Instant {
+        *unlock!(self.last_active)
+    }
+
+    /// Returns true, if this entity has been stuck in a poll for longer than the
+    /// threshold, e.g. because its core doesn't return. An idle entity isn't stalled.
+    pub fn is_stalled(&self, threshold: Duration) -> bool {
+        self.processing.load(Ordering::Acquire)
+            && self.clock.now().saturating_duration_since(self.last_active()) > threshold
+    }
+
+    /// Lets this entity only take the effects each joined environment has broadcast
+    /// so far, and process a started batch once it took them.
+    pub(crate) fn pause_intake(&self) {
+        let limits = unlock!(self.joined_environments)
+            .iter()
+            .map(|(env, joined)| (env.clone(), joined.environment.num_broadcast()))
+            .collect();
+        unlock!(self.intake_limits).replace(limits);
+        self.cancellation_token.cancel();
+
+        self.waker.notify();
+    }
+
+    /// Lets this entity take effects again after pausing its intake.
+    pub(crate) fn resume_intake(&self) {
+        unlock!(self.intake_limits).take();
+        self.cancellation_token.reset();
+        self.waker.notify();
+    }
+
+    /// Returns true, if the intake of this entity is paused and it has processed and
+    /// emitted everything it took before.
+    pub(crate) fn is_drained(&self) -> bool {
+        // Lock in the same order as when polled
+        let took_all = {
+            let joined = unlock!(self.joined_environments);
+            match unlock!(self.intake_limits).as_ref() {
+                Some(limits) => took_all(&joined, limits),
+                None => return false,
+            }
+        };
+
+        took_all
+            && !self.processing.load(Ordering::Acquire)
+            && unlock!(self.pending_outputs).is_empty()
+            && unlock!(self.batch).effects.is_empty()
+    }
+
+    /// Blocks until this entity has nothing pending and isn't processing anything,
+    /// or until the timeout has passed.
+    pub fn wait_until_idle(&self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+
+        while self.processing.load(Ordering::Acquire) || self.pending() > 0 {
+            if Instant::now() >= deadline {
+                return Err(Error::App(
+                    "Timed out waiting for the entity to become idle.",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
 }
 
 impl Future for EntityHost {
@@ -188,7 +1424,9 @@ impl Future for EntityHost {
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<(), Self::Error> {
-        self.waker.task.register();
+        self.waker.register();
+        *unlock!(self.last_active) = self.clock.now();
+        self.processing.store(true, Ordering::Release);
 
         // this scope will modify 'joined_environments'
         {
@@ -196,65 +1434,463 @@ impl Future for EntityHost {
             let mut num = 0;
 
             let mut joined = unlock!(self.joined_environments);
-            let affected = unlock!(self.affected_environments);
+            let mut affected = unlock!(self.affected_environments);
             let mut core = unlock!(self.entity);
 
+            // Forget affected environments that were deleted, and let a started core
+            // know
+            let lost = std::mem::take(&mut *unlock!(self.lost_environments));
+            for LostEnvironment { name, stale } in lost {
+                // Skip environments this entity didn't affect
+                if stale && affected.remove(&name).is_none() {
+                    continue;
+                }
+
+                println!(
+                    "Ent. {} stopped affecting deleted environment '{}'",
+                    &self.uuid[0..5],
+                    name
+                );
+
+                if self.core_started.load(Ordering::Acquire) {
+                    if let Some(core) = core.as_mut() {
+                        core.on_affect_lost(&name);
+                    }
+                }
+            }
+
             let mut out_chan = unlock!(self.out_chan);
+            let mut pending_outputs = unlock!(self.pending_outputs);
+            let mut last_sequences = unlock!(self.last_sequences);
+            let mut routes = unlock!(self.routes);
+            let inbox = unlock!(self.inbox);
+            let intake_limits = unlock!(self.intake_limits);
+            let mut observation = unlock!(self.observation);
+            let mut output_buffer = unlock!(self.output_buffer);
             let mut to_drop = vec![];
 
-            'outer: loop {
+            // Outputs only count as emitted, if they reach an affected environment
+            let num_emitted = match affected.is_empty() {
+                true => None,
+                false => Some(self.num_emitted_effects.as_ref()),
+            };
+
+            // Keep outputs instead of broadcasting them into the void, until the
+            // entity affects an environment
+            let mut held = match affected.is_empty() {
+                true => output_buffer.as_mut(),
+                false => None,
+            };
+
+            // Start a newly injected core before it processes anything
+            if let Some(c) = core.as_mut() {
+                if !self.core_started.swap(true, Ordering::AcqRel) {
+                    let ctx = StartContext {
+                        uuid: self.uuid.clone(),
+                        joined_environments: joined.keys().cloned().collect(),
+                        affected_environments: affected.keys().cloned().collect(),
+                        env_infos: joined
+                            .values()
+                            .map(|joined| joined.info.clone())
+                            .collect(),
+                        cancellation_token: self.cancellation_token.clone(),
+                    };
+
+                    if let Err(e) = c.on_start(&ctx) {
+                        println!("Ent. {} failed to start: {:?}", &self.uuid[0..5], e);
+
+                        let num_failed =
+                            self.num_failed_starts.fetch_add(1, Ordering::AcqRel) + 1;
+                        self.report(Error::StartFailed {
+                            uuid: self.uuid.clone(),
+                            cause: format!("{:?}", e),
+                        });
+
+                        // Start the core again on one of the next timer ticks
+                        let policy = *unlock!(self.restart_policy);
+                        if matches!(policy, RestartPolicy::Retry(max) if num_failed <= max)
+                        {
+                            self.core_started.store(false, Ordering::Release);
+                            self.processing.store(false, Ordering::Release);
+
+                            return Ok(Async::NotReady);
+                        }
+
+                        // Stop listening to the joined environments and end this
+                        // future. The supervisor removes the entity.
+                        unlock!(self.start_error).replace(e);
+                        core.take();
+                        joined.clear();
+                        self.terminated.store(true, Ordering::Release);
+                        self.processing.store(false, Ordering::Release);
+
+                        return Ok(Async::Ready(()));
+                    }
+
+                    // Tell the new core about all joined environments
+                    for joined in joined.values_mut() {
+                        joined.announced = false;
+                    }
+                }
+
+                for joined in joined.values_mut().filter(|joined| !joined.announced) {
+                    c.on_join(&joined.info);
+                    joined.announced = true;
+                }
+            }
+
+            // Let the core emit its effects for every tick interval that has passed
+            if let Some(ticker) = unlock!(self.ticker).as_mut() {
+                let now = self.clock.now();
+
+                // Outputs of a tick carry on from what the core took in before, so
+                // a core delaying its inputs to the next tick can't escape the hop
+                // limit
+                let lineage = ticker.lineage;
+                while ticker.next <= now {
+                    if let Some(core) = core.as_mut() {
+                        let outputs = core.on_tick(ticker.next);
+                        pending_outputs.extend(
+                            outputs.into_iter().map(|output| lineage.wrap(output)),
+                        );
+                        ticker.lineage = Lineage::default();
+                    }
+                    ticker.next += ticker.interval;
+                }
+            }
+
+            // Process the current batch if its time window has passed
+            let batch_limits = core.as_ref().and_then(|core| core.batch_limits());
+            let reducing = core.as_ref().is_some_and(|core| core.is_reducing());
+            let mut batch = unlock!(self.batch);
+
+            if let Some(limits) = batch_limits.as_ref() {
+                if batch.is_due(limits, self.clock.now()) {
+                    if let Some(core) = core.as_mut() {
+                        pending_outputs.extend(self.run_batch(&mut **core, &mut batch));
+                    }
+                }
+            }
+
+            // Try to broadcast outputs that were held back during the last poll
+            broadcast_pending(
+                &mut out_chan,
+                &mut pending_outputs,
+                held.as_deref_mut(),
+                num_emitted,
+            );
+
+            // The effect all effects taken in so far were reduced to, the environment
+            // of the last one and their lineage, if the core reduces them
+            let mut reduced: Option<(Name, Effect, Lineage)> = None;
+
+            // The lineage of all effects taken in by the core during this poll
+            let mut taken = Lineage::default();
+
+            // Only receive new effects if there is room for their results, otherwise
+            // wait for the affected environments to catch up. Source entities have
+            // nothing to receive.
+            'outer: while !self.source
+                && !self.terminating.load(Ordering::Acquire)
+                && pending_outputs.is_empty()
+            {
                 // number of dry in-channels
                 let mut num_dry = 0;
 
                 // Check each joined environment if there is a new effect
-                for (env, JoinedEnvironment { env_rx, env_drop_rx: _ }) in
-                    joined.iter_mut()
+                for (
+                    env,
+                    JoinedEnvironment {
+                        env_rx, direct_rx, kept, acked, environment, ..
+                    },
+                ) in joined.iter_mut()
                 {
+                    let log_policy = environment.config().get_log_policy();
+
                     // Try to receive as many effects as possible from that
                     // environment TODO: maybe make this a
                     // for-loop with an upper limit to give other
-                    // futures time to progress as well
-                    'inner: loop {
-                        match env_rx.try_recv() {
-                            Ok(effect) => {
-                                num += 1;
+                    // futures time to progress as well. Effects handed over
+                    // directly come first, they were all sent before anything left
+                    // in the broadcast channel
+                    loop {
+                        // Pause taking effects once the limit of a draining entity
+                        // is reached
+                        if let Some(limit) =
+                            intake_limits.as_ref().and_then(|limits| limits.get(env))
+                        {
+                            if acked.load(Ordering::Acquire) >= *limit {
+                                break;
+                            }
+                        }
+
+                        let envelope = match kept
+                            .pop_front()
+                            .or_else(|| direct_rx.try_recv().ok())
+                            .or_else(|| env_rx.try_recv().ok())
+                        {
+                            Some(envelope) => envelope,
+                            None => break,
+                        };
+                        let _ack = Ack(acked.as_ref());
 
+                        // Skip effects addressed to another joined entity, but keep
+                        // up with the sequence
+                        if let Some(recipient) = envelope.recipient.as_ref() {
+                            if *recipient != self.uuid {
+                                if let Some(sequence) = envelope.sequence {
+                                    last_sequences.insert(env.clone(), sequence);
+                                }
+                                continue;
+                            }
+                        }
+
+                        // Drop stale effects instead of handing them to the core
+                        if let Some(deadline) = envelope.deadline {
+                            if self.clock.now() > deadline {
                                 println!(
-                                    "Ent. {} received effect '{:?}' from environment {} ({})",
+                                    "Ent. {} dropped effect from environment {}: \
+                                     deadline exceeded",
                                     &self.uuid[0..5],
-                                    effect,
-                                    env,
-                                    num_effects + num,
+                                    env
                                 );
+                                self.num_expired_effects.fetch_add(1, Ordering::Relaxed);
+                                if let Some(sequence) = envelope.sequence {
+                                    last_sequences.insert(env.clone(), sequence);
+                                }
+                                continue;
+                            }
+                        }
+
+                        num += 1;
+
+                        if let Some(effect) = log_policy.render(&envelope.effect) {
+                            println!(
+                                "Ent. {} received effect '{}' from environment {} ({})",
+                                &self.uuid[0..5],
+                                effect,
+                                env,
+                                num_effects + num,
+                            );
+                        }
+
+                        #[cfg(feature = "tracing")]
+                        let _span = tracing::trace_span!(
+                            "entity.process",
+                            env = %env,
+                            entity = %self.uuid,
+                            seq = num_effects + num,
+                            size = envelope.effect.size(),
+                        )
+                        .entered();
+
+                        // Check if effects of a sequenced environment were missed
+                        if let Some(sequence) = envelope.sequence {
+                            match last_sequences.insert(env.clone(), sequence) {
+                                Some(last) if sequence > last + 1 => {
+                                    let gap = GapDetected {
+                                        env: env.clone(),
+                                        from: last + 1,
+                                        to: sequence - 1,
+                                    };
+
+                                    println!(
+                                        "Ent. {} missed effects {}..{} of environment {}",
+                                        &self.uuid[0..5],
+                                        gap.from,
+                                        gap.to,
+                                        env
+                                    );
 
-                                // Process the effect data
-                                let effect = match core
-                                    .as_mut()
-                                    .map(|core| core.process_effect(effect, &env))
-                                {
-                                    Some(effect) => effect,
-                                    None => Effect::Empty,
-                                };
-
-                                // Broadcast result to affected environments
-                                out_chan.broadcast(effect);
-
-                                // Wake all affected environments if half of the
-                                // broadcaster buffer size is full
-                                if num == BROADCAST_BUFFER_SIZE / 2 {
-                                    for (_, AffectedEnvironment { env_waker }) in
-                                        affected.iter()
-                                    {
-                                        env_waker.task.notify();
+                                    #[cfg(feature = "tracing")]
+                                    tracing::debug!(
+                                        from = gap.from,
+                                        to = gap.to,
+                                        "gap detected"
+                                    );
+
+                                    self.num_gaps_detected
+                                        .fetch_add(1, Ordering::Relaxed);
+                                    if let Some(core) = core.as_mut() {
+                                        core.on_gap(&gap);
                                     }
                                 }
+                                _ => (),
+                            }
+                        }
+
+                        // Treat a barrier as a checkpoint: process the current batch,
+                        // let the core know and pass the barrier on
+                        if let Effect::Barrier(id) = envelope.effect {
+                            if let Some(core) = core.as_mut() {
+                                if batch.started.is_some() {
+                                    pending_outputs
+                                        .extend(self.run_batch(&mut **core, &mut batch));
+                                }
+                                core.on_barrier(id);
+                            }
+
+                            pending_outputs.push_back(envelope);
+                            broadcast_pending(
+                                &mut out_chan,
+                                &mut pending_outputs,
+                                held.as_deref_mut(),
+                                num_emitted,
+                            );
+                            if !pending_outputs.is_empty() {
+                                break 'outer;
+                            }
+                            continue;
+                        }
+
+                        // Stop taking effects, if the core decides to end the entity
+                        if core
+                            .as_ref()
+                            .is_some_and(|core| core.should_terminate(&envelope.effect))
+                        {
+                            println!(
+                                "Ent. {} terminates on effect from environment {}",
+                                &self.uuid[0..5],
+                                env
+                            );
+                            self.terminating.store(true, Ordering::Release);
+                            break 'outer;
+                        }
+
+                        // Dispatch the effect to a callback or drop it, if the core
+                        // isn't supposed to see effects from this environment
+                        match routes.get_mut(env) {
+                            Some(RouteTarget::Callback(callback)) => {
+                                callback(envelope.effect);
+                                continue;
+                            }
+                            Some(RouteTarget::Ignore) => continue,
+                            _ => (),
+                        }
+
+                        // Leave the effect to be pulled, if effects are buffered
+                        if let Some((sender, _)) = inbox.as_ref() {
+                            let _ = sender.send(envelope.effect);
+                            continue;
+                        }
+
+                        taken.add(&envelope);
+
+                        // Fold the effect into those taken in during this poll, if the
+                        // core reduces them
+                        if let (true, Some(core)) = (reducing, core.as_mut()) {
+                            let (acc, mut lineage) = match reduced.take() {
+                                Some((_, acc, lineage)) => (Some(acc), lineage),
+                                None => (None, Lineage::default()),
+                            };
+                            lineage.add(&envelope);
+
+                            let acc = match acc {
+                                Some(acc) => self.invoke_core(None, || {
+                                    core.reduce(acc, envelope.effect)
+                                }),
+                                None => envelope.effect,
+                            };
+                            reduced.replace((env.clone(), acc, lineage));
+                            continue;
+                        }
+
+                        // Collect the effect into the current batch, if the core
+                        // processes batches, and process the batch once it is full
+                        if let Some(limits) = batch_limits.as_ref() {
+                            batch.push(envelope, self.clock.now());
+
+                            if batch.is_due(limits, self.clock.now()) {
+                                if let Some(core) = core.as_mut() {
+                                    pending_outputs
+                                        .extend(self.run_batch(&mut **core, &mut batch));
+                                }
+
+                                broadcast_pending(
+                                    &mut out_chan,
+                                    &mut pending_outputs,
+                                    held.as_deref_mut(),
+                                    num_emitted,
+                                );
+                                if !pending_outputs.is_empty() {
+                                    break 'outer;
+                                }
                             }
-                            _ => {
-                                num_dry += 1;
-                                break 'inner;
+                            continue;
+                        }
+
+                        let correlation_id = envelope.correlation_id;
+                        let hops = envelope.hops;
+
+                        if let Some(observed) = observation.as_mut() {
+                            observed.input = Some((env.clone(), envelope.effect.clone()));
+                        }
+
+                        // Process the effect data
+                        let mut output = match core.as_mut().map(|core| {
+                            self.invoke_core(correlation_id, || {
+                                core.process_envelope(envelope, env)
+                            })
+                        }) {
+                            Some(output) => output,
+                            None => Envelope {
+                                correlation_id,
+                                ..Envelope::new(Effect::Empty)
+                            },
+                        };
+
+                        output.hops = hops;
+
+                        if let Some(observed) = observation.as_mut() {
+                            observed.output = Some(output.effect.clone());
+                        }
+
+                        self.tracer.record(correlation_id, || TraceStep::Processed {
+                            entity: self.uuid.clone(),
+                            environment: env.clone(),
+                        });
+                        self.tracer.record(correlation_id, || TraceStep::Emitted {
+                            entity: self.uuid.clone(),
+                        });
+
+                        // Keep the result, if there is no affected environment yet
+                        if let Some(buffer) = held.as_deref_mut() {
+                            buffer.push(output);
+                            continue;
+                        }
+
+                        // Drop the result, if it is targeted at none of the affected
+                        // environments
+                        if !is_taken(&output, &affected) {
+                            continue;
+                        }
+
+                        // Broadcast result to affected environments, or hold it back
+                        // if the broadcast buffer is full
+                        if let Err(output) = try_emit(&mut out_chan, output) {
+                            #[cfg(feature = "tracing")]
+                            tracing::debug!(
+                                size = output.effect.size(),
+                                "output held back"
+                            );
+
+                            pending_outputs.push_back(*output);
+                            break 'outer;
+                        }
+                        if let Some(num_emitted) = num_emitted {
+                            num_emitted.fetch_add(1, Ordering::Relaxed);
+                        }
+
+                        // Wake all affected environments if half of the
+                        // broadcaster buffer size is full
+                        if num == BROADCAST_BUFFER_SIZE / 2 {
+                            for (_, AffectedEnvironment { env_waker }) in affected.iter()
+                            {
+                                env_waker.notify();
                             }
                         }
                     }
+                    num_dry += 1;
                 }
 
                 // If all channels are dry this future can finally go to sleep
@@ -264,30 +1900,84 @@ impl Future for EntityHost {
                 }
             }
 
+            // Process the effect all effects taken in during this poll were reduced to
+            if let (Some((env, acc, lineage)), Some(core)) =
+                (reduced.take(), core.as_mut())
+            {
+                let mut output = self.invoke_core(lineage.correlation_id, || {
+                    core.process_envelope(lineage.wrap(acc), &env)
+                });
+                output.hops = lineage.hops;
+                if held.is_some() || is_taken(&output, &affected) {
+                    pending_outputs.push_back(output);
+                }
+                broadcast_pending(
+                    &mut out_chan,
+                    &mut pending_outputs,
+                    held.as_deref_mut(),
+                    num_emitted,
+                );
+            }
+
+            // Don't leave a batch behind, once a draining entity took everything
+            if let Some(limits) = intake_limits.as_ref() {
+                if took_all(&joined, limits) && batch.started.is_some() {
+                    if let Some(core) = core.as_mut() {
+                        pending_outputs.extend(self.run_batch(&mut **core, &mut batch));
+                    }
+                    broadcast_pending(
+                        &mut out_chan,
+                        &mut pending_outputs,
+                        held.as_deref_mut(),
+                        num_emitted,
+                    );
+                }
+            }
+
+            // Don't lose a started batch, once the core decided to end the entity
+            if self.terminating.load(Ordering::Acquire) && batch.started.is_some() {
+                if let Some(core) = core.as_mut() {
+                    pending_outputs.extend(self.run_batch(&mut **core, &mut batch));
+                }
+                broadcast_pending(&mut out_chan, &mut pending_outputs, held, num_emitted);
+            }
+
+            // Let the outputs of the next tick carry on from the effects taken in
+            if let Some(ticker) = unlock!(self.ticker).as_mut() {
+                ticker.lineage.merge(taken);
+            }
+
             self.num_received_effects.store(num_effects + num, Ordering::Release);
+            self.poll_counters.record(num as u64);
 
             // Wake all affected environments to process the remaining effects buffered in
             // the broadcast channel
             for (_, AffectedEnvironment { env_waker }) in affected.iter() {
-                env_waker.task.notify();
+                env_waker.notify();
+            }
+
+            // Wake all joined environments that might wait for room in their broadcast
+            // channel
+            if num > 0 {
+                for (_, JoinedEnvironment { env_waker, .. }) in joined.iter() {
+                    env_waker.notify();
+                }
             }
 
             // Check if any environment sent a sig-term
-            for (env, JoinedEnvironment { env_rx: _, env_drop_rx }) in joined.iter_mut() {
-                match env_drop_rx.0.poll() {
-                    Ok(Async::Ready(Some(is_term))) => {
-                        if is_term {
-                            println!(
-                                "Ent. {} received sig-term from environment '{}'",
-                                &self.uuid[0..5],
-                                env
-                            );
+            for (env, JoinedEnvironment { env_drop_rx, .. }) in joined.iter_mut() {
+                if let Ok(Async::Ready(Some(true))) = env_drop_rx.0.poll() {
+                    println!(
+                        "Ent. {} received sig-term from environment '{}'",
+                        &self.uuid[0..5],
+                        env
+                    );
 
-                            // Remember to unsubscribe from that environment
-                            to_drop.push(env.clone());
-                        }
-                    }
-                    _ => (),
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(env = %env, entity = %self.uuid, "environment dropped");
+
+                    // Remember to unsubscribe from that environment
+                    to_drop.push(env.clone());
                 }
             }
 
@@ -302,18 +1992,27 @@ impl Future for EntityHost {
             }
         } // we're finished with mutating 'joined_environments'
 
+        *unlock!(self.last_active) = self.clock.now();
+        self.processing.store(false, Ordering::Release);
+
+        // End this future once a terminated entity emitted everything it has pending
+        if self.terminating.load(Ordering::Acquire)
+            && unlock!(self.pending_outputs).is_empty()
+        {
+            // The supervisor tells the affected environments, once it removes the
+            // entity, so they read the last outputs first
+            unlock!(self.joined_environments).clear();
+            self.terminated.store(true, Ordering::Release);
+
+            return Ok(Async::Ready(()));
+        }
+
         // Check if the supervisor is about to shutdown
-        match unlock!(self.shutdown_listener).0.poll() {
-            // sig-term received
-            // NOTE: the 'watch' channel always yields Some!!
-            Ok(Async::Ready(Some(is_term))) => {
-                if is_term {
-                    println!("Ent. {} received sig-term", &self.uuid[0..5]);
-                    // End this future
-                    return Ok(Async::Ready(()));
-                }
-            }
-            _ => (),
+        // NOTE: the 'watch' channel always yields Some!!
+        if let Ok(Async::Ready(Some(true))) = unlock!(self.shutdown_listener).0.poll() {
+            println!("Ent. {} received sig-term", &self.uuid[0..5]);
+            // End this future
+            return Ok(Async::Ready(()));
         }
 
         // Entity goes to sleep
@@ -321,18 +2020,106 @@ impl Future for EntityHost {
     }
 }
 
+/// Returns true, if an entity took as many effects from each joined environment as
+/// its intake limits allow.
+fn took_all(
+    joined: &HashMap<Name, JoinedEnvironment>,
+    limits: &HashMap<Name, u64>,
+) -> bool {
+    joined.iter().all(|(env, JoinedEnvironment { acked, .. })| {
+        limits.get(env).is_none_or(|limit| acked.load(Ordering::Acquire) >= *limit)
+    })
+}
+
+/// Broadcasts held back outputs in order until the broadcast buffer is full, and
+/// counts them as emitted, if there is a counter. Moves them to the output buffer
+/// instead, if one is given.
+fn broadcast_pending(
+    out_chan: &mut Broadcaster<Envelope>,
+    pending_outputs: &mut VecDeque<Envelope>,
+    output_buffer: Option<&mut OutputBuffer>,
+    num_emitted: Option<&AtomicUsize>,
+) {
+    if let Some(buffer) = output_buffer {
+        pending_outputs.drain(..).for_each(|output| buffer.push(output));
+        return;
+    }
+
+    while let Some(output) = pending_outputs.pop_front() {
+        if let Err(output) = try_emit(out_chan, output) {
+            pending_outputs.push_front(*output);
+            break;
+        }
+        if let Some(num_emitted) = num_emitted {
+            num_emitted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns true, if the output is targeted at all affected environments or at one of
+/// them.
+fn is_taken(output: &Envelope, affected: &HashMap<Name, AffectedEnvironment>) -> bool {
+    output.target.as_ref().is_none_or(|target| affected.contains_key(target))
+}
+
+/// Broadcasts an output to the affected environments as one more hop on its way, or
+/// returns it unchanged if the broadcast buffer is full. The output is boxed, so the
+/// result stays small on the hot path.
+fn try_emit(
+    out_chan: &mut Broadcaster<Envelope>,
+    mut output: Envelope,
+) -> Result<(), Box<Envelope>> {
+    output.hops = output.hops.saturating_add(1);
+
+    out_chan.try_broadcast(output).map_err(|mut output| {
+        output.hops -= 1;
+        Box::new(output)
+    })
+}
+
 impl Clone for EntityHost {
     fn clone(&self) -> Self {
         Self {
             uuid: self.uuid.clone(),
             joined_environments: Arc::clone(&self.joined_environments),
             affected_environments: Arc::clone(&self.affected_environments),
+            lost_environments: Arc::clone(&self.lost_environments),
             out_chan: Arc::clone(&self.out_chan),
+            pending_outputs: Arc::clone(&self.pending_outputs),
             drop_notifier: Arc::clone(&self.drop_notifier),
             shutdown_listener: Arc::clone(&self.shutdown_listener),
             waker: self.waker.clone(),
             num_received_effects: Arc::clone(&self.num_received_effects),
+            num_emitted_effects: Arc::clone(&self.num_emitted_effects),
+            last_sequences: Arc::clone(&self.last_sequences),
+            num_gaps_detected: Arc::clone(&self.num_gaps_detected),
+            num_discarded_effects: Arc::clone(&self.num_discarded_effects),
+            num_expired_effects: Arc::clone(&self.num_expired_effects),
+            poll_counters: Arc::clone(&self.poll_counters),
             entity: Arc::clone(&self.entity),
+            clock: Arc::clone(&self.clock),
+            ticker: Arc::clone(&self.ticker),
+            tracer: Arc::clone(&self.tracer),
+            routes: Arc::clone(&self.routes),
+            processing: Arc::clone(&self.processing),
+            last_active: Arc::clone(&self.last_active),
+            intake_limits: Arc::clone(&self.intake_limits),
+            core_started: Arc::clone(&self.core_started),
+            start_error: Arc::clone(&self.start_error),
+            restart_policy: Arc::clone(&self.restart_policy),
+            num_failed_starts: Arc::clone(&self.num_failed_starts),
+            error_sink: Arc::clone(&self.error_sink),
+            processing_timeout: Arc::clone(&self.processing_timeout),
+            processing_timeouts: Arc::clone(&self.processing_timeouts),
+            batch: Arc::clone(&self.batch),
+            inbox: Arc::clone(&self.inbox),
+            observation: Arc::clone(&self.observation),
+            output_buffer: Arc::clone(&self.output_buffer),
+            cancellation_token: self.cancellation_token.clone(),
+            cancelled_for_timeout: Arc::clone(&self.cancelled_for_timeout),
+            terminating: Arc::clone(&self.terminating),
+            terminated: Arc::clone(&self.terminated),
+            source: self.source,
         }
     }
 }
@@ -0,0 +1,88 @@
+//! Envelope
+
+use super::effect::Effect;
+
+use std::time::Instant;
+
+/// An effect together with the metadata that travels along with it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Envelope {
+    /// The wrapped effect
+    pub effect: Effect,
+
+    /// An id shared by a submitted effect and all effects resulting from it
+    pub correlation_id: Option<u64>,
+
+    /// The position of the effect in the total order of a sequenced environment
+    pub sequence: Option<u64>,
+
+    /// The uuid of the only joined entity supposed to receive the effect, if not
+    /// all of them
+    pub recipient: Option<String>,
+
+    /// The id of a submission waiting for the effect to be broadcast
+    pub submission: Option<u64>,
+
+    /// The name of the only affected environment supposed to take the effect, if
+    /// not all of them
+    pub target: Option<String>,
+
+    /// The number of times the effect was emitted by an entity on its way
+    pub hops: u32,
+
+    /// The instant after which the submitted effect is stale and dropped instead of
+    /// delivered, if it has a deadline
+    pub deadline: Option<Instant>,
+
+    /// Whether the effect is broadcast again from the history of its environment
+    pub replayed: bool,
+}
+
+impl Envelope {
+    /// Creates a new envelope without a correlation id.
+    pub fn new(effect: Effect) -> Self {
+        Self {
+            effect,
+            correlation_id: None,
+            sequence: None,
+            recipient: None,
+            submission: None,
+            target: None,
+            hops: 0,
+            deadline: None,
+            replayed: false,
+        }
+    }
+
+    /// Sets the correlation id.
+    pub fn with_correlation_id(mut self, correlation_id: u64) -> Self {
+        self.correlation_id.replace(correlation_id);
+        self
+    }
+
+    /// Sets the only affected environment supposed to take the effect.
+    pub fn with_target<S: Into<String>>(mut self, env_name: S) -> Self {
+        self.target.replace(env_name.into());
+        self
+    }
+}
+
+impl From<Effect> for Envelope {
+    fn from(effect: Effect) -> Self {
+        Self::new(effect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncorrelated_by_default() {
+        let envelope = Envelope::from(Effect::from("hello"));
+        assert_eq!(None, envelope.correlation_id);
+
+        let envelope = envelope.with_correlation_id(42);
+        assert_eq!(Some(42), envelope.correlation_id);
+    }
+}
@@ -1,26 +1,45 @@
 //! Environment module.
 
 use super::effect::Effect;
-use super::entity::EntityHost;
+use super::entity::{EntityHost, EnvironmentChannels};
+use super::envelope::Envelope;
 
+use crate::clock::{Clock, SystemClock};
 use crate::common::trigger::{Trigger, TriggerHandle};
 use crate::common::watcher::Watcher;
-use crate::constants::BROADCAST_BUFFER_SIZE;
+use crate::constants::{BROADCAST_BUFFER_SIZE, DEFAULT_MAX_HOPS, MAX_UNBATCH_DEPTH};
 use crate::errors::Error;
+use crate::metrics::{PollCounters, PollStats};
+use crate::trace::{FlowTracer, TraceStep};
 
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use bus::Bus as Broadcaster;
 use bus::BusReader as BroadcastReceiver;
-use crossbeam_channel::Receiver;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use rand::Rng;
 use tokio::prelude::*;
 
+/// A function an environment applies to every effect it receives.
+pub type Transform = Box<dyn Fn(Effect) -> Effect + Send>;
+
+/// Notifies a submission once its effect was broadcast, or why it was dropped.
+type BroadcastNotifier = Sender<Result<(), Error>>;
+
 /// An environment in the EEE model.
 pub struct Environment {
     /// Name of the environment
     name: String,
 
+    /// Configuration of the environment
+    config: EnvironmentConfig,
+
     /// Entities that joined this environment
     joined_entities: Arc<Mutex<Vec<JoinedEntity>>>,
 
@@ -28,10 +47,36 @@ pub struct Environment {
     affecting_entities: Arc<Mutex<Vec<AffectingEntity>>>,
 
     /// Receiver half of the channel to the supervisor
-    in_chan: Arc<Receiver<Effect>>,
+    in_chan: Arc<Receiver<Envelope>>,
+
+    /// An effect taken from the channel to the supervisor by `peek`, that will be
+    /// broadcast next
+    lookahead: Arc<Mutex<Option<Envelope>>>,
+
+    /// The effects of a received batch that weren't broadcast yet, if batches are
+    /// unbatched
+    unbatched: Arc<Mutex<VecDeque<Envelope>>>,
+
+    /// A queue replacing the channel to the supervisor, if effects should be
+    /// processed by priority
+    priority_queue: Option<Arc<Mutex<PriorityQueue>>>,
+
+    /// A queue replacing the channel to the supervisor, if only the latest effect
+    /// per key should be processed
+    keyed_queue: Option<Arc<Mutex<KeyedQueue>>>,
 
     /// Sender half of the outgoing broadcast channel to send data to entities.
-    out_chan: Arc<Mutex<Broadcaster<Effect>>>,
+    out_chan: Arc<Mutex<Broadcaster<Envelope>>>,
+
+    /// An effect that couldn't be broadcast yet, because the broadcast buffer was
+    /// full.
+    pending_effect: Arc<Mutex<Option<Envelope>>>,
+
+    /// The last broadcast effects, oldest first, if a history is kept
+    history: Arc<Mutex<VecDeque<Envelope>>>,
+
+    /// Effects of the history waiting to be broadcast again, ahead of anything new
+    replays: Arc<Mutex<VecDeque<Envelope>>>,
 
     /// A notifier that signals the end of this environment to subscribed
     /// entities
@@ -45,11 +90,327 @@ pub struct Environment {
 
     /// The number of received effects.
     num_received_effects: Arc<AtomicUsize>,
+
+    /// Records the hops of correlated effects
+    tracer: Arc<FlowTracer>,
+
+    /// The sequence number of the last broadcast effect, if sequenced
+    last_sequence: Arc<AtomicU64>,
+
+    /// Standby environments receiving a copy of every broadcast effect
+    mirrors: Arc<Mutex<Vec<Mirror>>>,
+
+    /// Functions applied to every received effect in order, before it's broadcast
+    transforms: Arc<Mutex<Vec<Transform>>>,
+
+    /// The number of broadcast effects
+    num_broadcast: Arc<AtomicU64>,
+
+    /// The number of effects broadcast by all environments of the node
+    node_throughput: Arc<AtomicU64>,
+
+    /// How effects are distributed across the joined entities
+    distribution: Arc<Mutex<Distribution>>,
+
+    /// Notifiers of submissions waiting for their effect to be broadcast, keyed by
+    /// submission id
+    broadcast_notifiers: Arc<Mutex<HashMap<u64, BroadcastNotifier>>>,
+
+    /// The number of effects handed directly to the only joined entity
+    num_direct: Arc<AtomicU64>,
+
+    /// The time spent handing effects over to the joined entities in nanoseconds
+    delivery_nanos: Arc<AtomicU64>,
+
+    /// Whether an entity ever joined this environment
+    joined_once: Arc<AtomicBool>,
+
+    /// The number of effects broadcast while no entity was listening
+    num_no_listeners: Arc<AtomicU64>,
+
+    /// The number of effects dropped for exceeding the hop limit
+    num_loop_dropped: Arc<AtomicU64>,
+
+    /// The clock telling whether effects exceeded their deadline
+    clock: Arc<dyn Clock>,
+
+    /// The number of effects dropped for exceeding their deadline
+    num_expired: Arc<AtomicU64>,
+
+    /// The number of polls and the effects received by each of them
+    poll_counters: Arc<PollCounters>,
+}
+
+/// How an environment distributes effects across its joined entities.
+#[derive(Clone, Debug, Default)]
+pub enum Distribution {
+    /// Every joined entity receives every effect
+    #[default]
+    Broadcast,
+    /// Each effect is received by one joined entity, chosen randomly with a
+    /// probability proportional to its weight. The weights are keyed by entity uuid,
+    /// and entities without a weight don't receive anything. Barriers are still
+    /// received by all joined entities.
+    Weighted(HashMap<String, u32>),
+}
+
+/// How the effects an environment receives and broadcasts are rendered in logs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogPolicy {
+    /// The whole effect
+    #[default]
+    Full,
+    /// The variant and the payload size only
+    Summary,
+    /// The variant and a hash prefix of the effect instead of its payload
+    Redacted,
+    /// Nothing is logged
+    None,
+}
+
+impl LogPolicy {
+    /// Renders an effect for logging. Returns None, if it shouldn't be logged.
+    pub fn render(self, effect: &Effect) -> Option<String> {
+        match self {
+            LogPolicy::Full => Some(format!("{:?}", effect)),
+            LogPolicy::Summary => {
+                Some(format!("{}({} bytes)", effect.variant(), effect.size()))
+            }
+            LogPolicy::Redacted => {
+                let mut hasher = DefaultHasher::new();
+                effect.hash(&mut hasher);
+                let hash = format!("{:016x}", hasher.finish());
+
+                Some(format!("{}#{}", effect.variant(), &hash[..8]))
+            }
+            LogPolicy::None => None,
+        }
+    }
+}
+
+/// A standby environment receiving a copy of every effect an environment broadcasts.
+pub(crate) struct Mirror {
+    /// Name of the standby environment
+    pub target: String,
+
+    /// Sender half of the channel to the standby environment
+    pub sender: Sender<Envelope>,
+
+    /// A waker to wake up the standby environment's task/future
+    pub waker: Watcher,
+
+    /// The number of mirrored effects
+    pub num_mirrored: u64,
+}
+
+/// What an entity gets to know about an environment it joined.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnvInfo {
+    /// The name of the environment
+    pub name: String,
+
+    /// The number of effects that can be broadcast before the joined entities have
+    /// to catch up
+    pub buffer_size: usize,
+
+    /// Whether broadcast effects have a sequence number
+    pub sequenced: bool,
+
+    /// Whether queued effects are coalesced by key
+    pub keyed: bool,
+
+    /// Whether queued effects are broadcast by priority
+    pub prioritized: bool,
+
+    /// Whether batch effects are broadcast as the effects they contain
+    pub auto_unbatching: bool,
+}
+
+/// How far a standby environment is behind the environment it mirrors.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MirrorStatus {
+    /// Name of the standby environment
+    pub target: String,
+
+    /// The number of effects mirrored so far
+    pub num_mirrored: u64,
+
+    /// The number of mirrored effects the standby environment hasn't taken in yet
+    pub lag: usize,
+}
+
+/// Configuration of an environment.
+///
+/// # Example
+/// ```
+/// use reee::eee::environment::EnvironmentConfig;
+///
+/// let config = EnvironmentConfig::default().sequenced(true);
+///
+/// assert!(config.is_sequenced());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentConfig {
+    /// Whether broadcast effects get a sequence number
+    sequenced: bool,
+
+    /// Whether queued effects are coalesced by key
+    keyed: bool,
+
+    /// Whether batch effects are broadcast as the effects they contain
+    auto_unbatch: bool,
+
+    /// How effects are rendered in logs
+    log_policy: LogPolicy,
+
+    /// Whether effects bypass the broadcast channel, if only one entity joined
+    loopback_direct: bool,
+
+    /// Whether effects are held back until the first entity joined
+    buffer_until_first_join: bool,
+
+    /// The most hops an effect may have taken, if not the default
+    max_hops: Option<u32>,
+
+    /// The number of broadcast effects kept for replays, if any
+    history: Option<usize>,
+}
+
+impl EnvironmentConfig {
+    /// Lets the environment number all effects it broadcasts with a dense sequence
+    /// starting at 1, so joined entities can rely on a total order.
+    pub fn sequenced(mut self, sequenced: bool) -> Self {
+        self.sequenced = sequenced;
+        self
+    }
+
+    /// Returns true, if the environment numbers the effects it broadcasts.
+    pub fn is_sequenced(&self) -> bool {
+        self.sequenced
+    }
+
+    /// Lets the environment keep only the latest of all queued effects submitted
+    /// with the same key.
+    pub fn keyed(mut self, keyed: bool) -> Self {
+        self.keyed = keyed;
+        self
+    }
+
+    /// Returns true, if the environment coalesces queued effects by key.
+    pub fn is_keyed(&self) -> bool {
+        self.keyed
+    }
+
+    /// Lets the environment broadcast the effects contained in a batch effect one by
+    /// one instead of the batch, and count each of them as received. Nested batches
+    /// are flattened one level. Empty batches are broadcast as they are.
+    pub fn auto_unbatch(mut self, auto_unbatch: bool) -> Self {
+        self.auto_unbatch = auto_unbatch;
+        self
+    }
+
+    /// Returns true, if the environment broadcasts the contents of batch effects.
+    pub fn is_auto_unbatching(&self) -> bool {
+        self.auto_unbatch
+    }
+
+    /// Lets the environment drop effects that were emitted by entities more than
+    /// `max_hops` times on their way, e.g. because they circulate in a feedback loop.
+    pub fn max_hops(mut self, max_hops: u32) -> Self {
+        self.max_hops.replace(max_hops);
+        self
+    }
+
+    /// Returns the most hops an effect may have taken before the environment drops
+    /// it.
+    pub fn get_max_hops(&self) -> u32 {
+        self.max_hops.unwrap_or(DEFAULT_MAX_HOPS)
+    }
+
+    /// Lets the environment keep the last `capacity` effects it broadcast, so they
+    /// can be replayed.
+    pub fn history(mut self, capacity: usize) -> Self {
+        self.history.replace(capacity);
+        self
+    }
+
+    /// Returns the number of broadcast effects the environment keeps, if it keeps
+    /// any.
+    pub fn get_history(&self) -> Option<usize> {
+        self.history
+    }
+
+    /// Sets how the effects of the environment are rendered in logs, also by the
+    /// entities that joined it. By default they are logged in full.
+    pub fn log_policy(mut self, log_policy: LogPolicy) -> Self {
+        self.log_policy = log_policy;
+        self
+    }
+
+    /// Returns how the effects of the environment are rendered in logs.
+    pub fn get_log_policy(&self) -> LogPolicy {
+        self.log_policy
+    }
+
+    /// Lets the environment hand its effects directly to the entity that joined it,
+    /// as long as it's the only one, instead of broadcasting them. It falls back to
+    /// broadcasting once another entity joins, after the first one took all effects
+    /// handed to it directly.
+    pub fn loopback_direct(mut self, loopback_direct: bool) -> Self {
+        self.loopback_direct = loopback_direct;
+        self
+    }
+
+    /// Returns true, if the environment bypasses the broadcast channel for a single
+    /// joined entity.
+    pub fn is_loopback_direct(&self) -> bool {
+        self.loopback_direct
+    }
+
+    /// Lets the environment hold back all effects until the first entity joined it,
+    /// so that entity receives everything submitted before. Without it, effects
+    /// broadcast before an entity joined are never received by it. Afterwards
+    /// effects are broadcast as usual, even if all entities left again.
+    pub fn buffer_until_first_join(mut self, buffer_until_first_join: bool) -> Self {
+        self.buffer_until_first_join = buffer_until_first_join;
+        self
+    }
+
+    /// Returns true, if the environment holds back effects until the first entity
+    /// joined it.
+    pub fn is_buffering_until_first_join(&self) -> bool {
+        self.buffer_until_first_join
+    }
+}
+
+/// A queue of effects ordered by priority, and by arrival for equal priorities.
+#[derive(Default)]
+pub(crate) struct PriorityQueue {
+    /// The queued effects
+    heap: BinaryHeap<PrioritizedEffect>,
+
+    /// The arrival number of the next queued effect
+    next_seq: u64,
+}
+
+struct PrioritizedEffect {
+    priority: u8,
+    seq: u64,
+    envelope: Envelope,
 }
 
 pub(crate) struct JoinedEntity {
+    /// Entity uuid
+    pub ent_uuid: String,
+
     /// A waker to wake up the entity's task/future
     pub ent_waker: Watcher,
+
+    /// The number of effects the entity acknowledged, gone if it stopped listening
+    pub acked: Weak<AtomicU64>,
+
+    /// Sender half of the channel bypassing the broadcast channel
+    pub direct_tx: Sender<Envelope>,
 }
 
 pub(crate) struct AffectingEntity {
@@ -57,46 +418,206 @@ pub(crate) struct AffectingEntity {
     pub ent_uuid: String,
 
     /// Entity effect receiver
-    pub ent_rx: BroadcastReceiver<Effect>,
+    pub ent_rx: BroadcastReceiver<Envelope>,
 
     /// Entity drop signal receiver
     pub ent_drop_rx: TriggerHandle,
+
+    /// A waker to wake up the entity's task/future
+    pub ent_waker: Watcher,
 }
 
 impl Environment {
     /// Creates a new environment.
     pub(crate) fn new(
         name: &str,
-        in_chan: Receiver<Effect>,
+        in_chan: Receiver<Envelope>,
         shutdown_listener: TriggerHandle,
     ) -> Self {
         let waker = Watcher::new();
         Self {
             name: name.into(),
+            config: EnvironmentConfig::default(),
             joined_entities: shared_mut!(vec![]),
             affecting_entities: shared_mut!(vec![]),
             in_chan: shared!(in_chan),
+            lookahead: shared_mut!(None),
+            unbatched: shared_mut!(VecDeque::new()),
+            priority_queue: None,
+            keyed_queue: None,
             out_chan: shared_mut!(Broadcaster::new(BROADCAST_BUFFER_SIZE)),
+            pending_effect: shared_mut!(None),
+            history: shared_mut!(VecDeque::new()),
+            replays: shared_mut!(VecDeque::new()),
             drop_notifier: shared_mut!(Trigger::new()),
             shutdown_listener: shared_mut!(shutdown_listener),
             waker,
             num_received_effects: shared!(AtomicUsize::new(0)),
+            tracer: shared!(FlowTracer::default()),
+            last_sequence: shared!(AtomicU64::new(0)),
+            mirrors: shared_mut!(vec![]),
+            transforms: shared_mut!(vec![]),
+            num_broadcast: shared!(AtomicU64::new(0)),
+            node_throughput: shared!(AtomicU64::new(0)),
+            distribution: shared_mut!(Distribution::default()),
+            broadcast_notifiers: shared_mut!(HashMap::new()),
+            num_direct: shared!(AtomicU64::new(0)),
+            delivery_nanos: shared!(AtomicU64::new(0)),
+            joined_once: shared!(AtomicBool::new(false)),
+            num_no_listeners: shared!(AtomicU64::new(0)),
+            num_loop_dropped: shared!(AtomicU64::new(0)),
+            clock: shared!(SystemClock),
+            num_expired: shared!(AtomicU64::new(0)),
+            poll_counters: shared!(PollCounters::default()),
         }
     }
 
-    /// Registers an entity that wants to join this evironment.
+    /// Configures this environment.
+    pub(crate) fn with_config(mut self, config: EnvironmentConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Lets this environment receive effects through a queue coalescing them by key
+    /// instead of its channel to the supervisor.
+    pub(crate) fn with_keyed_queue(mut self, queue: Arc<Mutex<KeyedQueue>>) -> Self {
+        self.keyed_queue.replace(queue);
+        self
+    }
+
+    /// Lets this environment receive effects through a priority queue instead of its
+    /// channel to the supervisor.
+    pub(crate) fn with_priority_queue(
+        mut self,
+        queue: Arc<Mutex<PriorityQueue>>,
+    ) -> Self {
+        self.priority_queue.replace(queue);
+        self
+    }
+
+    /// Lets this environment read the time from the given clock.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Lets this environment record the hops of correlated effects.
+    pub(crate) fn with_flow_tracer(mut self, tracer: Arc<FlowTracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Lets this environment count its broadcast effects towards the throughput of
+    /// the node.
+    pub(crate) fn with_node_throughput(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.node_throughput = counter;
+        self
+    }
+
+    /// Registers an entity that wants to join this evironment. The entity receives
+    /// every effect broadcast from now on, including those submitted before but not
+    /// broadcast yet.
     pub(crate) fn register_joining_entity(
         &mut self,
         entity: &mut EntityHost,
     ) -> Result<(), Error> {
-        //
-        let env_rx = unlock!(self.out_chan).add_rx();
+        self.register_joining_entity_with_backfill(entity, 0)
+    }
+
+    /// Registers an entity that wants to join this environment, which first receives
+    /// up to `backfill` of the most recent effects kept in the history, marked as
+    /// replayed, and then every effect broadcast from now on. Fails for a non-zero
+    /// backfill, if the environment keeps no history.
+    pub(crate) fn register_joining_entity_with_backfill(
+        &mut self,
+        entity: &mut EntityHost,
+        backfill: usize,
+    ) -> Result<(), Error> {
+        if backfill > 0 && self.config.get_history().is_none() {
+            return Err(Error::App(
+                "This environment keeps no history to backfill from.",
+            ));
+        }
+
+        // An environment that was dropped or shut down won't broadcast anything anymore
+        if unlock!(self.drop_notifier).is_pulled() {
+            return Err(Error::App("This environment has been dropped."));
+        }
+        if unlock!(self.shutdown_listener).is_pulled() {
+            return Err(Error::App("This environment has been shut down."));
+        }
+
+        // Hold the joined entities while adding the receiver, so no effect can be
+        // broadcast to the new receiver before its entity is registered to be woken
+        let mut joined = unlock!(self.joined_entities);
+
+        // Nothing is broadcast while the joined entities are held, so the backfill
+        // ends right where the new receiver starts
+        let backfill = {
+            let history = unlock!(self.history);
+            history
+                .iter()
+                .skip(history.len().saturating_sub(backfill))
+                .map(|envelope| Envelope {
+                    effect: envelope.effect.clone(),
+                    correlation_id: envelope.correlation_id,
+                    hops: envelope.hops,
+                    replayed: true,
+                    ..Envelope::new(Effect::Empty)
+                })
+                .collect::<VecDeque<_>>()
+        };
+
+        // The new receiver won't see anything broadcast before, so it only has to
+        // acknowledge the backfill
+        let (env_rx, acked) = {
+            let mut env_tx = unlock!(self.out_chan);
+            let num_broadcast = self.num_broadcast.load(Ordering::Acquire);
+            let num_unacked = num_broadcast.saturating_sub(backfill.len() as u64);
+            (env_tx.add_rx(), shared!(AtomicU64::new(num_unacked)))
+        };
         let env_drop_rx = unlock!(self.drop_notifier).get_handle();
+        let env_waker = self.waker.clone();
+
+        let (ent_waker, direct_tx) = entity.join_environment(
+            &self.name,
+            EnvironmentChannels {
+                env_rx,
+                env_drop_rx,
+                env_waker,
+                acked: Arc::clone(&acked),
+                environment: self.clone(),
+                backfill,
+            },
+        )?;
+
+        joined.push(JoinedEntity {
+            ent_uuid: entity.uuid().into(),
+            ent_waker,
+            acked: Arc::downgrade(&acked),
+            direct_tx,
+        });
+
+        // Let the environment broadcast the effects it held back for the first entity
+        if !self.joined_once.swap(true, Ordering::AcqRel)
+            && self.config.is_buffering_until_first_join()
+        {
+            self.waker.notify();
+        }
+
+        Ok(())
+    }
 
-        let ent_waker = entity.join_environment(&self.name, env_rx, env_drop_rx)?;
-        let joiner = JoinedEntity { ent_waker };
+    /// Deregisters an entity that wants to leave this environment.
+    pub(crate) fn deregister_joining_entity(
+        &mut self,
+        entity: &mut EntityHost,
+    ) -> Result<(), Error> {
+        let mut joined = unlock!(self.joined_entities);
+
+        entity.leave_environment(&self.name)?;
 
-        unlock!(self.joined_entities).push(joiner);
+        joined.retain(|joiner| joiner.ent_uuid != entity.uuid());
 
         Ok(())
     }
@@ -106,12 +627,12 @@ impl Environment {
         &mut self,
         entity: &mut EntityHost,
     ) -> Result<(), Error> {
-        //
+        // Lock the environment before the entity, like when joining
+        let mut affecting = unlock!(self.affecting_entities);
         let env_waker = self.waker.clone();
 
-        //
         let affector = entity.affect_environment(&self.name, env_waker)?;
-        unlock!(self.affecting_entities).push(affector);
+        affecting.push(affector);
 
         Ok(())
     }
@@ -133,116 +654,894 @@ impl Environment {
     /// Returns the number of effects that this entity has received.
     pub fn num_received_effects(&self) -> usize {
         self.num_received_effects.load(Ordering::Relaxed)
-        //*unlock!(self.num_received_effects)
+    }
+
+    /// Sets how effects are distributed across the joined entities. By default every
+    /// joined entity receives every effect.
+    pub fn set_distribution(&mut self, distribution: Distribution) {
+        *unlock!(self.distribution) = distribution;
+    }
+
+    /// Returns the uuids of the entities that joined this environment.
+    pub fn joined_entities(&self) -> Vec<String> {
+        unlock!(self.joined_entities)
+            .iter()
+            .map(|joiner| joiner.ent_uuid.clone())
+            .collect()
+    }
+
+    /// Returns the uuids of the entities that affect this environment.
+    pub fn affecting_entities(&self) -> Vec<String> {
+        unlock!(self.affecting_entities)
+            .iter()
+            .map(|affecting| affecting.ent_uuid.clone())
+            .collect()
+    }
+
+    /// Returns the number of entities that joined this environment.
+    pub fn num_joined_entities(&self) -> usize {
+        unlock!(self.joined_entities).len()
+    }
+
+    /// Returns the number of entities that affect this environment.
+    pub fn num_affecting_entities(&self) -> usize {
+        unlock!(self.affecting_entities).len()
+    }
+
+    /// Returns the number of effects waiting to be broadcast.
+    pub(crate) fn num_queued(&self) -> usize {
+        let queued = match (&self.priority_queue, &self.keyed_queue) {
+            (Some(queue), _) => unlock!(queue).len(),
+            (_, Some(queue)) => unlock!(queue).len(),
+            _ => self.in_chan.len() + unlock!(self.lookahead).iter().count(),
+        };
+
+        // Don't hold one lock while taking the other, polling takes them the other
+        // way around
+        let unbatched = unlock!(self.unbatched).len();
+        let replays = unlock!(self.replays).len();
+
+        queued + unbatched + replays + unlock!(self.pending_effect).iter().count()
+    }
+
+    /// Returns the number of effects handed directly to the only joined entity,
+    /// bypassing the broadcast channel.
+    pub fn num_delivered_directly(&self) -> u64 {
+        self.num_direct.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of effects broadcast while no joined entity was listening,
+    /// which were lost.
+    pub fn num_broadcast_no_listeners(&self) -> u64 {
+        self.num_no_listeners.load(Ordering::Relaxed)
+    }
+
+    /// Returns the effects kept in the history, oldest first.
+    pub fn history(&self) -> Vec<Effect> {
+        unlock!(self.history).iter().map(|envelope| envelope.effect.clone()).collect()
+    }
+
+    /// Broadcasts the effects kept in the history once more to all entities that
+    /// joined this environment now, ahead of anything new, and returns their number.
+    /// Replayed effects aren't kept in the history again and don't count towards the
+    /// throughput. Fails if the environment keeps no history.
+    pub fn replay_all(&self) -> Result<usize, Error> {
+        if self.config.get_history().is_none() {
+            return Err(Error::App("This environment keeps no history to replay."));
+        }
+
+        let replays = unlock!(self.history)
+            .iter()
+            .map(|envelope| Envelope {
+                effect: envelope.effect.clone(),
+                correlation_id: envelope.correlation_id,
+                hops: envelope.hops,
+                replayed: true,
+                ..Envelope::new(Effect::Empty)
+            })
+            .collect::<Vec<_>>();
+        let num = replays.len();
+
+        unlock!(self.replays).extend(replays);
+        self.waker.notify();
+
+        Ok(num)
+    }
+
+    /// Returns the number of effects dropped, because they exceeded the hop limit.
+    pub fn num_loop_dropped(&self) -> u64 {
+        self.num_loop_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of effects dropped instead of broadcast, because their
+    /// deadline was exceeded.
+    pub fn num_expired(&self) -> u64 {
+        self.num_expired.load(Ordering::Relaxed)
+    }
+
+    /// Returns how often this environment was woken up and polled, and how many
+    /// effects each poll received.
+    pub fn poll_stats(&self) -> PollStats {
+        self.poll_counters.stats(self.waker.num_notifies())
+    }
+
+    /// Returns the number of times this environment was woken up. Notifications
+    /// arriving before it started polling again are coalesced into one.
+    pub fn num_notifies(&self) -> u64 {
+        self.waker.num_notifies()
+    }
+
+    /// Returns the time spent handing effects over to the joined entities, through
+    /// the broadcast channel or directly.
+    pub fn delivery_time(&self) -> Duration {
+        Duration::from_nanos(self.delivery_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Returns true, if the environment holds back all effects, because no entity
+    /// joined it yet.
+    pub(crate) fn is_awaiting_first_join(&self) -> bool {
+        self.config.is_buffering_until_first_join()
+            && !self.joined_once.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of effects broadcast so far.
+    pub(crate) fn num_broadcast(&self) -> u64 {
+        self.num_broadcast.load(Ordering::Acquire)
+    }
+
+    /// Returns a receiver that gets notified once the effect of the given submission
+    /// has been broadcast, or with an error once it was dropped instead.
+    pub(crate) fn notify_on_broadcast(
+        &self,
+        submission: u64,
+    ) -> Receiver<Result<(), Error>> {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        unlock!(self.broadcast_notifiers).insert(submission, sender);
+        receiver
+    }
+
+    /// Stops waiting for the effect of the given submission to be broadcast.
+    pub(crate) fn cancel_broadcast_notification(&self, submission: u64) {
+        unlock!(self.broadcast_notifiers).remove(&submission);
+    }
+
+    /// Forgets about a deleted entity that joined or affected this environment.
+    pub(crate) fn forget_entity(&self, uuid: &str) {
+        unlock!(self.joined_entities).retain(|joiner| joiner.ent_uuid != uuid);
+        unlock!(self.affecting_entities).retain(|affecting| affecting.ent_uuid != uuid);
+    }
+
+    /// Returns the configuration of this environment.
+    pub fn config(&self) -> &EnvironmentConfig {
+        &self.config
+    }
+
+    /// Returns what an entity joining this environment gets to know about it.
+    pub fn info(&self) -> EnvInfo {
+        EnvInfo {
+            name: self.name.clone(),
+            buffer_size: BROADCAST_BUFFER_SIZE,
+            sequenced: self.config.is_sequenced(),
+            keyed: self.keyed_queue.is_some(),
+            prioritized: self.priority_queue.is_some(),
+            auto_unbatching: self.config.is_auto_unbatching(),
+        }
+    }
+
+    /// Returns the sequence number of the last broadcast effect, or 0 if the
+    /// environment isn't sequenced or hasn't broadcast anything yet.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::Acquire)
     }
 
     /// Returns a waker that allows to wake this environments task/future.
     pub(crate) fn get_waker(&self) -> Watcher {
         self.waker.clone()
     }
+
+    /// Returns the next effect this environment will broadcast without consuming
+    /// it, as it will be broadcast: an effect held back during the last poll, then a
+    /// replayed one, then the next effect submitted by the supervisor, transformed.
+    /// Effects of affecting entities aren't looked at.
+    pub fn peek(&self) -> Option<Effect> {
+        if let Some(envelope) = unlock!(self.pending_effect).as_ref() {
+            return Some(envelope.effect.clone());
+        }
+        if let Some(envelope) = unlock!(self.replays).front() {
+            return Some(envelope.effect.clone());
+        }
+
+        let unbatched =
+            unlock!(self.unbatched).front().map(|envelope| envelope.effect.clone());
+        let effect = match unbatched {
+            Some(effect) => effect,
+            None => self.peek_queued()?,
+        };
+
+        let mut envelope = Envelope::new(effect);
+        self.transform(&mut envelope);
+
+        Some(envelope.effect)
+    }
+
+    /// Returns the next effect submitted by the supervisor that is still queued,
+    /// without consuming it.
+    fn peek_queued(&self) -> Option<Effect> {
+        let effect = if let Some(queue) = &self.priority_queue {
+            unlock!(queue).peek().map(|envelope| envelope.effect.clone())
+        } else if let Some(queue) = &self.keyed_queue {
+            unlock!(queue).peek().map(|envelope| envelope.effect.clone())
+        } else {
+            let mut lookahead = unlock!(self.lookahead);
+            if lookahead.is_none() {
+                *lookahead = self.in_chan.try_recv().ok();
+            }
+
+            lookahead.as_ref().map(|envelope| envelope.effect.clone())
+        };
+
+        // The first effect of a batch is broadcast first
+        match effect {
+            Some(effect) if self.config.is_auto_unbatching() => {
+                effect.unbatch(MAX_UNBATCH_DEPTH).into_iter().next().or(Some(effect))
+            }
+            effect => effect,
+        }
+    }
+
+    /// Appends a function to the transforms applied to every effect received from
+    /// now on. Transforms are applied in the order they were added.
+    pub(crate) fn add_transform(&self, transform: Transform) {
+        unlock!(self.transforms).push(transform);
+    }
+
+    /// Removes all transforms.
+    pub(crate) fn clear_transforms(&self) {
+        unlock!(self.transforms).clear();
+    }
+
+    /// Lets a standby environment receive a copy of every effect this environment
+    /// broadcasts from now on.
+    pub(crate) fn add_mirror(&self, mirror: Mirror) {
+        unlock!(self.mirrors).push(mirror);
+    }
+
+    /// Stops copying effects to a standby environment. Returns false, if there was no
+    /// such mirror.
+    pub(crate) fn remove_mirror(&self, target: &str) -> bool {
+        let mut mirrors = unlock!(self.mirrors);
+        let num_mirrors = mirrors.len();
+
+        mirrors.retain(|mirror| mirror.target != target);
+
+        mirrors.len() < num_mirrors
+    }
+
+    /// Returns the names of all standby environments of this environment.
+    pub(crate) fn mirror_targets(&self) -> Vec<String> {
+        unlock!(self.mirrors).iter().map(|mirror| mirror.target.clone()).collect()
+    }
+
+    /// Returns how far each standby environment is behind this environment.
+    pub fn mirror_status(&self) -> Vec<MirrorStatus> {
+        unlock!(self.mirrors)
+            .iter()
+            .map(|mirror| MirrorStatus {
+                target: mirror.target.clone(),
+                num_mirrored: mirror.num_mirrored,
+                lag: mirror.sender.len(),
+            })
+            .collect()
+    }
+
+    /// Broadcasts an effect to the joined entities, or returns it boxed if it has to
+    /// be held back. A barrier is held back until all joined entities acknowledged
+    /// the effects broadcast before it.
+    fn broadcast(
+        &self,
+        env_tx: &mut Broadcaster<Envelope>,
+        joined: &[JoinedEntity],
+        mut envelope: Envelope,
+    ) -> Result<(), Box<Envelope>> {
+        if envelope.recipient.is_none() {
+            envelope.recipient = self.choose_recipient(joined, &envelope);
+        }
+
+        if let Effect::Barrier(_) = envelope.effect {
+            let num_broadcast = self.num_broadcast.load(Ordering::Acquire);
+
+            let acked = |joiner: &JoinedEntity| match joiner.acked.upgrade() {
+                Some(acked) => acked.load(Ordering::Acquire) >= num_broadcast,
+                None => true,
+            };
+            if !joined.iter().all(acked) {
+                return Err(Box::new(envelope));
+            }
+        }
+
+        let submission = envelope.submission;
+        let replayed = envelope.replayed;
+        let retained = match self.config.get_history() {
+            Some(capacity) if !replayed && capacity > 0 => Some(envelope.clone()),
+            _ => None,
+        };
+
+        let started = Instant::now();
+        let delivered = self.deliver(env_tx, joined, envelope);
+        self.delivery_nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        delivered?;
+
+        // Replays are counted for the entities to acknowledge, but not as throughput
+        self.num_broadcast.fetch_add(1, Ordering::AcqRel);
+        if !replayed {
+            self.node_throughput.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(envelope) = retained {
+            let mut history = unlock!(self.history);
+            history.push_back(envelope);
+            while history.len() > self.config.get_history().unwrap_or(0) {
+                history.pop_front();
+            }
+        }
+
+        // The broadcast channel silently drops an effect without readers. That's only
+        // counted, not logged, as it happens for every effect until someone joins.
+        if !joined.iter().any(|joiner| joiner.acked.upgrade().is_some()) {
+            self.num_no_listeners.fetch_add(1, Ordering::Relaxed);
+
+            #[cfg(feature = "tracing")]
+            tracing::trace!(env = %self.name, "effect broadcast without listeners");
+        }
+
+        if let Some(submission) = submission {
+            if let Some(notifier) = unlock!(self.broadcast_notifiers).remove(&submission)
+            {
+                let _ = notifier.try_send(Ok(()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, if a received effect exceeded its deadline and has to be dropped
+    /// instead of broadcast. It is checked before the effect is sequenced or mirrored,
+    /// so it leaves no trace, and a submission waiting for it is told it was dropped.
+    fn is_expired(&self, envelope: &Envelope) -> bool {
+        if envelope.deadline.is_none_or(|deadline| self.clock.now() <= deadline) {
+            return false;
+        }
+
+        println!("Env. {} dropped effect: deadline exceeded", self.name);
+        self.num_expired.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(submission) = envelope.submission {
+            if let Some(notifier) = unlock!(self.broadcast_notifiers).remove(&submission)
+            {
+                let _ = notifier.try_send(Err(Error::App(
+                    "The effect exceeded its deadline and was dropped.",
+                )));
+            }
+        }
+
+        true
+    }
+
+    /// Hands an effect over to the joined entities. It bypasses the broadcast channel
+    /// if the loopback is direct, the entity is the only one and it took everything
+    /// broadcast before. Nothing is broadcast while an entity has effects handed to it
+    /// directly left, so the order is kept when falling back. An effect that can't
+    /// be handed over yet is returned boxed.
+    fn deliver(
+        &self,
+        env_tx: &mut Broadcaster<Envelope>,
+        joined: &[JoinedEntity],
+        envelope: Envelope,
+    ) -> Result<(), Box<Envelope>> {
+        let listening = |joiner: &&JoinedEntity| joiner.acked.upgrade().is_some();
+
+        if let (true, [joiner]) = (self.config.is_loopback_direct(), joined) {
+            if let Some(acked) = joiner.acked.upgrade() {
+                let num_broadcast = self.num_broadcast.load(Ordering::Acquire);
+
+                if acked.load(Ordering::Acquire) >= num_broadcast
+                    || !joiner.direct_tx.is_empty()
+                {
+                    return match joiner.direct_tx.try_send(envelope) {
+                        Ok(()) => {
+                            self.num_direct.fetch_add(1, Ordering::Relaxed);
+                            Ok(())
+                        }
+                        Err(TrySendError::Full(envelope)) => Err(Box::new(envelope)),
+                        Err(TrySendError::Disconnected(_)) => Ok(()),
+                    };
+                }
+            }
+        }
+
+        if joined.iter().filter(listening).any(|joiner| !joiner.direct_tx.is_empty()) {
+            return Err(Box::new(envelope));
+        }
+
+        env_tx.try_broadcast(envelope).map_err(Box::new)
+    }
+
+    /// Chooses the joined entity to receive an effect, if it shouldn't be received by
+    /// all of them.
+    fn choose_recipient(
+        &self,
+        joined: &[JoinedEntity],
+        envelope: &Envelope,
+    ) -> Option<String> {
+        if let Effect::Barrier(_) = envelope.effect {
+            return None;
+        }
+
+        let weights = match &*unlock!(self.distribution) {
+            Distribution::Broadcast => return None,
+            Distribution::Weighted(weights) => joined
+                .iter()
+                .filter_map(|joiner| {
+                    let weight = *weights.get(&joiner.ent_uuid)?;
+                    Some((&joiner.ent_uuid, weight))
+                })
+                .filter(|(_, weight)| *weight > 0)
+                .collect::<Vec<_>>(),
+        };
+
+        let total = weights.iter().map(|(_, weight)| u64::from(*weight)).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = rand::thread_rng().gen_range(0, total);
+        for (uuid, weight) in weights {
+            if pick < u64::from(weight) {
+                return Some(uuid.clone());
+            }
+            pick -= u64::from(weight);
+        }
+
+        None
+    }
+
+    /// Applies all transforms to a received effect in order.
+    fn transform(&self, envelope: &mut Envelope) {
+        for transform in unlock!(self.transforms).iter() {
+            let effect = std::mem::replace(&mut envelope.effect, Effect::Empty);
+            envelope.effect = transform(effect);
+        }
+    }
+
+    /// Sends a copy of an effect that is about to be broadcast to all standby
+    /// environments. A standby environment that is gone is forgotten without
+    /// affecting this environment.
+    fn mirror(&self, envelope: &Envelope) {
+        unlock!(self.mirrors).retain_mut(|mirror| {
+            if mirror.sender.send(envelope.clone()).is_err() {
+                return false;
+            }
+
+            mirror.num_mirrored += 1;
+            mirror.waker.notify();
+
+            true
+        });
+    }
+
+    /// Stamps an effect that is about to be broadcast with the next sequence number,
+    /// if this environment is sequenced.
+    fn sequence(&self, envelope: &mut Envelope) {
+        envelope.sequence = if self.config.sequenced {
+            Some(self.last_sequence.fetch_add(1, Ordering::AcqRel) + 1)
+        } else {
+            None
+        };
+    }
+
+    /// Tries to receive the next effect submitted by the supervisor.
+    fn try_recv(&self) -> Option<Envelope> {
+        let mut unbatched = unlock!(self.unbatched);
+        if let Some(envelope) = unbatched.pop_front() {
+            return Some(envelope);
+        }
+
+        let envelope = self.try_recv_queued()?;
+        if !self.config.is_auto_unbatching() {
+            return Some(envelope);
+        }
+
+        let effects = match &envelope.effect {
+            Effect::Batch(_) => envelope.effect.unbatch(MAX_UNBATCH_DEPTH),
+            _ => return Some(envelope),
+        };
+        if effects.is_empty() {
+            return Some(envelope);
+        }
+
+        // Only the last effect completes a waiting submission
+        let num_effects = effects.len();
+        for (i, effect) in effects.into_iter().enumerate() {
+            unbatched.push_back(Envelope {
+                effect,
+                correlation_id: envelope.correlation_id,
+                sequence: envelope.sequence,
+                recipient: envelope.recipient.clone(),
+                submission: if i + 1 == num_effects { envelope.submission } else { None },
+                target: envelope.target.clone(),
+                hops: envelope.hops,
+                deadline: envelope.deadline,
+                replayed: envelope.replayed,
+            });
+        }
+
+        unbatched.pop_front()
+    }
+
+    /// Receives the next submitted effect from the queue or channel it was submitted
+    /// to.
+    fn try_recv_queued(&self) -> Option<Envelope> {
+        if let Some(queue) = &self.keyed_queue {
+            return unlock!(queue).pop();
+        }
+
+        match &self.priority_queue {
+            Some(queue) => unlock!(queue).pop(),
+            None => match unlock!(self.lookahead).take() {
+                Some(envelope) => Some(envelope),
+                None => self.in_chan.try_recv().ok(),
+            },
+        }
+    }
+}
+
+impl PriorityQueue {
+    /// Queues an effect with the given priority.
+    pub fn push(&mut self, envelope: Envelope, priority: u8) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        self.heap.push(PrioritizedEffect { priority, seq, envelope });
+    }
+
+    /// Returns the number of queued effects.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Returns the effect with the highest priority without removing it.
+    pub fn peek(&self) -> Option<&Envelope> {
+        self.heap.peek().map(|prioritized| &prioritized.envelope)
+    }
+
+    /// Removes the effect with the highest priority from the queue.
+    pub fn pop(&mut self) -> Option<Envelope> {
+        self.heap.pop().map(|prioritized| prioritized.envelope)
+    }
+}
+
+/// A queue of effects in arrival order, that keeps only the latest effect per key.
+#[derive(Default)]
+pub(crate) struct KeyedQueue {
+    /// The keys of the queued effects in arrival order, None for unkeyed effects
+    order: VecDeque<Option<String>>,
+
+    /// The latest queued effect per key
+    keyed: HashMap<String, Envelope>,
+
+    /// The queued effects without a key
+    unkeyed: VecDeque<Envelope>,
+}
+
+impl KeyedQueue {
+    /// Queues an effect. An effect with a key replaces a queued effect with the same
+    /// key, and takes its place in the queue.
+    pub fn push(&mut self, envelope: Envelope, key: Option<&str>) {
+        match key {
+            Some(key) => {
+                if self.keyed.insert(key.into(), envelope).is_none() {
+                    self.order.push_back(Some(key.into()));
+                }
+            }
+            None => {
+                self.unkeyed.push_back(envelope);
+                self.order.push_back(None);
+            }
+        }
+    }
+
+    /// Returns the number of queued effects.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Returns the next effect without removing it.
+    pub fn peek(&self) -> Option<&Envelope> {
+        match self.order.front()? {
+            Some(key) => self.keyed.get(key),
+            None => self.unkeyed.front(),
+        }
+    }
+
+    /// Removes the next effect from the queue.
+    pub fn pop(&mut self) -> Option<Envelope> {
+        match self.order.pop_front()? {
+            Some(key) => self.keyed.remove(&key),
+            None => self.unkeyed.pop_front(),
+        }
+    }
 }
 
+impl Ord for PrioritizedEffect {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        // Higher priorities first, then first come first served
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for PrioritizedEffect {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PrioritizedEffect {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for PrioritizedEffect {}
+
 impl Future for Environment {
     type Item = ();
     type Error = Error;
 
     fn poll(&mut self) -> Poll<(), Self::Error> {
-        self.waker.task.register();
+        self.waker.register();
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("env.broadcast", env = %self.name).entered();
 
         // As long as effects can be received go on broadcasting them
         {
             let joined = unlock!(self.joined_entities);
             let mut affecting = unlock!(self.affecting_entities);
             let mut env_tx = unlock!(self.out_chan);
+            let mut pending_effect = unlock!(self.pending_effect);
 
             // TODO: maybe make this a for-loop with some predefined max number
             // of effects to not block other futures from making
             // progress
             let mut num_received = self.num_received_effects.load(Ordering::Acquire);
+            let num_received_before = num_received;
 
             let mut num = 0;
 
-            // Forward incoming effects from the supervisor to all subscribed entities
-            loop {
+            // Hold back all effects until the first entity joined, if configured
+            let awaiting_first_join = self.is_awaiting_first_join();
+
+            // Try to broadcast an effect that was held back during the last poll
+            if let Some(effect) = pending_effect.take() {
+                if let Err(effect) = self.broadcast(&mut env_tx, &joined, effect) {
+                    pending_effect.replace(*effect);
+                }
+            }
+
+            // Replay the history before anything new
+            let mut replays = unlock!(self.replays);
+            while pending_effect.is_none() {
+                let envelope = match replays.pop_front() {
+                    Some(envelope) => envelope,
+                    None => break,
+                };
+                if let Err(envelope) = self.broadcast(&mut env_tx, &joined, envelope) {
+                    pending_effect.replace(*envelope);
+                }
+            }
+            drop(replays);
+
+            // Forward incoming effects from the supervisor to all subscribed entities,
+            // unless the joined entities need to catch up first
+            while !awaiting_first_join && pending_effect.is_none() {
                 // Try to receive a new effect from the supervisor
-                match self.in_chan.try_recv() {
-                    Ok(effect) => {
-                        num += 1;
+                let mut envelope = match self.try_recv() {
+                    Some(envelope) => envelope,
+                    None => break,
+                };
 
-                        println!(
-                            "Env. {} received effect '{:?}' from supervisor ({})",
-                            self.name,
-                            effect,
-                            num_received + num
-                        );
+                num += 1;
+
+                if let Some(effect) =
+                    self.config.get_log_policy().render(&envelope.effect)
+                {
+                    println!(
+                        "Env. {} received effect '{}' from supervisor ({})",
+                        self.name,
+                        effect,
+                        num_received + num
+                    );
+                }
 
-                        // Broadcast received effect to joined entities
-                        env_tx.broadcast(effect);
+                #[cfg(feature = "tracing")]
+                tracing::trace!(
+                    source = "supervisor",
+                    size = envelope.effect.size(),
+                    "effect received"
+                );
 
-                        // Wake all joined entities if half of the broadcaster
-                        // buffer size is full
-                        if num == BROADCAST_BUFFER_SIZE / 2 {
-                            for JoinedEntity { ent_waker } in joined.iter() {
-                                ent_waker.task.notify();
-                            }
+                self.tracer.record(envelope.correlation_id, || TraceStep::Received {
+                    environment: self.name.clone(),
+                });
 
-                            num_received += num;
-                            num = 0;
-                        }
+                // Drop stale effects instead of handing them over
+                if self.is_expired(&envelope) {
+                    continue;
+                }
+
+                self.transform(&mut envelope);
+                self.sequence(&mut envelope);
+                self.mirror(&envelope);
+
+                // Broadcast received effect to joined entities, or hold it back if the
+                // broadcast buffer is full
+                if let Err(envelope) = self.broadcast(&mut env_tx, &joined, envelope) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(size = envelope.effect.size(), "effect held back");
+
+                    pending_effect.replace(*envelope);
+                }
+
+                // Wake all joined entities if half of the broadcaster
+                // buffer size is full
+                if num == BROADCAST_BUFFER_SIZE / 2 {
+                    for JoinedEntity { ent_waker, .. } in joined.iter() {
+                        ent_waker.notify();
                     }
-                    _ => break,
+
+                    num_received += num;
+                    num = 0;
                 }
             } // end forwarding supervisor effects
 
             // Wake all joined entities to process the remaining effects buffered in the
             // broadcast channel
-            for JoinedEntity { ent_waker } in joined.iter() {
-                ent_waker.task.notify();
+            for JoinedEntity { ent_waker, .. } in joined.iter() {
+                ent_waker.notify();
             }
 
             num_received += num;
             num = 0;
 
-            //
-            for AffectingEntity { ent_uuid, ent_rx, ent_drop_rx: _ } in
+            // Receive the effects of all affecting entities and forward them to the
+            // joined entities, unless those need to catch up first
+            for AffectingEntity { ent_uuid, ent_rx, ent_waker, .. } in
                 affecting.iter_mut()
             {
-                loop {
-                    match ent_rx.try_recv() {
-                        Ok(effect) => {
-                            num += 1;
-
-                            println!(
-                                "Env. {} received effect '{:?}' from entity {} ({})",
-                                self.name,
-                                effect,
-                                &ent_uuid[0..5],
-                                num_received + num,
-                            );
+                let mut num_from_entity = 0;
+
+                while !awaiting_first_join && pending_effect.is_none() {
+                    let mut envelope = match ent_rx.try_recv() {
+                        Ok(envelope) => envelope,
+                        Err(_) => break,
+                    };
+
+                    num_from_entity += 1;
+
+                    // Skip effects targeted at another affected environment
+                    if let Some(target) = envelope.target.as_ref() {
+                        if *target != self.name {
+                            continue;
                         }
-                        _ => break,
+                    }
+
+                    // Drop effects that took too many hops, e.g. in a feedback loop
+                    if envelope.hops > self.config.get_max_hops() {
+                        println!(
+                            "Env. {} dropped effect from entity {} after {} hops",
+                            self.name,
+                            &ent_uuid[0..5],
+                            envelope.hops
+                        );
+                        self.num_loop_dropped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+
+                    num += 1;
+
+                    if let Some(effect) =
+                        self.config.get_log_policy().render(&envelope.effect)
+                    {
+                        println!(
+                            "Env. {} received effect '{}' from entity {} ({})",
+                            self.name,
+                            effect,
+                            &ent_uuid[0..5],
+                            num_received + num,
+                        );
+                    }
+
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(
+                        source = "entity",
+                        entity = %ent_uuid,
+                        size = envelope.effect.size(),
+                        "effect received"
+                    );
+
+                    self.tracer.record(envelope.correlation_id, || TraceStep::Received {
+                        environment: self.name.clone(),
+                    });
+
+                    if self.is_expired(&envelope) {
+                        continue;
+                    }
+
+                    self.transform(&mut envelope);
+                    self.sequence(&mut envelope);
+                    self.mirror(&envelope);
+
+                    if let Err(envelope) = self.broadcast(&mut env_tx, &joined, envelope)
+                    {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(
+                            size = envelope.effect.size(),
+                            "effect held back"
+                        );
+
+                        pending_effect.replace(*envelope);
                     }
                 }
+
+                // Wake the entity in case it waits for room in its broadcast channel
+                if num_from_entity > 0 {
+                    ent_waker.notify();
+                }
+            }
+
+            // Wake all joined entities to process the effects forwarded from affecting
+            // entities
+            if num > 0 {
+                for JoinedEntity { ent_waker, .. } in joined.iter() {
+                    ent_waker.notify();
+                }
             }
 
             self.num_received_effects.store(num_received + num, Ordering::Release);
-        }
+            self.poll_counters.record((num_received + num - num_received_before) as u64);
 
-        // Check for shutdown signal
-        match unlock!(self.shutdown_listener).0.poll() {
-            // sig-term received
-            Ok(Async::Ready(Some(is_term))) => {
-                if is_term {
-                    println!("Env. {} received sig-term", self.name);
-                    // End this future
-                    return Ok(Async::Ready(()));
+            // Forget about affecting entities that were dropped
+            let mut to_drop = vec![];
+            for (i, AffectingEntity { ent_uuid, ent_drop_rx, .. }) in
+                affecting.iter_mut().enumerate()
+            {
+                if let Ok(Async::Ready(Some(true))) = ent_drop_rx.0.poll() {
+                    println!(
+                        "Env. {} received sig-term from entity {}",
+                        self.name,
+                        &ent_uuid[0..5]
+                    );
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(entity = %ent_uuid, "affecting entity dropped");
+
+                    to_drop.push(i);
                 }
             }
-            _ => (),
+            for i in to_drop.into_iter().rev() {
+                affecting.remove(i);
+            }
+        }
+
+        // Check for shutdown signal
+        if let Ok(Async::Ready(Some(true))) = unlock!(self.shutdown_listener).0.poll() {
+            println!("Env. {} received sig-term", self.name);
+            // End this future
+            return Ok(Async::Ready(()));
         }
 
         // otherwise go to sleep
-        return Ok(Async::NotReady);
+        Ok(Async::NotReady)
     }
 }
 
@@ -250,14 +1549,80 @@ impl Clone for Environment {
     fn clone(&self) -> Self {
         Self {
             name: self.name.clone(),
+            config: self.config.clone(),
             joined_entities: Arc::clone(&self.joined_entities),
             affecting_entities: Arc::clone(&self.affecting_entities),
             in_chan: Arc::clone(&self.in_chan),
+            lookahead: Arc::clone(&self.lookahead),
+            unbatched: Arc::clone(&self.unbatched),
+            priority_queue: self.priority_queue.clone(),
+            keyed_queue: self.keyed_queue.clone(),
             out_chan: Arc::clone(&self.out_chan),
+            pending_effect: Arc::clone(&self.pending_effect),
+            history: Arc::clone(&self.history),
+            replays: Arc::clone(&self.replays),
             drop_notifier: Arc::clone(&self.drop_notifier),
             shutdown_listener: Arc::clone(&self.shutdown_listener),
             waker: self.waker.clone(),
             num_received_effects: Arc::clone(&self.num_received_effects),
+            tracer: Arc::clone(&self.tracer),
+            last_sequence: Arc::clone(&self.last_sequence),
+            mirrors: Arc::clone(&self.mirrors),
+            transforms: Arc::clone(&self.transforms),
+            num_broadcast: Arc::clone(&self.num_broadcast),
+            node_throughput: Arc::clone(&self.node_throughput),
+            distribution: Arc::clone(&self.distribution),
+            broadcast_notifiers: Arc::clone(&self.broadcast_notifiers),
+            num_direct: Arc::clone(&self.num_direct),
+            delivery_nanos: Arc::clone(&self.delivery_nanos),
+            joined_once: Arc::clone(&self.joined_once),
+            num_no_listeners: Arc::clone(&self.num_no_listeners),
+            num_loop_dropped: Arc::clone(&self.num_loop_dropped),
+            clock: Arc::clone(&self.clock),
+            num_expired: Arc::clone(&self.num_expired),
+            poll_counters: Arc::clone(&self.poll_counters),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_effects_by_log_policy() {
+        let ascii = Effect::ascii("secret").unwrap();
+        let bytes = Effect::from(vec![1_u8, 2, 3]);
+
+        assert_eq!(
+            Some(String::from("Ascii(\"secret\")")),
+            LogPolicy::Full.render(&ascii)
+        );
+        assert_eq!(
+            Some(String::from("Bytes([1, 2, 3])")),
+            LogPolicy::Full.render(&bytes)
+        );
+
+        assert_eq!(
+            Some(String::from("Ascii(6 bytes)")),
+            LogPolicy::Summary.render(&ascii)
+        );
+        assert_eq!(
+            Some(String::from("Bytes(3 bytes)")),
+            LogPolicy::Summary.render(&bytes)
+        );
+
+        let redacted = LogPolicy::Redacted.render(&ascii).unwrap();
+        assert!(redacted.starts_with("Ascii#"));
+        assert_eq!(14, redacted.len());
+        assert!(!redacted.contains("secret"));
+        assert_eq!(Some(redacted), LogPolicy::Redacted.render(&ascii.clone()));
+        assert_ne!(
+            LogPolicy::Redacted.render(&bytes),
+            LogPolicy::Redacted.render(&Effect::from(vec![1_u8, 2, 4]))
+        );
+
+        assert_eq!(None, LogPolicy::None.render(&ascii));
+        assert_eq!(None, LogPolicy::None.render(&bytes));
+    }
+}
@@ -1,9 +1,22 @@
 //! EEE models.
 
+pub mod aggregate;
 pub mod effect;
 pub mod entity;
+pub mod envelope;
 pub mod environment;
+#[cfg(feature = "regex")]
+pub mod router;
+pub mod template;
 
-pub use effect::Effect;
-pub use entity::{Entity, EntityHost};
-pub use environment::Environment;
+pub use aggregate::{AggKind, AggregateCore, StatsCore};
+pub use effect::{Effect, EffectKind, Endian, Meta};
+pub use entity::{
+    CancellationToken, CoreSubscriptions, Entity, EntityHost, GapDetected, RestartPolicy,
+    RouteTarget, StartContext,
+};
+pub use envelope::Envelope;
+pub use environment::{Distribution, EnvInfo, Environment, LogPolicy, Transform};
+#[cfg(feature = "regex")]
+pub use router::RegexRouterCore;
+pub use template::TemplateCore;
@@ -0,0 +1,152 @@
+//! Router
+
+use super::effect::Effect;
+use super::entity::{Entity, StartContext};
+use super::envelope::Envelope;
+
+use crate::errors::{Error, Result};
+
+use regex::Regex;
+
+/// A core that routes every text effect it receives to the first affected
+/// environment whose pattern matches the text.
+///
+/// Routes are tried in the order they were added. A text without a matching route,
+/// or anything other than text, isn't emitted at all. The core fails to start, if
+/// a route leads to an environment its hosting entity doesn't affect.
+///
+/// # Example
+/// ```
+/// use reee::eee::{Effect, Envelope, Entity, RegexRouterCore};
+///
+/// let mut core = RegexRouterCore::new()
+///     .route(r"^\d+$", "numbers")
+///     .unwrap()
+///     .route(r"^[a-z]+$", "letters")
+///     .unwrap();
+/// let routed = core.process_envelope(Envelope::new(Effect::from("123")), "X");
+///
+/// assert_eq!(Some(String::from("numbers")), routed.target);
+/// ```
+#[derive(Default)]
+pub struct RegexRouterCore {
+    routes: Vec<(Regex, String)>,
+}
+
+impl RegexRouterCore {
+    /// Creates a core without any routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route to the given environment for all texts matching the pattern.
+    /// Fails if the pattern isn't a valid regular expression.
+    pub fn route(mut self, pattern: &str, env_name: &str) -> Result<Self> {
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(_) => return Err(Error::App("Invalid routing pattern")),
+        };
+        self.routes.push((regex, env_name.into()));
+
+        Ok(self)
+    }
+
+    /// Returns the environment an effect is routed to, if any.
+    pub fn target(&self, effect: &Effect) -> Option<&str> {
+        let text = effect.as_str()?;
+
+        self.routes
+            .iter()
+            .find(|(regex, _)| regex.is_match(text))
+            .map(|(_, env_name)| env_name.as_str())
+    }
+}
+
+impl Entity for RegexRouterCore {
+    fn on_start(&mut self, ctx: &StartContext) -> Result<()> {
+        let affected = ctx.affected_environments();
+
+        match self.routes.iter().all(|(_, env_name)| affected.contains(env_name)) {
+            true => Ok(()),
+            false => Err(Error::App("Routing to an environment that isn't affected")),
+        }
+    }
+
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        match self.target(&effect) {
+            Some(_) => effect,
+            None => Effect::Empty,
+        }
+    }
+
+    fn process_envelope(&mut self, envelope: Envelope, _: &str) -> Envelope {
+        let correlation_id = envelope.correlation_id;
+
+        match self.target(&envelope.effect).map(String::from) {
+            Some(target) => Envelope { correlation_id, ..Envelope::new(envelope.effect) }
+                .with_target(target),
+            // Environment names are never empty, so no environment takes this and the
+            // hosting entity drops it
+            None => Envelope { correlation_id, ..Envelope::new(Effect::Empty) }
+                .with_target(""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> RegexRouterCore {
+        RegexRouterCore::new()
+            .route(r"^\d+$", "numbers")
+            .unwrap()
+            .route(r"^[a-z]+$", "letters")
+            .unwrap()
+            .route(r"^[a-z0-9]+$", "mixed")
+            .unwrap()
+    }
+
+    #[test]
+    fn route_to_first_matching_environment() {
+        let mut core = router();
+        let route = |core: &mut RegexRouterCore, effect: Effect| {
+            core.process_envelope(Envelope::new(effect).with_correlation_id(7), "X")
+        };
+
+        let routed = route(&mut core, Effect::ascii("123").unwrap());
+        assert_eq!(Some(String::from("numbers")), routed.target);
+        assert_eq!(Effect::ascii("123").unwrap(), routed.effect);
+        assert_eq!(Some(7), routed.correlation_id);
+
+        let routed = route(&mut core, Effect::ascii("abc").unwrap());
+        assert_eq!(Some(String::from("letters")), routed.target);
+
+        assert_eq!(Some("mixed"), core.target(&Effect::from("abc123")));
+    }
+
+    #[test]
+    fn drop_unrouted_effects() {
+        let mut core = router();
+
+        let routed = core.process_envelope(Envelope::new(Effect::from("ABC")), "X");
+        assert_eq!(Some(String::new()), routed.target);
+        assert_eq!(Effect::Empty, routed.effect);
+        assert_eq!(None, core.target(&Effect::from(123_u64)));
+    }
+
+    #[test]
+    fn refuse_to_start_with_unaffected_targets() {
+        let affected = vec!["numbers".into(), "letters".into(), "mixed".into()];
+        let ctx = StartContext::detached(vec!["X".into()], affected);
+        assert!(router().on_start(&ctx).is_ok());
+
+        let ctx = StartContext::detached(vec!["X".into()], vec!["numbers".into()]);
+        assert!(router().on_start(&ctx).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_patterns() {
+        assert!(RegexRouterCore::new().route(r"^(\d+$", "numbers").is_err());
+    }
+}
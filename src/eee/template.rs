@@ -0,0 +1,120 @@
+//! Template
+
+use super::effect::Effect;
+use super::entity::Entity;
+
+use crate::errors::{Error, Result};
+
+/// A core that renders a template from every map effect it receives, replacing each
+/// `{key}` placeholder with the value of that key.
+///
+/// The rendered text is emitted as an ASCII effect, or as a UTF-8 effect if a value
+/// isn't ASCII. Anything other than a map, or a map that can't be rendered, results
+/// in an empty effect.
+///
+/// # Example
+/// ```
+/// use reee::eee::{Effect, Entity, TemplateCore};
+///
+/// let mut core = TemplateCore::new("Hello {name}");
+/// let effect = vec![("name", "world")].into_iter().collect::<Effect>();
+///
+/// assert_eq!(Some("Hello world"), core.process_effect(effect, "X").as_str());
+/// ```
+pub struct TemplateCore {
+    template: String,
+    strict: bool,
+}
+
+impl TemplateCore {
+    /// Creates a core rendering the given template. Placeholders without a value
+    /// are left as they are.
+    pub fn new<S: Into<String>>(template: S) -> Self {
+        Self { template: template.into(), strict: false }
+    }
+
+    /// Sets whether a placeholder without a value is an error instead of being left
+    /// as it is.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Renders the template with the values of a map effect.
+    pub fn render(&self, values: &Effect) -> Result<String> {
+        match values {
+            Effect::Map(_) => (),
+            _ => return Err(Error::App("Templates are rendered from map effects only")),
+        }
+
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+
+            rendered.push_str(&rest[..start]);
+            match values.get(&rest[start + 1..end]) {
+                Some(value) => rendered.push_str(value),
+                None if self.strict => return Err(Error::App("Unmatched placeholder")),
+                None => rendered.push_str(&rest[start..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        rendered.push_str(rest);
+
+        Ok(rendered)
+    }
+}
+
+impl Entity for TemplateCore {
+    fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+        match self.render(&effect) {
+            Ok(rendered) if rendered.is_ascii() => {
+                Effect::ascii(rendered).expect("checked to be ASCII")
+            }
+            Ok(rendered) => Effect::text(rendered),
+            Err(_) => Effect::Empty,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: Vec<(&str, &str)>) -> Effect {
+        pairs.into_iter().collect()
+    }
+
+    #[test]
+    fn substitute_placeholders() {
+        let mut core = TemplateCore::new("Hello {name}, you are {age}!");
+        let values = map(vec![("name", "Alice"), ("age", "42")]);
+
+        assert_eq!(
+            Effect::ascii("Hello Alice, you are 42!").unwrap(),
+            core.process_effect(values, "X")
+        );
+        assert_eq!(
+            Effect::text("Hello Jürgen, you are 7!"),
+            core.process_effect(map(vec![("name", "Jürgen"), ("age", "7")]), "X")
+        );
+        assert_eq!(Effect::Empty, core.process_effect(Effect::from("Alice"), "X"));
+    }
+
+    #[test]
+    fn unmatched_placeholders() {
+        let values = map(vec![("name", "Alice")]);
+
+        let lenient = TemplateCore::new("{greeting} {name}{");
+        assert_eq!("{greeting} Alice{", lenient.render(&values).unwrap());
+
+        let mut strict = TemplateCore::new("{greeting} {name}").strict(true);
+        assert!(strict.render(&values).is_err());
+        assert_eq!(Effect::Empty, strict.process_effect(values, "X"));
+    }
+}
@@ -16,6 +16,22 @@ pub enum Error {
     TriggerSend(tokio::sync::watch::error::SendError<bool>),
     /// An I/O error.
     Io(io::Error),
+    /// The environment behind a raw sender was deleted.
+    ChannelClosed,
+    /// An entity core took longer than the processing timeout for an effect.
+    ProcessingTimeout {
+        /// The uuid of the entity
+        uuid: String,
+        /// The correlation id of the effect, if it had one
+        effect_id: Option<u64>,
+    },
+    /// An entity core failed to start.
+    StartFailed {
+        /// The uuid of the entity
+        uuid: String,
+        /// What the core failed with
+        cause: String,
+    },
 }
 
 impl From<&'static str> for Error {
@@ -6,7 +6,14 @@ mod common;
 
 mod constants;
 
+pub mod clock;
 pub mod eee;
 pub mod errors;
+pub mod metrics;
 pub mod node;
 pub mod supervisor;
+pub mod topology;
+pub mod trace;
+
+pub use common::shutdown::GracefulShutdown;
+pub use common::trigger::TriggerHandle;
@@ -37,7 +37,7 @@ fn test1() {
     thread::sleep(Duration::from_millis(500));
 
     println!(">>> Sending effect 'hello' to {}", x.name());
-    node.submit_effect(Effect::from("hello"), &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
 
     thread::sleep(Duration::from_millis(1000));
 
@@ -66,10 +66,10 @@ fn test2() {
     thread::sleep(Duration::from_millis(500));
 
     println!(">>> Sending effect 'hello' to {}", x.name());
-    node.submit_effect(Effect::from("hello"), &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
 
     println!(">>> Sending effect 'world' to {}", y.name());
-    node.submit_effect(Effect::from("world"), &y.name()).unwrap();
+    node.submit_effect(Effect::from("world"), y.name()).unwrap();
 
     thread::sleep(Duration::from_millis(500));
 
@@ -112,7 +112,7 @@ fn test4() {
 
     node.join_environments(&mut a, vec![&x.name()]).unwrap();
     node.affect_environments(&mut a, vec![&y.name()]).unwrap();
-    node.submit_effect(Effect::from("hello"), &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
 
     thread::sleep(Duration::from_millis(1000));
 
@@ -131,7 +131,7 @@ fn test5() {
     node.join_environments(&mut a, vec![&x.name()]).unwrap();
     node.affect_environments(&mut a, vec![&y.name(), &z.name()]).unwrap();
 
-    node.submit_effect(Effect::from("hello"), &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
 
     thread::sleep(Duration::from_millis(1000));
 
@@ -141,22 +141,20 @@ fn test5() {
 struct StringReverse;
 impl Entity for StringReverse{
     fn process_effect(&mut self, effect: Effect, _environment: &str) -> Effect {
-        let result = match effect {
-            Effect::String(s) => Effect::from(s.chars().rev().collect::<String>()),
-            _ => Effect::Empty,
-        };
-        result
+        match effect.as_str() {
+            Some(s) => Effect::text(s.chars().rev().collect::<String>()),
+            None => Effect::Empty,
+        }
     }
 }
 
 struct StringUppercase;
 impl Entity for StringUppercase {
     fn process_effect(&mut self, effect: Effect, _environment: &str) -> Effect {
-        let result = match effect {
-            Effect::String(s) => Effect::from(s.to_uppercase()),
-            _ => Effect::Empty,
-        };
-        result
+        match effect.as_str() {
+            Some(s) => Effect::text(s.to_uppercase()),
+            None => Effect::Empty,
+        }
     }
 }
 
@@ -195,7 +193,7 @@ fn test6() {
 
     // Send 'hello' to input environment X
     println!(">>> Sending effect 'hello' to {}", x.name());
-    node.submit_effect(Effect::from("hello"), &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
 
     thread::sleep(Duration::from_millis(1000));
 
@@ -0,0 +1,573 @@
+//! Metrics
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The minimum time between two samples kept by a throughput meter.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The longest window a throughput rate is computed over.
+const MAX_WINDOW: Duration = Duration::from_secs(60);
+
+/// A snapshot of the counters of all environments and entities of a supervisor.
+#[derive(Clone, Debug, Default)]
+pub struct SupervisorMetrics {
+    /// The metrics of each environment, sorted by name.
+    pub environments: Vec<EnvironmentMetrics>,
+
+    /// The metrics of each entity, sorted by uuid.
+    pub entities: Vec<EntityMetrics>,
+
+    /// The number of effects flowing through the node.
+    pub throughput: Throughput,
+}
+
+/// The number of effects broadcast by all environments of a node, in total and per
+/// second over sliding windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Throughput {
+    /// The number of effects broadcast since the node was created.
+    pub total: u64,
+
+    /// Effects per second during the last second.
+    pub rate_1s: f64,
+
+    /// Effects per second during the last 10 seconds.
+    pub rate_10s: f64,
+
+    /// Effects per second during the last 60 seconds.
+    pub rate_60s: f64,
+}
+
+/// Computes the throughput from samples of a growing total.
+#[derive(Debug, Default)]
+pub(crate) struct ThroughputMeter {
+    /// Samples of the total, oldest first
+    samples: VecDeque<(Instant, u64)>,
+
+    /// The throughput computed from the latest sample
+    current: Throughput,
+}
+
+impl ThroughputMeter {
+    /// Records the total at the given time and updates the rates.
+    pub fn sample(&mut self, now: Instant, total: u64) {
+        match self.samples.back() {
+            Some(&(last, _)) if now < last + SAMPLE_INTERVAL => (),
+            _ => self.samples.push_back((now, total)),
+        }
+
+        // Keep the newest sample older than the longest window as its baseline
+        while self.samples.len() > 1 && self.samples[1].0 + MAX_WINDOW <= now {
+            self.samples.pop_front();
+        }
+
+        self.current = Throughput {
+            total,
+            rate_1s: self.rate(now, total, Duration::from_secs(1)),
+            rate_10s: self.rate(now, total, Duration::from_secs(10)),
+            rate_60s: self.rate(now, total, MAX_WINDOW),
+        };
+    }
+
+    /// Returns the throughput computed from the latest sample.
+    pub fn current(&self) -> Throughput {
+        self.current
+    }
+
+    /// Returns the effects per second during the window ending now. Before a full
+    /// window has been sampled, the window starts at the oldest sample.
+    fn rate(&self, now: Instant, total: u64, window: Duration) -> f64 {
+        let baseline = self
+            .samples
+            .iter()
+            .rev()
+            .find(|(time, _)| *time + window <= now)
+            .or_else(|| self.samples.front());
+
+        match baseline {
+            Some(&(_, then)) => (total - then) as f64 / window.as_secs_f64(),
+            None => 0.0,
+        }
+    }
+}
+
+/// How often the future of an environment or entity was woken up and polled, and
+/// how many effects each poll handled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PollStats {
+    /// The number of polls.
+    pub num_polls: u64,
+
+    /// The number of times the future was woken up.
+    pub num_wakes: u64,
+
+    /// The number of polls that didn't handle any effect.
+    pub num_spurious_polls: u64,
+
+    /// The number of effects handled by all polls.
+    pub num_handled: u64,
+
+    /// The fewest effects handled by a poll that handled any.
+    pub min_per_poll: u64,
+
+    /// The most effects handled by a poll.
+    pub max_per_poll: u64,
+}
+
+impl PollStats {
+    /// Returns the average number of effects handled by the polls that handled any.
+    pub fn avg_per_poll(&self) -> f64 {
+        match self.num_polls - self.num_spurious_polls {
+            0 => 0.0,
+            num_polls => self.num_handled as f64 / num_polls as f64,
+        }
+    }
+}
+
+/// Counts the polls of a future and the effects each of them handled.
+#[derive(Debug)]
+pub(crate) struct PollCounters {
+    num_polls: AtomicU64,
+    num_spurious_polls: AtomicU64,
+    num_handled: AtomicU64,
+    min_per_poll: AtomicU64,
+    max_per_poll: AtomicU64,
+}
+
+impl Default for PollCounters {
+    fn default() -> Self {
+        Self {
+            num_polls: AtomicU64::new(0),
+            num_spurious_polls: AtomicU64::new(0),
+            num_handled: AtomicU64::new(0),
+            min_per_poll: AtomicU64::new(u64::MAX),
+            max_per_poll: AtomicU64::new(0),
+        }
+    }
+}
+
+impl PollCounters {
+    /// Records a poll that handled the given number of effects.
+    pub fn record(&self, num_handled: u64) {
+        self.num_polls.fetch_add(1, Ordering::Relaxed);
+
+        if num_handled == 0 {
+            self.num_spurious_polls.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.num_handled.fetch_add(num_handled, Ordering::Relaxed);
+        self.min_per_poll.fetch_min(num_handled, Ordering::Relaxed);
+        self.max_per_poll.fetch_max(num_handled, Ordering::Relaxed);
+    }
+
+    /// Returns the counters together with the number of times the future was woken
+    /// up.
+    pub fn stats(&self, num_wakes: u64) -> PollStats {
+        let min_per_poll = match self.min_per_poll.load(Ordering::Relaxed) {
+            u64::MAX => 0,
+            min => min,
+        };
+
+        PollStats {
+            num_polls: self.num_polls.load(Ordering::Relaxed),
+            num_wakes,
+            num_spurious_polls: self.num_spurious_polls.load(Ordering::Relaxed),
+            num_handled: self.num_handled.load(Ordering::Relaxed),
+            min_per_poll,
+            max_per_poll: self.max_per_poll.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The poll statistics of all environments and entities of a supervisor.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeStats {
+    /// The statistics of each environment by name.
+    pub environments: BTreeMap<String, PollStats>,
+
+    /// The statistics of each entity by uuid.
+    pub entities: BTreeMap<String, PollStats>,
+}
+
+/// The counters of an environment.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMetrics {
+    /// The name of the environment.
+    pub name: String,
+
+    /// The number of effects the environment has received.
+    pub num_received_effects: usize,
+
+    /// The number of submitted effects waiting to be broadcast.
+    pub num_queued_effects: usize,
+
+    /// How often the environment was polled.
+    pub polls: PollStats,
+}
+
+/// The counters of an entity.
+#[derive(Clone, Debug)]
+pub struct EntityMetrics {
+    /// The uuid of the entity.
+    pub uuid: String,
+
+    /// The number of effects the entity has received.
+    pub num_received_effects: usize,
+
+    /// The number of effects the entity has emitted to the environments it affects.
+    pub num_emitted_effects: usize,
+
+    /// How often the entity was polled.
+    pub polls: PollStats,
+}
+
+/// The counters of a namespace.
+#[derive(Clone, Debug)]
+pub struct NamespaceMetrics {
+    /// The name of the namespace.
+    pub name: String,
+
+    /// The metrics of each environment of the namespace, sorted by name.
+    pub environments: Vec<EnvironmentMetrics>,
+
+    /// The metrics of each entity of the namespace, sorted by uuid.
+    pub entities: Vec<EntityMetrics>,
+
+    /// The number of effects submitted into the namespace.
+    pub num_submitted_effects: u64,
+
+    /// The number of effects rejected for exceeding the effects per second.
+    pub num_throttled_effects: u64,
+}
+
+impl SupervisorMetrics {
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+
+        write_header(
+            &mut text,
+            "reee_env_received_total",
+            "counter",
+            "Number of effects received by an environment.",
+        );
+        for env in self.environments.iter() {
+            writeln!(
+                text,
+                "reee_env_received_total{{env=\"{}\"}} {}",
+                escape(&env.name),
+                env.num_received_effects
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_env_queued_effects",
+            "gauge",
+            "Number of submitted effects waiting to be broadcast by an environment.",
+        );
+        for env in self.environments.iter() {
+            writeln!(
+                text,
+                "reee_env_queued_effects{{env=\"{}\"}} {}",
+                escape(&env.name),
+                env.num_queued_effects
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_env_polls_total",
+            "counter",
+            "Number of times an environment was polled.",
+        );
+        for env in self.environments.iter() {
+            writeln!(
+                text,
+                "reee_env_polls_total{{env=\"{}\"}} {}",
+                escape(&env.name),
+                env.polls.num_polls
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_env_spurious_polls_total",
+            "counter",
+            "Number of times an environment was polled without handling any effect.",
+        );
+        for env in self.environments.iter() {
+            writeln!(
+                text,
+                "reee_env_spurious_polls_total{{env=\"{}\"}} {}",
+                escape(&env.name),
+                env.polls.num_spurious_polls
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_entity_received_total",
+            "counter",
+            "Number of effects received by an entity.",
+        );
+        for ent in self.entities.iter() {
+            writeln!(
+                text,
+                "reee_entity_received_total{{entity=\"{}\"}} {}",
+                ent.uuid, ent.num_received_effects
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_entity_emitted_total",
+            "counter",
+            "Number of effects emitted by an entity.",
+        );
+        for ent in self.entities.iter() {
+            writeln!(
+                text,
+                "reee_entity_emitted_total{{entity=\"{}\"}} {}",
+                ent.uuid, ent.num_emitted_effects
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_entity_polls_total",
+            "counter",
+            "Number of times an entity was polled.",
+        );
+        for ent in self.entities.iter() {
+            writeln!(
+                text,
+                "reee_entity_polls_total{{entity=\"{}\"}} {}",
+                ent.uuid, ent.polls.num_polls
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_entity_spurious_polls_total",
+            "counter",
+            "Number of times an entity was polled without handling any effect.",
+        );
+        for ent in self.entities.iter() {
+            writeln!(
+                text,
+                "reee_entity_spurious_polls_total{{entity=\"{}\"}} {}",
+                ent.uuid, ent.polls.num_spurious_polls
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "reee_node_effects_total",
+            "counter",
+            "Number of effects broadcast by all environments of the node.",
+        );
+        writeln!(text, "reee_node_effects_total {}", self.throughput.total).unwrap();
+
+        write_header(
+            &mut text,
+            "reee_node_effects_rate",
+            "gauge",
+            "Effects per second broadcast by all environments of the node.",
+        );
+        for (window, rate) in [
+            ("1s", self.throughput.rate_1s),
+            ("10s", self.throughput.rate_10s),
+            ("60s", self.throughput.rate_60s),
+        ]
+        .iter()
+        {
+            writeln!(text, "reee_node_effects_rate{{window=\"{}\"}} {}", window, rate)
+                .unwrap();
+        }
+
+        text
+    }
+}
+
+fn write_header(text: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A tiny blocking HTTP responder serving the metrics of a supervisor.
+#[cfg(feature = "metrics-http")]
+pub(crate) mod http {
+    use crate::supervisor::Supervisor;
+
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Answers every request on the listener with the current metrics until `stop`
+    /// is set.
+    pub fn serve(listener: TcpListener, supervisor: Supervisor, stop: Arc<AtomicBool>) {
+        listener.set_nonblocking(true).expect("error configuring the metrics listener");
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = respond(stream, &supervisor) {
+                            println!("Error serving metrics: {}", e);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => println!("Error accepting metrics connection: {}", e),
+                }
+            }
+        });
+    }
+
+    fn respond(mut stream: TcpStream, supervisor: &Supervisor) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        // Read until the end of the request header, its content doesn't matter
+        let mut request = vec![];
+        let mut buf = [0; 512];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let body = supervisor.metrics().to_prometheus();
+
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_prometheus_text() {
+        let metrics = SupervisorMetrics {
+            environments: vec![EnvironmentMetrics {
+                name: String::from("X"),
+                num_received_effects: 123,
+                num_queued_effects: 2,
+                polls: PollStats {
+                    num_polls: 40,
+                    num_spurious_polls: 4,
+                    ..PollStats::default()
+                },
+            }],
+            entities: vec![EntityMetrics {
+                uuid: String::from("a"),
+                num_received_effects: 7,
+                num_emitted_effects: 5,
+                polls: PollStats { num_polls: 6, ..PollStats::default() },
+            }],
+            throughput: Throughput {
+                total: 50,
+                rate_1s: 2.5,
+                rate_10s: 1.0,
+                rate_60s: 0.5,
+            },
+        };
+
+        let text = metrics.to_prometheus();
+
+        assert!(text.contains("# TYPE reee_env_received_total counter\n"));
+        assert!(text.contains("reee_env_received_total{env=\"X\"} 123\n"));
+        assert!(text.contains("reee_env_queued_effects{env=\"X\"} 2\n"));
+        assert!(text.contains("reee_entity_received_total{entity=\"a\"} 7\n"));
+        assert!(text.contains("reee_entity_emitted_total{entity=\"a\"} 5\n"));
+        assert!(text.contains("reee_env_polls_total{env=\"X\"} 40\n"));
+        assert!(text.contains("reee_env_spurious_polls_total{env=\"X\"} 4\n"));
+        assert!(text.contains("reee_entity_polls_total{entity=\"a\"} 6\n"));
+        assert!(text.contains("reee_node_effects_total 50\n"));
+        assert!(text.contains("reee_node_effects_rate{window=\"1s\"} 2.5\n"));
+    }
+
+    #[test]
+    fn count_effects_handled_per_poll() {
+        let counters = PollCounters::default();
+        assert_eq!(PollStats::default(), counters.stats(0));
+
+        for &num_handled in [3, 0, 5, 1, 0].iter() {
+            counters.record(num_handled);
+        }
+
+        let stats = counters.stats(4);
+        assert_eq!(
+            PollStats {
+                num_polls: 5,
+                num_wakes: 4,
+                num_spurious_polls: 2,
+                num_handled: 9,
+                min_per_poll: 1,
+                max_per_poll: 5,
+            },
+            stats
+        );
+        assert_eq!(3.0, stats.avg_per_poll());
+    }
+
+    #[test]
+    fn compute_rates_over_sliding_windows() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut meter = ThroughputMeter::default();
+
+        meter.sample(at(0), 0);
+        meter.sample(at(1), 10);
+        assert_eq!(10.0, meter.current().rate_1s);
+        assert_eq!(1.0, meter.current().rate_10s);
+
+        // 10 effects per second for 60 seconds
+        for secs in 2..=60 {
+            meter.sample(at(secs), secs * 10);
+        }
+        assert_eq!(
+            Throughput { total: 600, rate_1s: 10.0, rate_10s: 10.0, rate_60s: 10.0 },
+            meter.current()
+        );
+
+        // Nothing for 5 seconds
+        meter.sample(at(65), 600);
+        assert_eq!(0.0, meter.current().rate_1s);
+        assert_eq!(5.0, meter.current().rate_10s);
+        assert_eq!(550.0 / 60.0, meter.current().rate_60s);
+    }
+}
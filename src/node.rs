@@ -1,25 +1,93 @@
 //! A node featuring a Supervisor.
 
+use crate::clock::Clock;
 use crate::common::shutdown::GracefulShutdown;
+use crate::eee::environment::EnvironmentConfig;
+use crate::eee::environment::MirrorStatus;
 use crate::eee::Effect;
+use crate::eee::Entity;
 use crate::eee::EntityHost;
 use crate::eee::Environment;
-use crate::errors::Result;
-use crate::supervisor::Supervisor;
+use crate::errors::{Error, Result};
+use crate::metrics::{NamespaceMetrics, SupervisorMetrics, Throughput};
+use crate::supervisor::{
+    EnvironmentSender, JoinOptions, MirrorTarget, NamespaceLimits, PruneOptions,
+    PruneReport, Supervisor,
+};
+use crate::topology::{self, CoreFactory, TopologySpec, ValidationReport};
+use crate::trace::TraceStep;
+
+use std::collections::HashMap;
+#[cfg(feature = "metrics-http")]
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use tokio::prelude::*;
 use tokio::runtime::Runtime;
 
 /// A node featuring a Supervisor
 pub struct Node {
-    /// The Tokio runtime for this node.
-    runtime: Runtime,
+    /// A handle to this node, which owns its runtime.
+    handle: NodeHandle,
+
+    /// Whether the node was started.
+    started: bool,
 
     /// The supervisor used for messaging.
     supervisor: Supervisor,
 
     /// Graceful shutdown of the supervisor and all started async tasks.
     graceful_shutdown: GracefulShutdown,
+
+    /// The environment effects are submitted to, if none is named.
+    default_environment: Option<String>,
+
+    /// The tasks that panicked.
+    failed_tasks: Arc<Mutex<Vec<TaskFailure>>>,
+
+    /// The cores topologies can refer to by name.
+    cores: HashMap<String, CoreFactory>,
+
+    /// The unix signals `run` reacts to besides CTRL-C.
+    #[cfg(all(unix, feature = "unix-signals"))]
+    signals: Signals,
+}
+
+/// The unix signals a running node reacts to besides CTRL-C.
+///
+/// By default SIGTERM shuts the node down gracefully and SIGHUP is left alone.
+#[cfg(all(unix, feature = "unix-signals"))]
+pub struct Signals {
+    /// Whether SIGTERM shuts the node down
+    sigterm: bool,
+
+    /// Gets called on every SIGHUP, which doesn't shut the node down
+    on_sighup: Option<Box<dyn Fn() + Send>>,
+}
+
+#[cfg(all(unix, feature = "unix-signals"))]
+impl Signals {
+    /// Sets whether SIGTERM shuts the node down gracefully.
+    pub fn sigterm(mut self, enabled: bool) -> Self {
+        self.sigterm = enabled;
+        self
+    }
+
+    /// Calls the callback on every SIGHUP, e.g. to reload a configuration.
+    pub fn on_sighup<F: Fn() + Send + 'static>(mut self, callback: F) -> Self {
+        self.on_sighup.replace(Box::new(callback));
+        self
+    }
+}
+
+#[cfg(all(unix, feature = "unix-signals"))]
+impl Default for Signals {
+    fn default() -> Self {
+        Self { sigterm: true, on_sighup: None }
+    }
 }
 
 impl Node {
@@ -28,63 +96,433 @@ impl Node {
         let graceful_shutdown = GracefulShutdown::new();
         let sd_handle = graceful_shutdown.get_listener();
 
+        let supervisor = Supervisor::new(sd_handle)?;
+        let handle = NodeHandle {
+            runtime: shared_mut!(Some(Runtime::new()?)),
+            supervisor: supervisor.clone(),
+            graceful_shutdown: graceful_shutdown.clone(),
+            watchdog_stop: shared!(AtomicBool::new(false)),
+            #[cfg(feature = "metrics-http")]
+            metrics_stop: shared!(AtomicBool::new(false)),
+            stopped: shared!((Mutex::new(false), Condvar::new())),
+        };
+
         Ok(Self {
-            runtime: Runtime::new()?,
-            supervisor: Supervisor::new(sd_handle)?,
+            handle,
+            started: false,
+            supervisor,
             graceful_shutdown,
+            default_environment: None,
+            failed_tasks: shared_mut!(Vec::new()),
+            cores: HashMap::new(),
+            #[cfg(all(unix, feature = "unix-signals"))]
+            signals: Signals::default(),
         })
     }
 
-    /// Initializes the node.
+    /// Creates a new [`Node`] whose entities read the time from the given clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Result<Self> {
+        let mut node = Self::new()?;
+        node.supervisor = node.supervisor.with_clock(clock);
+
+        Ok(node)
+    }
+
+    /// Initializes the node, unless it was started already.
     pub fn init(&mut self) {
+        if !self.started {
+            self.start().expect("error starting the node");
+        }
+    }
+
+    /// Starts the node and keeps it running in the background. Environments and
+    /// entities can still be created afterwards.
+    ///
+    /// The returned handle allows to submit effects, and to shut down or wait for the
+    /// node from any thread.
+    pub fn start(&mut self) -> Result<NodeHandle> {
+        if self.started {
+            return Err(Error::App("The node has already been started."));
+        }
+
         // Spawn the Supervisor onto the runtime
-        self.runtime.spawn(self.supervisor.clone().map_err(|_| ()));
+        let supervisor = self.supervisor.clone();
+        self.spawn(String::from("supervisor"), supervisor.map_err(|_| ()))?;
+
+        // Cancel long-running cores from outside the runtime, whose workers they block
+        self.supervisor.spawn_watchdog(Arc::clone(&self.handle.watchdog_stop));
+        self.started = true;
+
+        Ok(self.handle.clone())
+    }
+
+    /// Sets the unix signals `run` reacts to besides CTRL-C.
+    #[cfg(all(unix, feature = "unix-signals"))]
+    pub fn set_signals(&mut self, signals: Signals) {
+        self.signals = signals;
     }
 
-    /// Shuts down the node on CTRL-C.
-    pub fn run(self) -> Result<()> {
+    /// Starts the node, unless it was started already, and stops it on CTRL-C.
+    pub fn run(mut self) -> Result<()> {
+        self.init();
+
         println!("Waiting for Ctrl-C...",);
 
+        #[cfg(all(unix, feature = "unix-signals"))]
+        self.graceful_shutdown
+            .wait_for_signals(self.signals.sigterm, self.signals.on_sighup.as_deref());
+
+        #[cfg(not(all(unix, feature = "unix-signals")))]
         self.graceful_shutdown.wait_for_ctrl_c();
 
         println!();
 
-        self.shutdown()
+        self.stop().map(|_| ())
+    }
+
+    /// Stops all environments and entities of the node and waits for them to finish.
+    /// The node can still be inspected afterwards, but not started again.
+    pub fn stop(&mut self) -> Result<ShutdownReport> {
+        self.handle.stop()
+    }
+
+    /// Spawns a future onto the runtime of this node. If the future panics, the panic
+    /// is logged and the task is reported as failed under the given name.
+    fn spawn<F>(&mut self, task: String, future: F) -> Result<()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let failed_tasks = Arc::clone(&self.failed_tasks);
+        let future = AssertUnwindSafe(future).catch_unwind().then(move |result| {
+            if let Err(panic) = result {
+                let message = match panic.downcast_ref::<&str>() {
+                    Some(msg) => msg.to_string(),
+                    None => match panic.downcast_ref::<String>() {
+                        Some(msg) => msg.clone(),
+                        None => String::from("unknown panic"),
+                    },
+                };
+                println!("Task {} panicked: {}", task, message);
+
+                unlock!(failed_tasks).push(TaskFailure { task, message });
+            }
+            Ok(())
+        });
+
+        match unlock!(self.handle.runtime).as_mut() {
+            Some(runtime) => {
+                runtime.spawn(future);
+                Ok(())
+            }
+            None => Err(Error::App("The node has already been stopped.")),
+        }
+    }
+
+    /// Fails if the node is shutting down, so nothing is created that would never
+    /// run.
+    fn check_running(&self) -> Result<()> {
+        if unlock!(self.handle.runtime).is_none()
+            || self.graceful_shutdown.is_shutting_down()
+        {
+            return Err(Error::App("node is shutting down"));
+        }
+
+        Ok(())
     }
 
     /// Creates an environment.
     pub fn create_environment(&mut self, name: &str) -> Result<Environment> {
+        self.check_running()?;
         let sd_handle = self.graceful_shutdown.get_listener();
         let env = self.supervisor.create_environment(name, sd_handle)?;
 
         // Spawn the Environment future onto the Tokio runtime
-        self.runtime.spawn(env.clone().map_err(|_| ()));
+        self.spawn(format!("environment '{}'", env.name()), env.clone().map_err(|_| ()))?;
+
+        Ok(env)
+    }
+
+    /// Creates an environment with the given configuration.
+    pub fn create_environment_with_config(
+        &mut self,
+        name: &str,
+        config: EnvironmentConfig,
+    ) -> Result<Environment> {
+        self.check_running()?;
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let env =
+            self.supervisor.create_environment_with_config(name, config, sd_handle)?;
+
+        // Spawn the Environment future onto the Tokio runtime
+        self.spawn(format!("environment '{}'", env.name()), env.clone().map_err(|_| ()))?;
+
+        Ok(env)
+    }
+
+    /// Creates an environment that processes effects by priority.
+    pub fn create_priority_queue_environment(
+        &mut self,
+        name: &str,
+    ) -> Result<Environment> {
+        self.check_running()?;
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let env = self.supervisor.create_priority_queue_environment(name, sd_handle)?;
+
+        // Spawn the Environment future onto the Tokio runtime
+        self.spawn(format!("environment '{}'", env.name()), env.clone().map_err(|_| ()))?;
+
+        Ok(env)
+    }
+
+    /// Creates `n` replicas of an environment, which effects submitted under the
+    /// logical name are distributed to round robin.
+    pub fn create_replicated_environment(
+        &mut self,
+        name: &str,
+        n: usize,
+    ) -> Result<Vec<Environment>> {
+        self.check_running()?;
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let replicas =
+            self.supervisor.create_replicated_environment(name, n, sd_handle)?;
+
+        // Spawn the Environment futures onto the Tokio runtime
+        for env in replicas.iter() {
+            self.spawn(
+                format!("environment '{}'", env.name()),
+                env.clone().map_err(|_| ()),
+            )?;
+        }
+
+        Ok(replicas)
+    }
+
+    /// Returns the environment with the given name, or creates it if it doesn't exist
+    /// yet.
+    pub fn ensure_environment(&mut self, name: &str) -> Result<Environment> {
+        self.check_running()?;
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let (env, created) =
+            self.supervisor.get_or_create_environment(name, sd_handle)?;
+
+        // Only spawn the Environment future if it was just created
+        if created {
+            self.spawn(
+                format!("environment '{}'", env.name()),
+                env.clone().map_err(|_| ()),
+            )?;
+        }
 
         Ok(env)
     }
 
+    /// Lets every effect submitted to the source environment also be submitted to
+    /// the destination environment.
+    pub fn clone_environment(&mut self, src: &str, dst: &str) -> Result<()> {
+        self.supervisor.clone_environment(src, dst)
+    }
+
+    /// Lets a standby environment receive a copy of every effect the environment
+    /// broadcasts.
+    pub fn mirror(&mut self, env_name: &str, target: MirrorTarget) -> Result<()> {
+        self.supervisor.mirror(env_name, target)
+    }
+
+    /// Stops mirroring an environment into a standby environment.
+    pub fn unmirror(&mut self, env_name: &str, target: &str) -> Result<()> {
+        self.supervisor.unmirror(env_name, target)
+    }
+
+    /// Returns how far each standby environment is behind the environment.
+    pub fn mirror_status(&self, env_name: &str) -> Result<Vec<MirrorStatus>> {
+        self.supervisor.mirror_status(env_name)
+    }
+
+    /// Appends a function to the transforms the environment applies to every effect
+    /// it receives.
+    pub fn add_transform<F>(&mut self, env_name: &str, transform: F) -> Result<()>
+    where
+        F: Fn(Effect) -> Effect + Send + 'static,
+    {
+        self.supervisor.add_transform(env_name, transform)
+    }
+
+    /// Removes all transforms of the environment.
+    pub fn clear_transforms(&mut self, env_name: &str) -> Result<()> {
+        self.supervisor.clear_transforms(env_name)
+    }
+
+    /// Removes orphaned entities and empty environments, and reports what was removed.
+    pub fn prune(&mut self, options: PruneOptions) -> Result<PruneReport> {
+        self.supervisor.prune(options)
+    }
+
+    /// Takes the errors the entities of this node reported so far, e.g. every time a
+    /// core failed to start.
+    pub fn take_errors(&self) -> Vec<Error> {
+        self.supervisor.take_errors()
+    }
+
+    /// Lets an alias stand for an existing environment.
+    pub fn alias(&mut self, existing: &str, alias: &str) -> Result<()> {
+        self.supervisor.alias(existing, alias)
+    }
+
+    /// Removes an alias.
+    pub fn remove_alias(&mut self, alias: &str) -> Result<()> {
+        self.supervisor.remove_alias(alias)
+    }
+
+    /// Creates a namespace without limits and returns a handle confined to it.
+    pub fn create_namespace(&mut self, name: &str) -> Result<NamespaceHandle<'_>> {
+        self.create_namespace_with_limits(name, NamespaceLimits::default())
+    }
+
+    /// Creates a namespace with the given limits and returns a handle confined to it.
+    pub fn create_namespace_with_limits(
+        &mut self,
+        name: &str,
+        limits: NamespaceLimits,
+    ) -> Result<NamespaceHandle<'_>> {
+        self.supervisor.create_namespace(name, limits)?;
+
+        Ok(NamespaceHandle { node: self, name: name.into() })
+    }
+
+    /// Returns a handle confined to an existing namespace.
+    pub fn namespace(&mut self, name: &str) -> Result<NamespaceHandle<'_>> {
+        if !self.supervisor.has_namespace(name) {
+            return Err(Error::App("There is no namespace with that name."));
+        }
+
+        Ok(NamespaceHandle { node: self, name: name.into() })
+    }
+
+    /// Lets the tenant of a namespace reach an environment outside of it.
+    pub fn grant(&mut self, namespace: &str, env_name: &str) -> Result<()> {
+        self.supervisor.grant(namespace, env_name)
+    }
+
+    /// Returns a handle to the supervisor of this node. Changes made through it affect
+    /// the node, but environments and entities created with it aren't spawned onto
+    /// the node's runtime.
+    pub fn supervisor(&self) -> Supervisor {
+        self.supervisor.clone()
+    }
+
+    /// Lets topologies refer to the cores the factory constructs by name.
+    pub fn register_core<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Result<Box<dyn Entity>> + Send + 'static,
+    {
+        self.cores.insert(name.into(), Box::new(factory));
+    }
+
+    /// Checks a topology against the registered cores and the environments of this
+    /// node without creating anything, spawning tasks or accepting effects. Cores
+    /// are only constructed and started, if the topology asks for it, and dropped
+    /// right away.
+    pub fn validate(&self, spec: &TopologySpec) -> Result<ValidationReport> {
+        Ok(topology::validate(spec, &self.cores, |env| self.has_environment(env)))
+    }
+
+    /// Returns true, if this node has an environment with the specified name.
+    pub fn has_environment(&self, name: &str) -> bool {
+        self.supervisor.has_environment(name)
+    }
+
+    /// Returns true, if this node has an entity with the specified uuid.
+    pub fn has_entity(&self, uuid: &str) -> bool {
+        self.supervisor.has_entity(uuid)
+    }
+
+    /// Returns the uuids of all entities stuck in a poll for longer than the
+    /// threshold, sorted.
+    pub fn stalled_entities(&self, threshold: Duration) -> Vec<String> {
+        self.supervisor.stalled_entities(threshold)
+    }
+
     /// Creates an entity.
     pub fn create_entity(&mut self) -> Result<EntityHost> {
+        self.check_running()?;
         let sd_handle = self.graceful_shutdown.get_listener();
         let ent = self.supervisor.create_entity(sd_handle)?;
 
         // Spawn the Entity future onto the Tokio runtime
-        self.runtime.spawn(ent.clone().map_err(|_| ()));
+        self.spawn(format!("entity '{}'", ent.uuid()), ent.clone().map_err(|_| ()))?;
+
+        Ok(ent)
+    }
+
+    /// Creates a source entity driven by the ticks of its core.
+    pub fn create_source_entity(&mut self, core: Box<dyn Entity>) -> Result<EntityHost> {
+        self.check_running()?;
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let ent = self.supervisor.create_source_entity(core, sd_handle)?;
+
+        // Spawn the Entity future onto the Tokio runtime
+        self.spawn(format!("entity '{}'", ent.uuid()), ent.clone().map_err(|_| ()))?;
+
+        Ok(ent)
+    }
+
+    /// Creates an entity running the core, wired to the environments the core
+    /// subscribes to. Missing environments it affects are created first, if
+    /// `create_affected` is set.
+    pub fn create_entity_auto(
+        &mut self,
+        core: Box<dyn Entity>,
+        create_affected: bool,
+    ) -> Result<EntityHost> {
+        self.check_running()?;
+        if create_affected {
+            for env_name in core.subscriptions().affects.iter() {
+                self.ensure_environment(env_name)?;
+            }
+        }
+        let sd_handle = self.graceful_shutdown.get_listener();
+        let ent = self.supervisor.create_entity_auto(core, sd_handle)?;
+
+        // Spawn the Entity future onto the Tokio runtime
+        self.spawn(format!("entity '{}'", ent.uuid()), ent.clone().map_err(|_| ()))?;
 
         Ok(ent)
     }
 
+    /// Returns the health of the node, i.e. which of its tasks failed so far and
+    /// which entities exceed their processing timeout right now.
+    pub fn health(&self) -> HealthReport {
+        HealthReport {
+            failed_tasks: unlock!(self.failed_tasks).clone(),
+            timed_out_entities: self.supervisor.timed_out_entities(),
+        }
+    }
+
     /// Shuts down then node.
     pub fn shutdown(mut self) -> Result<()> {
-        // Send the signal to make all infinite futures return
-        // Ok(Async::Ready(None))
-        self.graceful_shutdown.send_sig_term()?;
+        self.stop().map(|_| ())
+    }
 
-        println!("Shutting down...");
+    /// Serves the counters of all environments and entities in the Prometheus text
+    /// format over HTTP until the node is shut down. Returns the bound address.
+    #[cfg(feature = "metrics-http")]
+    pub fn serve_metrics<A: ToSocketAddrs>(&self, bind_addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(bind_addr)?;
+        let addr = listener.local_addr()?;
 
-        self.runtime.shutdown_on_idle().wait().unwrap();
+        crate::metrics::http::serve(
+            listener,
+            self.supervisor.clone(),
+            Arc::clone(&self.handle.metrics_stop),
+        );
 
-        Ok(())
+        Ok(addr)
+    }
+
+    /// Retires an entity after it processed and emitted everything broadcast to it
+    /// so far.
+    pub fn drain_entity(&mut self, uuid: &str, timeout: Duration) -> Result<()> {
+        self.supervisor.drain_entity(uuid, timeout)
     }
 
     /// Let an entity join a single or multiple environments.
@@ -96,6 +534,25 @@ impl Node {
         self.supervisor.join_environments(entity, environments)
     }
 
+    /// Let an entity join an environment as told by the options.
+    pub fn join_environments_with(
+        &mut self,
+        entity: &mut EntityHost,
+        env_name: &str,
+        options: JoinOptions,
+    ) -> Result<()> {
+        self.supervisor.join_environments_with(entity, env_name, options)
+    }
+
+    /// Let an entity leave a single or multiple environments.
+    pub fn leave_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        self.supervisor.leave_environments(entity, environments)
+    }
+
     /// Let an entity affect a single or multiple environments.
     pub fn affect_environments(
         &mut self,
@@ -105,8 +562,1488 @@ impl Node {
         self.supervisor.affect_environments(entity, environments)
     }
 
+    /// Lets an entity join environments, once they're created if they don't exist yet.
+    pub fn join_environments_deferred(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        self.supervisor.join_environments_deferred(entity, environments)
+    }
+
+    /// Lets an entity affect environments, once they're created if they don't exist
+    /// yet.
+    pub fn affect_environments_deferred(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        self.supervisor.affect_environments_deferred(entity, environments)
+    }
+
+    /// Returns the joins waiting for their environment to be created.
+    pub fn pending_joins(&self) -> Vec<(String, String)> {
+        self.supervisor.pending_joins()
+    }
+
+    /// Returns the affects waiting for their environment to be created.
+    pub fn pending_affects(&self) -> Vec<(String, String)> {
+        self.supervisor.pending_affects()
+    }
+
     /// Submit an effect
     pub fn submit_effect(&mut self, effect: Effect, env_name: &str) -> Result<()> {
         self.supervisor.submit_effect(effect, env_name)
     }
+
+    /// Submit an effect that is dropped once it's older than `max_age`
+    pub fn submit_effect_with_deadline(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        max_age: Duration,
+    ) -> Result<()> {
+        self.supervisor.submit_effect_with_deadline(effect, env_name, max_age)
+    }
+
+    /// Returns a handle submitting effects straight into the channel of an
+    /// environment, bypassing everything else `submit_effect` does.
+    pub fn raw_sender(&self, env_name: &str) -> Result<EnvironmentSender> {
+        self.supervisor.raw_sender(env_name)
+    }
+
+    /// Submits the value of an OS environment variable as an ASCII effect
+    pub fn submit_env_var(&mut self, var_name: &str, env_name: &str) -> Result<()> {
+        self.supervisor.submit_env_var(var_name, env_name)
+    }
+
+    /// Sets the environment effects are submitted to by `submit`. Fails if there is no
+    /// such environment.
+    pub fn set_default_environment(&mut self, name: &str) -> Result<()> {
+        if !self.supervisor.has_environment(name) {
+            return Err(Error::App("There is no environment with that name."));
+        }
+        self.default_environment.replace(name.into());
+
+        Ok(())
+    }
+
+    /// Submits an effect to the default environment. Fails if no default environment
+    /// was set, or if it was deleted.
+    pub fn submit(&mut self, effect: Effect) -> Result<()> {
+        let env_name = match self.default_environment.as_ref() {
+            Some(env_name) => env_name,
+            None => return Err(Error::App("No default environment was set.")),
+        };
+        if !self.supervisor.has_environment(env_name) {
+            return Err(Error::App("The default environment was deleted."));
+        }
+
+        self.supervisor.submit_effect(effect, env_name)
+    }
+
+    /// Submit an effect and block until all entities that joined the environment
+    /// received it
+    pub fn submit_and_wait(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.supervisor.submit_and_wait(effect, env_name, timeout)
+    }
+
+    /// Submit an effect and block until the environment broadcast it
+    pub fn submit_effect_sync(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.supervisor.submit_effect_sync(effect, env_name, timeout)
+    }
+
+    /// Submit effects to several environments, either all or none
+    pub fn submit_all(&mut self, effects: &[(Effect, &str)]) -> Result<()> {
+        self.supervisor.submit_all(effects)
+    }
+
+    /// Submit an effect with a key, replacing any queued effect with the same key
+    pub fn submit_keyed(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        key: &str,
+    ) -> Result<()> {
+        self.supervisor.submit_keyed(effect, env_name, key)
+    }
+
+    /// Submit an effect with a correlation id
+    pub fn submit_correlated_effect(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        correlation_id: u64,
+    ) -> Result<()> {
+        self.supervisor.submit_correlated_effect(effect, env_name, correlation_id)
+    }
+
+    /// Starts recording the hops of correlated effects.
+    pub fn enable_flow_tracing(&self) {
+        self.supervisor.enable_flow_tracing()
+    }
+
+    /// Lets submitting to an environment nobody joined fail.
+    pub fn set_strict_delivery(&mut self, strict: bool) {
+        self.supervisor.set_strict_delivery(strict)
+    }
+
+    /// Returns the number of effects flowing through this node, in total and per
+    /// second.
+    pub fn throughput(&self) -> Throughput {
+        self.supervisor.throughput()
+    }
+
+    /// Returns the recorded hops of all effects with the given correlation id.
+    pub fn trace(&self, correlation_id: u64) -> Vec<TraceStep> {
+        self.supervisor.trace(correlation_id)
+    }
+
+    /// Submit an effect with a priority
+    pub fn submit_effect_prioritized(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        priority: u8,
+    ) -> Result<()> {
+        self.supervisor.submit_effect_prioritized(effect, env_name, priority)
+    }
+}
+
+/// A handle to a node confined to the environments of a namespace.
+///
+/// Environment names passed to it are taken within the namespace, i.e. "orders" in
+/// the namespace "acme" stands for "acme.orders", unless the namespace was granted
+/// access to an environment of that full name.
+pub struct NamespaceHandle<'a> {
+    /// The node the namespace belongs to
+    node: &'a mut Node,
+
+    /// The name of the namespace
+    name: String,
+}
+
+impl NamespaceHandle<'_> {
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Creates an environment within the namespace.
+    pub fn create_environment(&mut self, name: &str) -> Result<Environment> {
+        self.create_environment_with_config(name, EnvironmentConfig::default())
+    }
+
+    /// Creates an environment with the given configuration within the namespace.
+    pub fn create_environment_with_config(
+        &mut self,
+        name: &str,
+        config: EnvironmentConfig,
+    ) -> Result<Environment> {
+        self.node.check_running()?;
+        let sd_handle = self.node.graceful_shutdown.get_listener();
+        let env = self
+            .node
+            .supervisor
+            .create_namespaced_environment(&self.name, name, config, sd_handle)?;
+
+        // Spawn the Environment future onto the Tokio runtime
+        self.node
+            .spawn(format!("environment '{}'", env.name()), env.clone().map_err(|_| ()))?;
+
+        Ok(env)
+    }
+
+    /// Creates an entity within the namespace.
+    pub fn create_entity(&mut self) -> Result<EntityHost> {
+        self.node.check_running()?;
+        let sd_handle = self.node.graceful_shutdown.get_listener();
+        let ent = self.node.supervisor.create_namespaced_entity(&self.name, sd_handle)?;
+
+        // Spawn the Entity future onto the Tokio runtime
+        self.node.spawn(format!("entity '{}'", ent.uuid()), ent.clone().map_err(|_| ()))?;
+
+        Ok(ent)
+    }
+
+    /// Let an entity of the namespace join a single or multiple environments.
+    pub fn join_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        self.node.supervisor.join_namespaced_environments(
+            &self.name,
+            entity,
+            environments,
+        )
+    }
+
+    /// Let an entity of the namespace affect a single or multiple environments.
+    pub fn affect_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        self.node.supervisor.affect_namespaced_environments(
+            &self.name,
+            entity,
+            environments,
+        )
+    }
+
+    /// Submit an effect
+    pub fn submit_effect(&mut self, effect: Effect, env_name: &str) -> Result<()> {
+        self.node.supervisor.submit_namespaced_effect(&self.name, effect, env_name)
+    }
+
+    /// Returns the counters of the namespace.
+    pub fn metrics(&self) -> NamespaceMetrics {
+        self.node
+            .supervisor
+            .namespace_metrics(&self.name)
+            .expect("a handle's namespace exists")
+    }
+}
+
+/// What a node looked like when it was stopped.
+#[derive(Clone, Debug)]
+pub struct ShutdownReport {
+    /// The counters of all environments and entities.
+    pub metrics: SupervisorMetrics,
+
+    /// How long it took for all environments and entities to finish.
+    pub duration: Duration,
+}
+
+/// The health of a running node.
+#[derive(Clone, Debug, Default)]
+pub struct HealthReport {
+    /// The tasks that panicked, in the order they did.
+    pub failed_tasks: Vec<TaskFailure>,
+
+    /// The uuids of the entities whose core exceeds its processing timeout, sorted.
+    pub timed_out_entities: Vec<String>,
+}
+
+impl HealthReport {
+    /// Returns whether none of the tasks failed and no entity is timed out.
+    pub fn is_healthy(&self) -> bool {
+        self.failed_tasks.is_empty() && self.timed_out_entities.is_empty()
+    }
+}
+
+/// A task of a node that panicked.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TaskFailure {
+    /// The task, e.g. "environment 'X'" or "entity '<uuid>'".
+    pub task: String,
+
+    /// The message the task panicked with.
+    pub message: String,
+}
+
+/// A handle to a node running in the background.
+#[derive(Clone)]
+pub struct NodeHandle {
+    /// The Tokio runtime of the node, or None if the node was shut down.
+    runtime: Arc<Mutex<Option<Runtime>>>,
+
+    /// The supervisor of the node.
+    supervisor: Supervisor,
+
+    /// Graceful shutdown of the supervisor and all started async tasks.
+    graceful_shutdown: GracefulShutdown,
+
+    /// A flag to stop watching over the processing timeouts.
+    watchdog_stop: Arc<AtomicBool>,
+
+    /// A flag to stop serving metrics.
+    #[cfg(feature = "metrics-http")]
+    metrics_stop: Arc<AtomicBool>,
+
+    /// A flag signaling that the node was shut down.
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl NodeHandle {
+    /// Submit an effect
+    pub fn submit_effect(&self, effect: Effect, env_name: &str) -> Result<()> {
+        if unlock!(self.runtime).is_none() {
+            return Err(Error::App("The node has already been shut down."));
+        }
+
+        self.supervisor.clone().submit_effect(effect, env_name)
+    }
+
+    /// Shuts down the node and wakes up all threads waiting for it.
+    pub fn shutdown(&self) -> Result<()> {
+        self.stop().map(|_| ())
+    }
+
+    /// Stops all environments and entities of the node, waits for them to finish,
+    /// and wakes up all threads waiting for the node.
+    fn stop(&self) -> Result<ShutdownReport> {
+        let runtime = match unlock!(self.runtime).take() {
+            Some(runtime) => runtime,
+            None => return Err(Error::App("The node has already been stopped.")),
+        };
+        let started = Instant::now();
+
+        // Send the signal to make all infinite futures return
+        // Ok(Async::Ready(None))
+        self.graceful_shutdown.send_sig_term()?;
+
+        // Let cores stuck in a long-running invocation bail out right away, as the
+        // supervisor may not get to run while they block the runtime
+        self.supervisor.cancel_entities();
+        self.watchdog_stop.store(true, Ordering::Relaxed);
+
+        println!("Shutting down...");
+
+        #[cfg(feature = "metrics-http")]
+        self.metrics_stop.store(true, Ordering::Relaxed);
+
+        runtime.shutdown_on_idle().wait().unwrap();
+
+        let (stopped, cvar) = &*self.stopped;
+        *unlock!(stopped) = true;
+        cvar.notify_all();
+
+        Ok(ShutdownReport {
+            metrics: self.supervisor.metrics(),
+            duration: started.elapsed(),
+        })
+    }
+
+    /// Blocks the current thread until the node was shut down.
+    pub fn join(&self) {
+        let (stopped, cvar) = &*self.stopped;
+        let mut stopped = unlock!(stopped);
+
+        while !*stopped {
+            stopped = cvar.wait(stopped).expect("error taking the lock");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::ManualClock;
+    use crate::eee::{CancellationToken, StartContext};
+
+    use std::thread;
+
+    use crossbeam_channel::{unbounded, Receiver, Sender};
+
+    /// Waits until the condition holds, or fails the test after a few seconds.
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for the condition");
+            thread::yield_now();
+        }
+    }
+
+    /// Holds on to every effect until it's woken up, while the clock moves on by the
+    /// given time.
+    struct Sleeper {
+        clock: ManualClock,
+        duration: Duration,
+        asleep: Sender<()>,
+        wake_up: Receiver<()>,
+    }
+
+    impl Sleeper {
+        /// Returns the core, a receiver telling when it fell asleep, and a sender
+        /// waking it up.
+        fn new(
+            clock: &ManualClock,
+            duration: Duration,
+        ) -> (Self, Receiver<()>, Sender<()>) {
+            let (asleep, asleep_rx) = unbounded();
+            let (wake_up_tx, wake_up) = unbounded();
+            let sleeper = Self { clock: clock.clone(), duration, asleep, wake_up };
+
+            (sleeper, asleep_rx, wake_up_tx)
+        }
+    }
+
+    impl Entity for Sleeper {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            self.clock.advance(self.duration);
+            self.asleep.send(()).unwrap();
+            self.wake_up.recv().unwrap();
+            effect
+        }
+    }
+
+    /// Passes effects on until it receives a poison pill.
+    struct Mortal;
+
+    impl Entity for Mortal {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            effect
+        }
+
+        fn should_terminate(&self, effect: &Effect) -> bool {
+            effect.as_str() == Some("poison")
+        }
+    }
+
+    #[test]
+    fn submit_to_default_environment() {
+        use crate::supervisor::PruneOptions;
+
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        assert!(node.submit(Effect::from("lost")).is_err());
+        assert!(node.set_default_environment("X").is_err());
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        a.buffer_effects(true);
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.set_default_environment(x.name()).unwrap();
+        node.submit(Effect::from("hello")).unwrap();
+        assert_eq!(Some(Effect::from("hello")), a.recv(Duration::from_secs(5)).unwrap());
+
+        // Delete the default environment once nobody joined it anymore
+        node.leave_environments(&mut a, vec![x.name()]).unwrap();
+        let options =
+            PruneOptions { empty_environments: true, ..PruneOptions::default() };
+        node.prune(options).unwrap();
+
+        assert!(!node.has_environment(x.name()));
+        assert!(node.submit(Effect::from("lost")).is_err());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn report_stalled_entities() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let (sleeper, asleep, wake_up) = Sleeper::new(&clock, Duration::from_millis(300));
+        a.inject_core(Box::new(sleeper));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let threshold = Duration::from_millis(100);
+        assert!(!a.is_stalled(threshold));
+
+        node.submit_effect(Effect::from("slow"), x.name()).unwrap();
+
+        asleep.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(a.is_stalled(threshold));
+        assert_eq!(vec![a.uuid().to_string()], node.stalled_entities(threshold));
+
+        // Once the core returns, the entity is idle rather than stalled
+        wake_up.send(()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert!(!a.is_stalled(threshold));
+        assert!(node.stalled_entities(threshold).is_empty());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn report_panicked_tasks() {
+        let mut node = Node::new().unwrap();
+        node.init();
+        assert!(node.health().is_healthy());
+
+        let task = String::from("environment 'Boom'");
+        node.spawn(task.clone(), future::poll_fn(|| -> Poll<(), ()> { panic!("boom") }))
+            .unwrap();
+
+        wait_until(|| !node.health().is_healthy());
+        let failure = TaskFailure { task, message: String::from("boom") };
+        assert_eq!(vec![failure], node.health().failed_tasks);
+
+        // Other environments keep working
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        a.buffer_effects(true);
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        assert_eq!(Some(Effect::from("hello")), a.recv(Duration::from_secs(5)).unwrap());
+        assert_eq!(1, node.health().failed_tasks.len());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn report_processing_timeouts() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let (sleeper, asleep, wake_up) = Sleeper::new(&clock, Duration::from_millis(300));
+        a.inject_core(Box::new(sleeper));
+        a.set_processing_timeout(Duration::from_millis(100));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.submit_correlated_effect(Effect::from("slow"), x.name(), 1).unwrap();
+
+        asleep.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(vec![a.uuid().to_string()], node.health().timed_out_entities);
+        wake_up.send(()).unwrap();
+
+        // The entity goes on processing once the core returns
+        node.submit_correlated_effect(Effect::from("slow"), x.name(), 2).unwrap();
+        asleep.recv_timeout(Duration::from_secs(5)).unwrap();
+        wake_up.send(()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert!(node.health().is_healthy());
+
+        let effect_ids = a
+            .take_processing_timeouts()
+            .into_iter()
+            .map(|e| match e {
+                Error::ProcessingTimeout { uuid, effect_id } => {
+                    assert_eq!(a.uuid(), uuid);
+                    effect_id
+                }
+                _ => panic!("expected a processing timeout"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Some(1), Some(2)], effect_ids);
+        assert!(a.take_processing_timeouts().is_empty());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn share_the_supervisor() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let sv = node.supervisor();
+        assert_eq!(0, sv.num_environments());
+
+        node.create_environment("X").unwrap();
+        node.create_environment("Y").unwrap();
+        assert_eq!(2, sv.num_environments());
+        assert_eq!(2, node.supervisor().num_environments());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn pull_buffered_effects() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let timeout = Duration::from_millis(100);
+        assert!(a.recv(timeout).is_err());
+
+        a.buffer_effects(true);
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        node.submit_effect(Effect::from("world"), x.name()).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Some(Effect::from("hello")), a.recv(timeout).unwrap());
+        assert_eq!(Some(Effect::from("world")), a.recv(timeout).unwrap());
+        assert_eq!(None, a.recv(Duration::from_millis(10)).unwrap());
+        assert_eq!(2, a.num_received_effects());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn observe_last_input_and_output() {
+        struct Double;
+
+        impl Entity for Double {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                match effect {
+                    Effect::U8(n) => Effect::from(n * 2),
+                    _ => Effect::Empty,
+                }
+            }
+        }
+
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        a.inject_core(Box::new(Double));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        node.submit_and_wait(Effect::from(1_u8), x.name(), timeout).unwrap();
+        assert_eq!(None, a.last_input());
+
+        a.observe(true);
+        for n in 2..=4_u8 {
+            node.submit_and_wait(Effect::from(n), x.name(), timeout).unwrap();
+        }
+        a.wait_until_idle(timeout).unwrap();
+
+        assert_eq!(Some((String::from("X"), Effect::from(4_u8))), a.last_input());
+        assert_eq!(Some(Effect::from(8_u8)), a.last_output());
+
+        a.observe(false);
+        assert_eq!(None, a.last_output());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn start_submit_and_shutdown() {
+        let mut node = Node::new().unwrap();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let handle = node.start().unwrap();
+
+        // Wait for the node on another thread
+        let joiner = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.join())
+        };
+
+        handle.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        handle.submit_effect(Effect::from("world"), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(2, x.num_received_effects());
+        assert_eq!(2, a.num_received_effects());
+
+        handle.shutdown().unwrap();
+        joiner.join().unwrap();
+
+        assert!(handle.submit_effect(Effect::from("late"), x.name()).is_err());
+        assert!(handle.shutdown().is_err());
+
+        // The node was shut down through its handle
+        assert!(node.stop().is_err());
+        assert!(node.create_entity().is_err());
+    }
+
+    #[test]
+    fn submit_and_wait_for_joined_entities() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let mut b = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+        node.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        node.submit_and_wait(Effect::from("hello"), x.name(), timeout).unwrap();
+
+        assert_eq!(1, a.num_received_effects());
+        assert_eq!(1, b.num_received_effects());
+
+        node.submit_and_wait(Effect::from("world"), x.name(), timeout).unwrap();
+
+        assert_eq!(2, a.num_received_effects());
+        assert_eq!(2, b.num_received_effects());
+
+        assert!(node.submit_and_wait(Effect::from(1_u8), "Z", timeout).is_err());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn start_and_stop_from_a_plain_thread() {
+        let mut node = Node::new().unwrap();
+        node.start().unwrap();
+        assert!(node.start().is_err());
+
+        // Creating environments and entities keeps working after the start
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        node.submit_effect(Effect::from("world"), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        let report = node.stop().unwrap();
+        assert_eq!(2, report.metrics.environments[0].num_received_effects);
+        assert_eq!(2, report.metrics.entities[0].num_received_effects);
+
+        // The stopped node can be inspected, but not started or stopped again
+        assert!(node.has_environment("X"));
+        assert_eq!(2, a.num_received_effects());
+        assert!(node.stop().is_err());
+        assert!(node.start().is_err());
+        assert!(node.create_entity().is_err());
+    }
+
+    #[test]
+    fn refuse_creating_environments_after_shutdown() {
+        let mut node = Node::new().unwrap();
+        node.init();
+        node.stop().unwrap();
+
+        match node.create_environment("X") {
+            Err(Error::App(msg)) => assert_eq!("node is shutting down", msg),
+            _ => panic!("expected an error"),
+        }
+        assert!(!node.has_environment("X"));
+    }
+
+    /// Emits an increasing counter a number of times on every tick.
+    struct Counter(u64, u64);
+
+    impl Entity for Counter {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            effect
+        }
+
+        fn on_tick(&mut self, _: Instant) -> Vec<Effect> {
+            (0..self.1)
+                .map(|_| {
+                    self.0 += 1;
+                    Effect::from(self.0)
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn tick_once_per_elapsed_interval() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let y = node.create_environment("Y").unwrap();
+        let mut a = node.create_entity().unwrap();
+        a.inject_core(Box::new(Counter(0, 1)));
+        node.affect_environments(&mut a, vec![y.name()]).unwrap();
+
+        let interval = Duration::from_secs(1);
+        a.set_tick_interval(interval);
+
+        // Nothing is due as long as the clock stands still
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(0, y.num_received_effects());
+
+        for _ in 0..25 {
+            clock.advance(interval);
+        }
+
+        wait_until(|| y.num_received_effects() >= 25);
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(25, y.num_received_effects());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn measure_throughput() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // Nothing is sampled before the supervisor runs, so the first sample is taken
+        // once all of these were broadcast
+        for i in 0..20_u8 {
+            node.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        node.init();
+        wait_until(|| node.throughput().total == 20);
+
+        for i in 0..10_u8 {
+            node.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let expected =
+            Throughput { total: 30, rate_1s: 10.0, rate_10s: 1.0, rate_60s: 10.0 / 60.0 };
+        wait_until(|| node.throughput() == expected);
+
+        // The longer windows still start at the first sample
+        for i in 0..10_u8 {
+            node.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        wait_until(|| node.throughput().total == 40);
+        let throughput = node.throughput();
+        assert_eq!(2.0, throughput.rate_10s);
+        assert_eq!(20.0 / 60.0, throughput.rate_60s);
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn source_entity_drives_downstream_entity() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let y = node.create_environment("Y").unwrap();
+        let mut source = node.create_source_entity(Box::new(Counter(0, 10))).unwrap();
+        let mut b = node.create_entity().unwrap();
+
+        node.affect_environments(&mut source, vec![y.name()]).unwrap();
+        node.join_environments(&mut b, vec![y.name()]).unwrap();
+        assert!(node.join_environments(&mut source, vec![y.name()]).is_err());
+
+        let interval = Duration::from_secs(1);
+        source.set_tick_interval(interval);
+
+        for _ in 0..3 {
+            clock.advance(interval);
+        }
+
+        wait_until(|| b.num_received_effects() >= 30);
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(30, y.num_received_effects());
+        assert_eq!(30, b.num_received_effects());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn sequenced_fan_in_is_totally_ordered() {
+        use crate::eee::Envelope;
+
+        /// Collects the sequence numbers of all effects it processes.
+        struct Collector(Arc<Mutex<Vec<u64>>>);
+
+        impl Entity for Collector {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn process_envelope(&mut self, envelope: Envelope, _: &str) -> Envelope {
+                unlock!(self.0).extend(envelope.sequence);
+                envelope
+            }
+        }
+
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let config = EnvironmentConfig::default().sequenced(true);
+        let y = node.create_environment_with_config("Y", config).unwrap();
+
+        let mut a = node.create_source_entity(Box::new(Counter(0, 500))).unwrap();
+        let mut b = node.create_source_entity(Box::new(Counter(0, 500))).unwrap();
+        node.affect_environments(&mut a, vec![y.name()]).unwrap();
+        node.affect_environments(&mut b, vec![y.name()]).unwrap();
+
+        let sequences = shared_mut!(vec![]);
+        let mut c = node.create_entity().unwrap();
+        c.inject_core(Box::new(Collector(Arc::clone(&sequences))));
+        node.join_environments(&mut c, vec![y.name()]).unwrap();
+
+        let interval = Duration::from_secs(1);
+        a.set_tick_interval(interval);
+        b.set_tick_interval(interval);
+        clock.advance(interval);
+
+        wait_until(|| unlock!(sequences).len() >= 1000);
+        c.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(1000, y.last_sequence());
+        assert_eq!((1..=1000).collect::<Vec<u64>>(), *unlock!(sequences));
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn process_effects_in_batches() {
+        use crate::eee::entity::BatchingCore;
+
+        /// Records the size of every batch it processes.
+        struct BatchRecorder(Arc<Mutex<Vec<usize>>>);
+
+        impl Entity for BatchRecorder {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn process_batch(&mut self, effects: Vec<Effect>) -> Vec<Effect> {
+                unlock!(self.0).push(effects.len());
+                effects
+            }
+        }
+
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let y = node.create_environment("Y").unwrap();
+        let mut a = node.create_entity().unwrap();
+
+        let batches = shared_mut!(vec![]);
+        let window = Duration::from_secs(1);
+        let core = BatchRecorder(Arc::clone(&batches));
+        a.inject_core(Box::new(BatchingCore::new(Box::new(core), 5, window)));
+
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+        node.affect_environments(&mut a, vec![y.name()]).unwrap();
+
+        for i in 0..13_u8 {
+            node.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        // The started batch keeps the entity busy until its time window has passed
+        wait_until(|| y.num_received_effects() >= 10);
+        assert_eq!(vec![5, 5], *unlock!(batches));
+        assert_eq!(10, y.num_received_effects());
+
+        // The remaining effects are processed once the time window has passed
+        clock.advance(window);
+
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(vec![5, 5, 3], *unlock!(batches));
+        assert_eq!(13, y.num_received_effects());
+
+        node.shutdown().unwrap();
+    }
+
+    #[cfg(all(unix, feature = "unix-signals"))]
+    #[test]
+    fn shut_down_gracefully_on_sigterm() {
+        use std::process::Command;
+        use std::sync::atomic::AtomicUsize;
+        use tokio_signal::unix::{Signal, SIGHUP, SIGTERM};
+
+        let kill = |signal: &str| {
+            let pid = std::process::id().to_string();
+            Command::new("kill").args(["-s", signal, &pid]).status().unwrap();
+        };
+
+        // Make sure the test process isn't killed by a signal sent before the node
+        // listens to it
+        Signal::new(SIGTERM).wait().unwrap();
+        Signal::new(SIGHUP).wait().unwrap();
+
+        let mut node = Node::new().unwrap();
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let reloads = shared!(AtomicUsize::new(0));
+        let counter = Arc::clone(&reloads);
+        node.set_signals(Signals::default().on_sighup(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+
+        let runner = thread::spawn(move || node.run());
+
+        // Give the node time to listen to the signals, which can't be observed
+        sleep!(200);
+        kill("HUP");
+        wait_until(|| reloads.load(Ordering::SeqCst) == 1);
+        assert!(!runner.is_finished());
+
+        kill("TERM");
+        runner.join().unwrap().unwrap();
+
+        assert_eq!(1, a.num_received_effects());
+    }
+
+    /// Loops on every effect until it's told to bail out.
+    struct Spinner {
+        token: Option<CancellationToken>,
+        spinning: Sender<()>,
+    }
+
+    impl Spinner {
+        /// Returns the core, and a receiver telling when it started spinning.
+        fn new() -> (Self, Receiver<()>) {
+            let (spinning, spinning_rx) = unbounded();
+
+            (Self { token: None, spinning }, spinning_rx)
+        }
+    }
+
+    impl Entity for Spinner {
+        fn on_start(&mut self, ctx: &StartContext) -> Result<()> {
+            self.token.replace(ctx.cancellation_token());
+            Ok(())
+        }
+
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            self.spinning.send(()).unwrap();
+
+            let token = self.token.as_ref().unwrap();
+            while !token.is_cancelled() {
+                token.wait_timeout(Duration::from_millis(10));
+            }
+            effect
+        }
+    }
+
+    #[test]
+    fn cancel_long_running_cores_on_shutdown() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let (spinner, spinning) = Spinner::new();
+        a.inject_core(Box::new(spinner));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        node.submit_effect(Effect::from("spin"), x.name()).unwrap();
+        spinning.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let report = node.stop().unwrap();
+        assert!(report.duration < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cancel_cores_exceeding_the_processing_timeout() {
+        let clock = ManualClock::new();
+        let mut node = Node::with_clock(shared!(clock.clone())).unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let (spinner, spinning) = Spinner::new();
+        a.inject_core(Box::new(spinner));
+        a.set_processing_timeout(Duration::from_millis(100));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // Each invocation is cancelled once it times out, and the next one gets to
+        // run until it times out as well
+        node.submit_effect(Effect::from("spin"), x.name()).unwrap();
+        node.submit_effect(Effect::from("spin"), x.name()).unwrap();
+
+        for _ in 0..2 {
+            spinning.recv_timeout(Duration::from_secs(5)).unwrap();
+            clock.advance(Duration::from_millis(200));
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(2, a.take_processing_timeouts().len());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn terminate_entity_on_poison_pill() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let y = node.create_environment("Y").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let mut b = node.create_entity().unwrap();
+        a.inject_core(Box::new(Mortal));
+        b.buffer_effects(true);
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+        node.affect_environments(&mut a, vec![y.name()]).unwrap();
+        node.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        node.submit_effect(Effect::from("poison"), x.name()).unwrap();
+
+        wait_until(|| !node.has_entity(a.uuid()));
+        assert!(a.is_terminated());
+        assert_eq!(0, a.num_joined());
+        assert!(!x.joined_entities().contains(&a.uuid().to_string()));
+
+        // Only what came before the poison pill was emitted
+        assert_eq!(Some(Effect::from("hello")), b.recv(Duration::from_secs(5)).unwrap());
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(None, b.recv(Duration::from_millis(0)).unwrap());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn report_and_restart_cores_that_fail_to_start() {
+        use crate::eee::RestartPolicy;
+
+        /// Fails to start the given number of times.
+        struct Flaky(usize);
+
+        impl Entity for Flaky {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn on_start(&mut self, _: &StartContext) -> Result<()> {
+                if self.0 == 0 {
+                    return Ok(());
+                }
+                self.0 -= 1;
+                Err(Error::App("not ready"))
+            }
+        }
+
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let mut b = node.create_entity().unwrap();
+        a.set_restart_policy(RestartPolicy::Retry(2));
+        a.inject_core(Box::new(Flaky(2)));
+        b.inject_core(Box::new(Flaky(1)));
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+        node.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        // Without a restart policy the entity ends on the first failure
+        wait_until(|| !node.has_entity(b.uuid()));
+        assert!(b.take_start_error().is_some());
+        assert_eq!(1, b.num_failed_starts());
+
+        // The other one is started again until it succeeds
+        let timeout = Duration::from_secs(5);
+        node.submit_and_wait(Effect::from(1_u8), x.name(), timeout).unwrap();
+        assert!(a.take_start_error().is_none());
+        assert_eq!(2, a.num_failed_starts());
+        assert!(node.has_entity(a.uuid()));
+
+        let errors = node.take_errors();
+        assert_eq!(3, errors.len());
+        assert!(errors.iter().all(|e| matches!(e, Error::StartFailed { .. })));
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn flush_batch_on_poison_pill() {
+        use crate::eee::entity::BatchingCore;
+
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let x = node.create_environment("X").unwrap();
+        let y = node.create_environment("Y").unwrap();
+        let mut a = node.create_entity().unwrap();
+        let mut b = node.create_entity().unwrap();
+        let window = Duration::from_secs(60);
+        a.inject_core(Box::new(BatchingCore::new(Box::new(Mortal), 10, window)));
+        b.buffer_effects(true);
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+        node.affect_environments(&mut a, vec![y.name()]).unwrap();
+        node.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        node.submit_effect(Effect::from("world"), x.name()).unwrap();
+        node.submit_effect(Effect::from("poison"), x.name()).unwrap();
+
+        wait_until(|| !node.has_entity(a.uuid()));
+
+        // The wrapped core saw the poison pill, and the started batch wasn't lost
+        assert_eq!(Some(Effect::from("hello")), b.recv(Duration::from_secs(5)).unwrap());
+        assert_eq!(Some(Effect::from("world")), b.recv(Duration::from_secs(5)).unwrap());
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(None, b.recv(Duration::from_millis(0)).unwrap());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn isolate_namespaces() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        let (mut a, orders_a) = {
+            let mut acme = node.create_namespace("acme").unwrap();
+            let orders = acme.create_environment("orders").unwrap();
+            let mut a = acme.create_entity().unwrap();
+            a.buffer_effects(true);
+            acme.join_environments(&mut a, vec!["orders"]).unwrap();
+            (a, orders)
+        };
+        let (mut b, orders_b) = {
+            let mut umbrella = node.create_namespace("umbrella").unwrap();
+            let orders = umbrella.create_environment("orders").unwrap();
+            let mut b = umbrella.create_entity().unwrap();
+            b.buffer_effects(true);
+            umbrella.join_environments(&mut b, vec!["orders"]).unwrap();
+            (b, orders)
+        };
+        assert_eq!("acme.orders", orders_a.name());
+        assert_eq!("umbrella.orders", orders_b.name());
+
+        let mut acme = node.namespace("acme").unwrap();
+        acme.submit_effect(Effect::from("mine"), "orders").unwrap();
+
+        // Neither the environments nor the entities of another tenant are reachable
+        assert!(acme.submit_effect(Effect::from("theirs"), "umbrella.orders").is_err());
+        assert!(acme.join_environments(&mut a, vec!["umbrella.orders"]).is_err());
+        assert!(acme.join_environments(&mut b, vec!["orders"]).is_err());
+
+        assert_eq!(Some(Effect::from("mine")), a.recv(Duration::from_secs(5)).unwrap());
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(0, b.num_received_effects());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn confine_namespaces_to_the_environments_created_in_them() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        // Sharing the prefix doesn't put an environment into the namespace
+        node.create_environment("acme.secrets").unwrap();
+
+        let mut acme = node.create_namespace("acme").unwrap();
+        let config = EnvironmentConfig::default().sequenced(true);
+        let orders = acme.create_environment_with_config("orders", config).unwrap();
+        assert_eq!("acme.orders", orders.name());
+        assert!(orders.info().sequenced);
+
+        let mut a = acme.create_entity().unwrap();
+        assert!(acme.submit_effect(Effect::from("leak"), "secrets").is_err());
+        assert!(acme.join_environments(&mut a, vec!["secrets"]).is_err());
+        acme.join_environments(&mut a, vec!["orders"]).unwrap();
+
+        let metrics = acme.metrics();
+        let environments =
+            metrics.environments.iter().map(|env| env.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["acme.orders"], environments);
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn grant_access_across_namespaces() {
+        let mut node = Node::new().unwrap();
+        node.init();
+
+        node.create_namespace("umbrella").unwrap().create_environment("prices").unwrap();
+
+        let mut a = {
+            let mut acme = node.create_namespace("acme").unwrap();
+            let mut a = acme.create_entity().unwrap();
+            a.buffer_effects(true);
+            assert!(acme.join_environments(&mut a, vec!["umbrella.prices"]).is_err());
+            a
+        };
+
+        node.grant("acme", "umbrella.prices").unwrap();
+
+        let mut acme = node.namespace("acme").unwrap();
+        acme.join_environments(&mut a, vec!["umbrella.prices"]).unwrap();
+        node.namespace("umbrella")
+            .unwrap()
+            .submit_effect(Effect::from(1_u8), "prices")
+            .unwrap();
+
+        assert_eq!(Some(Effect::from(1_u8)), a.recv(Duration::from_secs(5)).unwrap());
+
+        node.shutdown().unwrap();
+    }
+
+    #[test]
+    fn segregate_namespace_metrics_and_limits() {
+        let mut node = Node::new().unwrap();
+        node.init();
+        let default = node.create_environment("orders").unwrap();
+
+        let limits = NamespaceLimits {
+            max_environments: Some(1),
+            max_effects_per_sec: Some(2),
+        };
+        let mut acme = node.create_namespace_with_limits("acme", limits).unwrap();
+        acme.create_environment("orders").unwrap();
+        assert!(acme.create_environment("invoices").is_err());
+        let a = acme.create_entity().unwrap();
+
+        for i in 0..3_u8 {
+            let submitted = acme.submit_effect(Effect::from(i), "orders");
+            assert_eq!(i < 2, submitted.is_ok());
+        }
+
+        let metrics = acme.metrics();
+        assert_eq!("acme", metrics.name);
+        let environments =
+            metrics.environments.iter().map(|env| env.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["acme.orders"], environments);
+        let entities =
+            metrics.entities.iter().map(|ent| ent.uuid.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec![a.uuid()], entities);
+        assert_eq!(2, metrics.num_submitted_effects);
+        assert_eq!(1, metrics.num_throttled_effects);
+
+        let umbrella = node.create_namespace("umbrella").unwrap();
+        let metrics = umbrella.metrics();
+        assert!(metrics.environments.is_empty());
+        assert!(metrics.entities.is_empty());
+        assert_eq!(0, metrics.num_submitted_effects);
+
+        // The default namespace isn't limited
+        for i in 0..3_u8 {
+            node.submit_effect(Effect::from(i), default.name()).unwrap();
+        }
+
+        node.shutdown().unwrap();
+    }
+
+    /// Refuses to start.
+    struct Grumpy;
+
+    impl Entity for Grumpy {
+        fn on_start(&mut self, _: &StartContext) -> Result<()> {
+            Err(Error::App("not today"))
+        }
+
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            effect
+        }
+    }
+
+    #[test]
+    fn validate_topologies_without_building_them() {
+        use crate::topology::{Diagnostic, EntitySpec};
+
+        let mut node = Node::new().unwrap();
+        node.create_environment("Existing").unwrap();
+        node.register_core("spin", || Ok(Box::new(Spinner::new().0)));
+        node.register_core("grumpy", || Ok(Box::new(Grumpy)));
+        node.register_core("broken", || Err(Error::App("missing config")));
+
+        let entity =
+            |name: &str, core: &str, joins: &[&str], affects: &[&str]| EntitySpec {
+                name: name.into(),
+                core: core.into(),
+                joins: joins.iter().map(|&env| env.into()).collect(),
+                affects: affects.iter().map(|&env| env.into()).collect(),
+            };
+
+        let mut spec = TopologySpec {
+            environments: ["X", "Y", "X", "Existing", "Z"]
+                .iter()
+                .map(|&env| env.into())
+                .collect(),
+            entities: vec![
+                entity("a", "spin", &["X"], &["Y"]),
+                entity("b", "spin", &["Y"], &["X"]),
+                entity("c", "unknown", &["Nowhere"], &["Void"]),
+                entity("a", "spin", &["Existing"], &[]),
+                entity("d", "broken", &[], &["X"]),
+                entity("e", "grumpy", &[], &["Y"]),
+            ],
+            max_environments: Some(3),
+            max_entities: Some(5),
+            start_cores: true,
+            ..TopologySpec::default()
+        };
+
+        let report = node.validate(&spec).unwrap();
+        assert!(!report.is_ok());
+
+        let expected = vec![
+            Diagnostic::DuplicateEnvironment("X".into()),
+            Diagnostic::DuplicateEnvironment("Existing".into()),
+            Diagnostic::DuplicateEntity("a".into()),
+            Diagnostic::TooManyEnvironments { count: 4, max: 3 },
+            Diagnostic::TooManyEntities { count: 6, max: 5 },
+            Diagnostic::UnknownCore { entity: "c".into(), core: "unknown".into() },
+            Diagnostic::DanglingJoin {
+                entity: "c".into(),
+                environment: "Nowhere".into(),
+            },
+            Diagnostic::DanglingAffect { entity: "c".into(), environment: "Void".into() },
+            Diagnostic::Cycle(vec!["X".into(), "Y".into(), "X".into()]),
+        ];
+        for diagnostic in expected.iter() {
+            assert!(report.errors.contains(diagnostic), "missing: {}", diagnostic);
+        }
+
+        let failed = |name: &str| {
+            report.errors.iter().any(|diagnostic| match diagnostic {
+                Diagnostic::CoreFailed { entity, .. } => entity == name,
+                _ => false,
+            })
+        };
+        assert!(failed("d"));
+        assert!(failed("e"));
+        assert!(!failed("a"));
+        assert_eq!(vec![Diagnostic::NoConsumers("Z".into())], report.warnings);
+
+        // Nothing was built
+        assert!(!node.has_environment("X"));
+        assert_eq!(0, node.supervisor().num_entities());
+
+        // Cycles can be allowed, and cores are only started if asked for
+        spec.environments = vec!["X".into(), "Y".into()];
+        spec.entities = vec![
+            entity("a", "spin", &["X"], &["Y"]),
+            entity("b", "broken", &["Y"], &["X"]),
+        ];
+        spec.allow_cycles = true;
+        spec.start_cores = false;
+        assert_eq!(ValidationReport::default(), node.validate(&spec).unwrap());
+    }
+
+    #[cfg(feature = "metrics-http")]
+    #[test]
+    fn serve_prometheus_metrics() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let mut node = Node::new().unwrap();
+
+        let x = node.create_environment("X").unwrap();
+        let mut a = node.create_entity().unwrap();
+        node.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        for i in 0..3_u8 {
+            node.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        let addr = node.serve_metrics("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("reee_env_received_total{env=\"X\"} 3\n"));
+        assert!(response.contains("reee_env_queued_effects{env=\"X\"} 0\n"));
+        assert!(response.contains(&format!(
+            "reee_entity_received_total{{entity=\"{}\"}} 3\n",
+            a.uuid()
+        )));
+
+        node.shutdown().unwrap();
+    }
 }
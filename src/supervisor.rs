@@ -1,45 +1,77 @@
 //! Supervisor module.
 
+use crate::clock::{Clock, SystemClock};
 use crate::common::trigger::TriggerHandle;
 use crate::common::watcher::Watcher;
+use crate::constants::TIMER_RESOLUTION_MS;
+use crate::eee::environment::{
+    EnvironmentConfig, KeyedQueue, Mirror, MirrorStatus, PriorityQueue,
+};
+use crate::eee::CoreSubscriptions;
 use crate::eee::Effect;
+use crate::eee::Entity;
 use crate::eee::EntityHost;
+use crate::eee::Envelope;
 use crate::eee::Environment;
 use crate::errors::{Error, Result};
+use crate::metrics::{
+    EntityMetrics, EnvironmentMetrics, NamespaceMetrics, RuntimeStats, SupervisorMetrics,
+    Throughput, ThroughputMeter,
+};
+use crate::trace::{FlowTracer, TraceStep};
 
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{unbounded, Sender};
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use tokio::prelude::*;
+use tokio::timer::Interval;
 
 /// Registry for Environments.
 ///
 /// # Example
 /// ```
+/// use reee::eee::Effect;
 /// use reee::supervisor::Supervisor;
+/// use reee::GracefulShutdown;
+/// use tokio::prelude::*;
+/// use tokio::runtime::Runtime;
+///
+/// let sd = GracefulShutdown::new();
+/// let mut runtime = Runtime::new().unwrap();
 ///
 /// // Create a supervisor
-/// let mut sv = Supervisor::new().unwrap();
+/// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
 ///
 /// // Create two environments X, Y
-/// let x = sv.create_environment("X").unwrap();
-/// let y = sv.create_environment("Y").unwrap();
+/// let x = sv.create_environment("X", sd.get_listener()).unwrap();
+/// let y = sv.create_environment("Y", sd.get_listener()).unwrap();
 ///
 /// // Create two entities
-/// let mut a = sv.create_entity().unwrap();
-/// let mut b = sv.create_entity().unwrap();
+/// let mut a = sv.create_entity(sd.get_listener()).unwrap();
+/// let mut b = sv.create_entity(sd.get_listener()).unwrap();
 ///
 /// // Let them join environments
-/// sv.join_environments(&mut a, vec![&x.name()]).unwrap();
-/// sv.join_environments(&mut b, vec![&x.name(), &y.name()]).unwrap();
+/// sv.join_environments(&mut a, vec![x.name()]).unwrap();
+/// sv.join_environments(&mut b, vec![x.name(), y.name()]).unwrap();
+///
+/// runtime.spawn(x.clone().map_err(|_| ()));
+/// runtime.spawn(y.clone().map_err(|_| ()));
+/// runtime.spawn(a.clone().map_err(|_| ()));
+/// runtime.spawn(b.clone().map_err(|_| ()));
 ///
-/// // Submit two effects to each environment
-/// sv.submit_effect("hello", "X").unwrap();
-/// sv.submit_effect("world", "Y").unwrap();
+/// // Submit an effect to each environment
+/// sv.submit_effect(Effect::from("hello"), "X").unwrap();
+/// sv.submit_effect(Effect::from("world"), "Y").unwrap();
 ///
-/// // Wait a little for effects to propagate
-/// std::thread::sleep(std::time::Duration::from_millis(500));
+/// // Wait for the entities to process the effects. Joining takes effect right
+/// // away, so both entities receive everything submitted afterwards.
+/// a.wait_until_idle(std::time::Duration::from_secs(5)).unwrap();
+/// b.wait_until_idle(std::time::Duration::from_secs(5)).unwrap();
 ///
 /// assert_eq!(1, x.num_received_effects());
 /// assert_eq!(1, y.num_received_effects());
@@ -50,6 +82,13 @@ pub struct Supervisor {
     inner: Arc<Mutex<Inner>>,
 }
 
+/// The state shared by all clones of a supervisor.
+///
+/// Locks are always taken in this order: the supervisor state, then the mutexes of
+/// an environment, then those of an entity. Topology changes hold the supervisor
+/// state throughout, so they never run concurrently. An entity never locks an
+/// environment while holding one of its own mutexes, it only reaches its
+/// environments through broadcast receivers, atomics and wakers.
 struct Inner {
     /// Environments managed by the supervisor
     environments: HashMap<String, EnvironmentConnection>,
@@ -59,403 +98,5011 @@ struct Inner {
 
     /// A listener for supervisor shutdown
     shutdown_listener: TriggerHandle,
-    /* A notfier for waking up the supervisor's task/future
-     *waker: Watcher, */
-}
 
-impl Clone for Supervisor {
-    fn clone(&self) -> Self {
-        Self {
-            inner: Arc::clone(&self.inner),
-        }
-    }
-}
+    /// The clock handed to all entities
+    clock: Arc<dyn Clock>,
 
-/// Connection between the supervisor and an environment.
-pub(crate) struct EnvironmentConnection {
-    /// Sender half of the channel between supervisor and environment
-    pub sender: Sender<Effect>,
+    /// A timer that periodically wakes up ticking entities
+    timer: Interval,
 
-    /// The environment that is linked to the supervisor
-    pub environment: Environment,
+    /// Records the hops of correlated effects
+    tracer: Arc<FlowTracer>,
 
-    /// A notfier for waking up the environment task/future
-    pub waker: Watcher,
-}
+    /// Whether submitting to an environment nobody joined is an error
+    strict_delivery: bool,
 
-/// Connection between the supervisor and an entity.
-pub(crate) struct EntityConnection {
-    /// An entity.
-    pub entity: EntityHost,
+    /// The number of effects broadcast by all environments
+    num_broadcast: Arc<AtomicU64>,
+
+    /// Computes the throughput of all environments on every timer tick
+    throughput: ThroughputMeter,
+
+    /// The id of the last submission waiting for its effect to be broadcast
+    last_submission: u64,
+
+    /// The names of the environments alternative names stand for, keyed by alias
+    aliases: HashMap<String, String>,
+
+    /// A supervisor that gets a copy of every effect submitted to an environment
+    standby: Standby,
+
+    /// Joins and affects declared for environments that don't exist yet
+    pending_edges: Vec<PendingEdge>,
+
+    /// The replicas effects submitted under a logical name are distributed to
+    replicas: HashMap<String, Replicas>,
+
+    /// The namespaces tenants are confined to, keyed by name
+    namespaces: HashMap<String, Namespace>,
+
+    /// The channel entities report errors through
+    errors: (Sender<Error>, Receiver<Error>),
+    /* A notfier for waking up the supervisor's task/future
+     *waker: Watcher, */
 }
 
-impl Supervisor {
-    /// Creates a new supervisor.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let sv = Supervisor::new().unwrap();
-    /// ```
-    pub fn new(shutdown_listener: TriggerHandle) -> Result<Self> {
-        let inner = Arc::new(Mutex::new(Inner {
-            environments: HashMap::new(),
-            entities: HashMap::new(),
-            shutdown_listener,
-        }));
+impl Inner {
+    /// Submits an effect to an environment and its clones.
+    fn submit_envelope(&self, envelope: Envelope, env_name: &str) -> Result<()> {
+        match self.environments.get(self.route(env_name)) {
+            Some(env_link) => {
+                self.check_listeners(env_link)?;
+                self.submit_to_clones(env_link, &envelope, 0)?;
+                env_link.send(envelope, 0)
+            }
+            None => Err(Error::App("No environment with this name available")),
+        }
+    }
 
-        Ok(Self {
-            inner,
-        })
+    /// Submits a copy of an effect submitted to another supervisor, which isn't
+    /// mirrored again.
+    fn submit_mirrored(&self, copy: Mirrored) -> Result<()> {
+        let Mirrored { envelope, env_name, priority, key } = copy;
+
+        match self.environments.get(self.route(&env_name)) {
+            Some(env_link) => {
+                self.check_listeners(env_link)?;
+                self.submit_to_clones(env_link, &envelope, priority)?;
+                match env_link.keyed_queue.as_ref() {
+                    Some(queue) => env_link.send_keyed(envelope, key.as_deref(), queue),
+                    None => env_link.send(envelope, priority),
+                }
+            }
+            None => Err(Error::App("No environment with this name available")),
+        }
     }
 
-    /// Creates a new environment.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    ///
-    /// sv.create_environment("X").unwrap();
-    /// ```
-    pub fn create_environment(
+    /// Creates a new environment and links it to the supervisor.
+    fn insert_environment(
         &mut self,
         name: &str,
         sd_handle: TriggerHandle,
-    ) -> Result<Environment> {
-        let mut inner = unlock!(self.inner);
-
-        if inner.environments.contains_key(name) {
-            return Err(Error::App("Environment with that name already exists."));
-        }
-
+        priority_queue: Option<Arc<Mutex<PriorityQueue>>>,
+        config: EnvironmentConfig,
+    ) -> Environment {
         // Create a communication channel between the supervisor and the new
         // environment.
         let (sender, receiver) = unbounded();
 
+        // Only keep the latest effect per key, if the environment coalesces by key
+        let keyed_queue = match config.is_keyed() {
+            true => Some(shared_mut!(KeyedQueue::default())),
+            false => None,
+        };
+
         // Create a new environment which gets the receiving end of the channel
-        let env = Environment::new(name, receiver, sd_handle);
+        let mut env = Environment::new(name, receiver, sd_handle)
+            .with_config(config)
+            .with_clock(Arc::clone(&self.clock))
+            .with_flow_tracer(Arc::clone(&self.tracer))
+            .with_node_throughput(Arc::clone(&self.num_broadcast));
+
+        // Let the environment receive effects by priority instead
+        if let Some(queue) = priority_queue.as_ref() {
+            env = env.with_priority_queue(Arc::clone(queue));
+        }
+        if let Some(queue) = keyed_queue.as_ref() {
+            env = env.with_keyed_queue(Arc::clone(queue));
+        }
 
         // Create a link between the supervisor and the new environment through
         // which the supervisor will send messages to the environment.
         let conn = EnvironmentConnection {
             sender,
+            priority_queue,
+            keyed_queue,
             environment: env.clone(),
             waker: env.get_waker(),
+            clones: vec![],
+            created: self.clock.now(),
+            alive: Arc::new(()),
         };
 
         // Store the link
-        inner.environments.insert(name.into(), conn);
+        self.environments.insert(name.into(), conn);
 
-        Ok(env)
+        // Complete the joins and affects declared before the environment existed
+        let (due, pending) =
+            self.pending_edges.drain(..).partition(|edge| edge.env_name == name);
+        self.pending_edges = pending;
+
+        for PendingEdge { mut entity, edge, .. } in due {
+            let registered = match edge {
+                Edge::Join => env.register_joining_entity(&mut entity),
+                Edge::Affect => env.register_affecting_entity(&mut entity),
+            };
+            if let Err(e) = registered {
+                println!(
+                    "Ent. {} failed to connect to environment '{}': {:?}",
+                    &entity.uuid()[0..5],
+                    name,
+                    e
+                );
+            }
+        }
+
+        env
     }
 
-    /// Delete an environment.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    ///
-    /// let x = sv.create_environment("X").unwrap();
-    ///
-    /// sv.delete_environment(&x.name()).unwrap();
-    /// ```
-    pub fn delete_environment(&mut self, env_name: &str) -> Result<()> {
-        let mut inner = unlock!(self.inner);
-        match inner.environments.remove(env_name) {
-            Some(env_conn) => {
-                // Inform subscribed entities that this environment is going to be dropped
-                env_conn.environment.send_sig_term()?;
+    /// Connects an entity to an environment right away, if it exists, or once it's
+    /// created otherwise.
+    fn connect_deferred(
+        &mut self,
+        entity: &mut EntityHost,
+        env_name: &str,
+        edge: Edge,
+    ) -> Result<()> {
+        let env_name = self.resolve(env_name).to_string();
+
+        match self.environments.get_mut(&env_name) {
+            Some(conn) => match edge {
+                Edge::Join => conn.environment.register_joining_entity(entity),
+                Edge::Affect => conn.environment.register_affecting_entity(entity),
+            },
+            None => {
+                let edge = PendingEdge { entity: entity.clone(), env_name, edge };
+                if !self.pending_edges.contains(&edge) {
+                    self.pending_edges.push(edge);
+                }
                 Ok(())
             }
-            None => Err(Error::App(
-                "There is no environment with that name managed by this supervisor.",
-            )),
         }
     }
 
-    /// Create an entity.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    ///
-    /// sv.create_entity().unwrap();
-    /// ```
-    pub fn create_entity(&mut self, sd_handle: TriggerHandle) -> Result<EntityHost> {
-        let mut inner = unlock!(self.inner);
-        let entity = EntityHost::new(sd_handle);
+    /// Returns the uuids of the entities and the names of the environments they wait
+    /// for, of all pending edges of the given kind, sorted.
+    fn pending(&self, edge: Edge) -> Vec<(String, String)> {
+        let mut pending = self
+            .pending_edges
+            .iter()
+            .filter(|pending| pending.edge == edge)
+            .map(|pending| (pending.entity.uuid().to_string(), pending.env_name.clone()))
+            .collect::<Vec<_>>();
+        pending.sort();
+
+        pending
+    }
+
+    /// Creates a new entity and links it to the supervisor.
+    fn insert_entity(&mut self, entity: EntityHost) -> EntityHost {
+        let entity = entity
+            .with_clock(Arc::clone(&self.clock))
+            .with_flow_tracer(Arc::clone(&self.tracer));
+        entity.report_errors_to(self.errors.0.clone());
 
         // Store the entity
-        inner.entities
-            .insert(entity.uuid().into(), EntityConnection { entity: entity.clone() });
+        let conn = EntityConnection { entity: entity.clone(), created: self.clock.now() };
+        self.entities.insert(entity.uuid().into(), conn);
 
-        Ok(entity)
+        entity
     }
 
-    /// Delete an entity.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    /// let mut a = sv.create_entity().unwrap();
-    ///
-    /// sv.delete_entity(a.uuid()).unwrap();
-    /// ```
-    pub fn delete_entity(&mut self, uuid: &str) -> Result<()> {
-        let mut inner = unlock!(self.inner);
-        match inner.entities.remove(uuid) {
-            Some(ent_conn) => {
-                // Unsubscribe from all environments the entity has joined and
-                ent_conn.entity.send_sig_term()?;
-                Ok(())
-            }
-            None => Err(Error::App(
-                "There is no entity with that uuid managed by this supervisor.",
-            )),
+    /// Unlinks an entity from the supervisor and lets all environments forget about
+    /// it right away.
+    fn remove_entity(&mut self, uuid: &str) -> Option<EntityConnection> {
+        let ent_conn = self.entities.remove(uuid)?;
+
+        for conn in self.environments.values() {
+            conn.environment.forget_entity(uuid);
+        }
+        self.pending_edges.retain(|edge| edge.entity.uuid() != uuid);
+        for ns in self.namespaces.values_mut() {
+            ns.entities.retain(|ent| ent != uuid);
         }
+
+        Some(ent_conn)
     }
 
-    /// Lets the specified entity join one or multiple environments.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    /// let x = sv.create_environment("X").unwrap();
-    /// let mut a = sv.create_entity().unwrap();
-    ///
-    /// sv.join_environments(&mut a, vec![&x.name()]).unwrap();
-    /// ```
-    pub fn join_environments(
-        &mut self,
-        mut entity: &mut EntityHost,
-        environments: Vec<&str>,
-    ) -> Result<()> {
-        let mut inner = unlock!(self.inner);
-        // Check, if all given environments are known to this supervisor
-        if !environments.iter().all(|env_name| inner.environments.contains_key(*env_name))
+    /// Fails in strict delivery mode, if nobody joined the environment, unless it
+    /// holds back effects for the first entity.
+    fn check_listeners(&self, env_link: &EnvironmentConnection) -> Result<()> {
+        let environment = &env_link.environment;
+        if self.strict_delivery
+            && environment.num_joined_entities() == 0
+            && !environment.is_awaiting_first_join()
         {
-            return Err(Error::App(
-                "At least one of the specified environments is unknown to this supervisor.",
-            ));
-        }
-
-        // Let the entity join all specified environments
-        for env_name in environments.iter() {
-            let conn = inner.environments.get_mut(*env_name).unwrap();
-            conn.environment.register_joining_entity(&mut entity)?;
+            return Err(Error::App("no listeners"));
         }
 
         Ok(())
     }
 
-    /// Lets the specified entity leave one or multiple environments.
-    pub fn leave_environments(
-        &mut self,
-        mut _host: &mut EntityHost,
-        _environments: Vec<&str>,
-    ) {
-        //
+    /// Returns the name of the environment an alias stands for, or the name itself
+    /// if it isn't an alias.
+    fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map(String::as_str).unwrap_or(name)
     }
 
-    /// Lets the specified entity affect one or multiple environments.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    /// let x = sv.create_environment("X").unwrap();
-    /// let mut a = sv.create_entity().unwrap();
-    ///
-    /// sv.affect_environments(&mut a, vec![&x.name()]).unwrap();
-    /// ```
-    pub fn affect_environments(
-        &mut self,
-        entity: &mut EntityHost,
-        environments: Vec<&str>,
-    ) -> Result<()> {
-        let mut inner = unlock!(self.inner);
-        // Check, if all given environments are known to this supervisor
-        if !environments.iter().all(|env_name| inner.environments.contains_key(*env_name))
-        {
-            return Err(Error::App(
-                "At least one of the specified environments is unknown to this supervisor.",
-            ));
+    /// Returns the name of the environment to submit an effect to: the next replica
+    /// in turn for a logical name of replicas, or else the resolved name.
+    fn route<'a>(&'a self, name: &'a str) -> &'a str {
+        let route = self.route_nth(name, 0);
+        self.advance_route(name, 1);
+
+        route
+    }
+
+    /// Returns the name of the environment the `nth` effect from now submitted under
+    /// a name goes to, without picking a replica yet.
+    fn route_nth<'a>(&'a self, name: &'a str, nth: usize) -> &'a str {
+        match self.replicas.get(name) {
+            Some(replicas) => {
+                let next = replicas.next.load(Ordering::Relaxed) + nth;
+                &replicas.names[next % replicas.names.len()]
+            }
+            None => self.resolve(name),
         }
+    }
 
-        // Let the entity affect all specified environments
-        for env_name in environments.iter() {
-            let conn = inner.environments.get_mut(*env_name).unwrap();
-            conn.environment.register_affecting_entity(entity)?;
+    /// Lets the next `n` replicas in turn take an effect, if the name stands for
+    /// replicas.
+    fn advance_route(&self, name: &str, n: usize) {
+        if let Some(replicas) = self.replicas.get(name) {
+            replicas.next.fetch_add(n, Ordering::Relaxed);
         }
+    }
 
-        Ok(())
+    /// Returns the name of the environment a tenant means: the name itself if the
+    /// namespace was granted access to it, or else the name within the namespace.
+    /// Fails if the namespace neither created nor was granted that environment.
+    fn qualify(&self, namespace: &str, name: &str) -> Result<String> {
+        let ns = match self.namespaces.get(namespace) {
+            Some(ns) => ns,
+            None => return Err(Error::App("There is no namespace with that name.")),
+        };
+
+        if ns.grants.iter().any(|grant| grant == name) {
+            return Ok(name.into());
+        }
+
+        let name = format!("{}.{}", namespace, name);
+        match ns.environments.contains(&name) {
+            true => Ok(name),
+            false => Err(Error::App("The namespace has no environment with that name.")),
+        }
     }
 
-    /*
-    pub fn stop_affecting_environments(
-        &mut self,
-        entity: &mut Entity,
-        environments: Vec<&str>,
-    ) -> Result<(), Error> {
-        //
+    /// Fails if the entity wasn't created in the namespace.
+    fn check_owner(&self, namespace: &str, entity: &EntityHost) -> Result<()> {
+        match self.namespaces.get(namespace) {
+            Some(ns) if ns.entities.iter().any(|uuid| uuid == entity.uuid()) => Ok(()),
+            Some(_) => Err(Error::App("The entity belongs to another namespace.")),
+            None => Err(Error::App("There is no namespace with that name.")),
+        }
     }
-    */
 
-    /// Submit an effect to an enviroment.
-    ///
-    /// # Example
-    /// ```
-    /// use reee::supervisor::Supervisor;
-    ///
-    /// let mut sv = Supervisor::new().unwrap();
-    /// let x = sv.create_environment("X").unwrap();
-    ///
-    /// sv.submit_effect("hello", &x.name()).unwrap();
-    /// ```
-    pub fn submit_effect(&mut self, effect: Effect, env_name: &str) -> Result<()> {
-        let inner = unlock!(self.inner);
-        match inner.environments.get(env_name) {
-            Some(env_link) => {
-                match env_link.sender.send(effect) {
-                    Err(_) => {
-                        return Err(Error::App(
-                            "Error sending the message to the environment",
-                        ))
-                    }
-                    _ => (),
-                }
-                // Notify the task associated with this environment to wake up
-                // and do some work
-                env_link.waker.task.notify();
+    /// Counts an effect submitted into a namespace, or fails if the namespace
+    /// exceeds its effects per second.
+    fn admit(&mut self, namespace: &str) -> Result<()> {
+        let now = self.clock.now();
+        let ns = match self.namespaces.get_mut(namespace) {
+            Some(ns) => ns,
+            None => return Err(Error::App("There is no namespace with that name.")),
+        };
+
+        if now >= ns.window_start + Duration::from_secs(1) {
+            ns.window_start = now;
+            ns.num_in_window = 0;
+        }
+        if let Some(max) = ns.limits.max_effects_per_sec {
+            if ns.num_in_window >= max {
+                ns.num_throttled += 1;
+                return Err(Error::App("The namespace exceeds its effects per second."));
             }
-            None => return Err(Error::App("No environment with this name available")),
         }
+        ns.num_in_window += 1;
+        ns.num_submitted += 1;
 
         Ok(())
     }
 
-    /// Returns the number of supervised environments.
-    pub fn num_environments(&self) -> usize {
-        let inner = unlock!(self.inner);
-        inner.environments.len()
-    }
+    /// Returns the counters of all environments, sorted by name.
+    fn environment_metrics(&self) -> Vec<EnvironmentMetrics> {
+        let mut environments = self
+            .environments
+            .iter()
+            .map(|(name, conn)| EnvironmentMetrics {
+                name: name.clone(),
+                num_received_effects: conn.environment.num_received_effects(),
+                num_queued_effects: match (&conn.priority_queue, &conn.keyed_queue) {
+                    (Some(queue), _) => unlock!(queue).len(),
+                    (_, Some(queue)) => unlock!(queue).len(),
+                    _ => conn.sender.len(),
+                },
+                polls: conn.environment.poll_stats(),
+            })
+            .collect::<Vec<_>>();
+        environments.sort_by(|a, b| a.name.cmp(&b.name));
 
-    /// Returns the number of supervised entities.
-    pub fn num_entities(&self) -> usize {
-        let inner = unlock!(self.inner);
-        inner.entities.len()
+        environments
     }
-}
 
-impl Future for Supervisor {
-    type Item = ();
-    type Error = Error;
+    /// Returns the counters of all entities, sorted by uuid.
+    fn entity_metrics(&self) -> Vec<EntityMetrics> {
+        let mut entities = self
+            .entities
+            .iter()
+            .map(|(uuid, conn)| EntityMetrics {
+                uuid: uuid.clone(),
+                num_received_effects: conn.entity.num_received_effects(),
+                num_emitted_effects: conn.entity.num_emitted_effects(),
+                polls: conn.entity.poll_stats(),
+            })
+            .collect::<Vec<_>>();
+        entities.sort_by(|a, b| a.uuid.cmp(&b.uuid));
 
-    fn poll(&mut self) -> Poll<(), Self::Error> {
-        let mut inner = unlock!(self.inner);
+        entities
+    }
 
-        // Check for shutdown signal
-        match inner.shutdown_listener.0.poll() {
-            // sig-term received
-            Ok(Async::Ready(Some(is_term))) => {
-                if is_term {
-                    println!("Supervisor received sig-term");
-                    // End this future
-                    return Ok(Async::Ready(()));
-                }
+    /// Sends a copy of an effect to all clones of an environment.
+    fn submit_to_clones(
+        &self,
+        env_link: &EnvironmentConnection,
+        envelope: &Envelope,
+        priority: u8,
+    ) -> Result<()> {
+        for clone in env_link.clones.iter() {
+            if let Some(clone_link) = self.environments.get(clone) {
+                clone_link.send(envelope.clone(), priority)?;
             }
-            _ => (),
         }
 
-        // otherwise go to sleep
-        return Ok(Async::NotReady);
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
+impl Clone for Supervisor {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// The standby environment of a mirror.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MirrorTarget {
+    /// An environment managed by the same supervisor
+    Local(String),
+}
+
+/// What to remove when pruning a supervisor.
+#[derive(Clone, Debug, Default)]
+pub struct PruneOptions {
+    /// Remove entities that neither joined nor affect any environment
+    pub orphan_entities: bool,
+
+    /// Remove environments that no entity joined or affects
+    pub empty_environments: bool,
+
+    /// Only remove what was created longer ago than this
+    pub older_than: Option<Duration>,
+
+    /// Remove empty environments even if they still have queued effects
+    pub force: bool,
+}
+
+/// How an entity joins an environment.
+#[derive(Clone, Debug, Default)]
+pub struct JoinOptions {
+    /// The number of the most recent effects kept in the history to receive first
+    backfill: usize,
+}
+
+impl JoinOptions {
+    /// Lets the entity receive up to `n` of the most recent effects kept in the
+    /// history of the environment, marked as replayed, before anything broadcast
+    /// after it joined.
+    pub fn backfill(mut self, n: usize) -> Self {
+        self.backfill = n;
+        self
+    }
+}
+
+/// What was removed when pruning a supervisor.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PruneReport {
+    /// Names of the removed environments, sorted
+    pub environments: Vec<String>,
+
+    /// Uuids of the removed entities, sorted
+    pub entities: Vec<String>,
+}
+
+/// Connection between the supervisor and an environment.
+pub(crate) struct EnvironmentConnection {
+    /// Sender half of the channel between supervisor and environment
+    pub sender: Sender<Envelope>,
+
+    /// A queue replacing the channel, if the environment processes effects by
+    /// priority
+    pub priority_queue: Option<Arc<Mutex<PriorityQueue>>>,
+
+    /// A queue replacing the channel, if the environment coalesces effects by key
+    pub keyed_queue: Option<Arc<Mutex<KeyedQueue>>>,
+
+    /// The environment that is linked to the supervisor
+    pub environment: Environment,
+
+    /// A notfier for waking up the environment task/future
+    pub waker: Watcher,
+
+    /// Environments that get a copy of every effect submitted to this one
+    pub clones: Vec<String>,
+
+    /// When the environment was created
+    pub created: Instant,
+
+    /// Dropped together with the connection, which lets raw senders notice the
+    /// environment was deleted
+    pub alive: Arc<()>,
+}
+
+impl EnvironmentConnection {
+    /// Sends an effect to the environment and wakes it up. The priority is ignored,
+    /// unless the environment processes effects by priority.
+    fn send(&self, envelope: Envelope, priority: u8) -> Result<()> {
+        if let Some(queue) = self.keyed_queue.as_ref() {
+            return self.send_keyed(envelope, None, queue);
+        }
+
+        match self.priority_queue.as_ref() {
+            Some(queue) => unlock!(queue).push(envelope, priority),
+            None => {
+                if self.sender.send(envelope).is_err() {
+                    return Err(Error::App(
+                        "Error sending the message to the environment",
+                    ));
+                }
+            }
+        }
+        // Notify the task associated with this environment to wake up
+        // and do some work
+        self.waker.notify();
+
+        Ok(())
+    }
+
+    /// Queues an effect replacing any queued effect with the same key and wakes up the
+    /// environment.
+    fn send_keyed(
+        &self,
+        envelope: Envelope,
+        key: Option<&str>,
+        queue: &Mutex<KeyedQueue>,
+    ) -> Result<()> {
+        unlock!(queue).push(envelope, key);
+        self.waker.notify();
+
+        Ok(())
+    }
+}
+
+/// A handle submitting effects straight into the channel of an environment.
+///
+/// It skips everything `submit_effect` does besides pushing the effect, waking the
+/// environment and mirroring it to the standby supervisor: aliases and replicas
+/// aren't resolved on every send, and there are no clones, no strict delivery and no
+/// namespace limits. Once the environment is deleted, sending fails with
+/// `Error::ChannelClosed`.
+#[derive(Clone)]
+pub struct EnvironmentSender {
+    /// Sender half of the channel between supervisor and environment
+    sender: Sender<Envelope>,
+
+    /// A notfier for waking up the environment task/future
+    waker: Watcher,
+
+    /// Gone once the environment was deleted
+    alive: Weak<()>,
+
+    /// The name of the environment
+    name: String,
+
+    /// The standby supervisor of the supervisor the sender was taken from
+    standby: Standby,
+}
+
+impl EnvironmentSender {
+    /// Pushes an effect into the channel of the environment and wakes it up. Repeated
+    /// wake-ups before the environment is polled again are coalesced into one poll.
+    pub fn send(&self, effect: Effect) -> Result<()> {
+        if self.alive.upgrade().is_none() {
+            return Err(Error::ChannelClosed);
+        }
+        let envelope = Envelope::new(effect);
+        let copy = self.standby.copy(&envelope, &self.name, 0, None);
+        if self.sender.send(envelope).is_err() {
+            return Err(Error::ChannelClosed);
+        }
+        self.waker.notify();
+        self.standby.mirror(copy.into_iter().collect());
+
+        Ok(())
+    }
+
+    /// Returns true, if the environment was deleted.
+    pub fn is_closed(&self) -> bool {
+        self.alive.upgrade().is_none()
+    }
+}
+
+/// An entity that is deleted from its supervisor once the guard is dropped.
+pub struct ScopedEntity {
+    /// The supervisor managing the entity
+    supervisor: Supervisor,
+
+    /// The guarded entity
+    entity: EntityHost,
+}
+
+impl Deref for ScopedEntity {
+    type Target = EntityHost;
+
+    fn deref(&self) -> &EntityHost {
+        &self.entity
+    }
+}
+
+impl DerefMut for ScopedEntity {
+    fn deref_mut(&mut self) -> &mut EntityHost {
+        &mut self.entity
+    }
+}
+
+impl Drop for ScopedEntity {
+    fn drop(&mut self) {
+        // The entity may have been deleted or have terminated already
+        if self.supervisor.has_entity(self.entity.uuid()) {
+            // Leave the error to be taken from the supervisor, drop can't return it
+            if let Err(e) = self.supervisor.delete_entity(self.entity.uuid()) {
+                let _ = unlock!(self.supervisor.inner).errors.0.send(e);
+            }
+        }
+    }
+}
+
+/// The standby supervisor of a supervisor, shared with its raw senders.
+#[derive(Clone, Default)]
+struct Standby {
+    /// The supervisor that gets a copy of every submitted effect, if any
+    supervisor: Arc<Mutex<Option<Supervisor>>>,
+
+    /// The number of effects the standby couldn't take
+    num_failures: Arc<AtomicU64>,
+}
+
+impl Standby {
+    /// Returns a copy of an effect submitted to an environment, if there is a
+    /// standby to take it.
+    fn copy(
+        &self,
+        envelope: &Envelope,
+        env_name: &str,
+        priority: u8,
+        key: Option<&str>,
+    ) -> Option<Mirrored> {
+        unlock!(self.supervisor).as_ref()?;
+
+        Some(Mirrored {
+            envelope: Envelope { submission: None, ..envelope.clone() },
+            env_name: env_name.into(),
+            priority,
+            key: key.map(String::from),
+        })
+    }
+
+    /// Submits copies to the environments of the same name of the standby. Must be
+    /// called without holding the lock of the mirrored supervisor, so two
+    /// supervisors can mirror to each other. A failing copy doesn't fail the
+    /// submission, it's only counted.
+    fn mirror(&self, copies: Vec<Mirrored>) {
+        let standby = match unlock!(self.supervisor).clone() {
+            Some(standby) => standby,
+            None => return,
+        };
+
+        let inner = unlock!(standby.inner);
+        for copy in copies {
+            if inner.submit_mirrored(copy).is_err() {
+                self.num_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A copy of an effect for the standby supervisor.
+struct Mirrored {
+    /// The copied effect
+    envelope: Envelope,
+
+    /// The name the effect was submitted to
+    env_name: String,
+
+    /// The priority the effect was submitted with
+    priority: u8,
+
+    /// The key the effect was submitted with
+    key: Option<String>,
+}
+
+/// The replicas of a logical environment.
+struct Replicas {
+    /// The names of the replicas
+    names: Vec<String>,
+
+    /// The number of effects distributed so far, which picks the next replica
+    next: AtomicUsize,
+}
+
+/// The limits of a namespace.
+#[derive(Clone, Debug, Default)]
+pub struct NamespaceLimits {
+    /// The most environments the namespace may have
+    pub max_environments: Option<usize>,
+
+    /// The most effects that may be submitted into the namespace per second
+    pub max_effects_per_sec: Option<u64>,
+}
+
+/// A namespace confining a tenant to the environments created in it.
+struct Namespace {
+    /// The limits of the namespace
+    limits: NamespaceLimits,
+
+    /// The full names of the environments created in the namespace
+    environments: Vec<String>,
+
+    /// The environments outside the namespace it was granted access to
+    grants: Vec<String>,
+
+    /// The uuids of the entities created in the namespace
+    entities: Vec<String>,
+
+    /// The number of effects submitted into the namespace
+    num_submitted: u64,
+
+    /// The number of effects rejected for exceeding the effects per second
+    num_throttled: u64,
+
+    /// When the current second of the rate limit started
+    window_start: Instant,
+
+    /// The number of effects submitted during the current second
+    num_in_window: u64,
+}
+
+/// How an entity is connected to an environment.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Edge {
+    Join,
+    Affect,
+}
+
+/// A join or affect waiting for its environment to be created.
+struct PendingEdge {
+    /// The entity to connect
+    entity: EntityHost,
+
+    /// The name of the environment
+    env_name: String,
+
+    /// Whether the entity joins or affects the environment
+    edge: Edge,
+}
+
+impl PartialEq for PendingEdge {
+    fn eq(&self, other: &Self) -> bool {
+        self.entity.uuid() == other.entity.uuid()
+            && self.env_name == other.env_name
+            && self.edge == other.edge
+    }
+}
+
+/// Connection between the supervisor and an entity.
+pub(crate) struct EntityConnection {
+    /// An entity.
+    pub entity: EntityHost,
+
+    /// When the entity was created
+    pub created: Instant,
+}
+
+impl Supervisor {
+    /// Creates a new supervisor.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// ```
+    pub fn new(shutdown_listener: TriggerHandle) -> Result<Self> {
+        let inner = Arc::new(Mutex::new(Inner {
+            environments: HashMap::new(),
+            entities: HashMap::new(),
+            shutdown_listener,
+            clock: shared!(SystemClock),
+            timer: Interval::new_interval(Duration::from_millis(TIMER_RESOLUTION_MS)),
+            tracer: shared!(FlowTracer::default()),
+            strict_delivery: false,
+            num_broadcast: shared!(AtomicU64::new(0)),
+            throughput: ThroughputMeter::default(),
+            last_submission: 0,
+            aliases: HashMap::new(),
+            standby: Standby::default(),
+            pending_edges: vec![],
+            replicas: HashMap::new(),
+            namespaces: HashMap::new(),
+            errors: unbounded(),
+        }));
+
+        Ok(Self {
+            inner,
+        })
+    }
+
+    /// Lets all environments and entities created from now on read the time from the
+    /// given clock.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        unlock!(self.inner).clock = clock;
+        self
+    }
+
+    /// Creates a new environment.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    ///
+    /// sv.create_environment("X", sd.get_listener()).unwrap();
+    /// ```
+    pub fn create_environment(
+        &mut self,
+        name: &str,
+        sd_handle: TriggerHandle,
+    ) -> Result<Environment> {
+        let mut inner = unlock!(self.inner);
+
+        if inner.environments.contains_key(name) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(name) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        Ok(inner.insert_environment(name, sd_handle, None, EnvironmentConfig::default()))
+    }
+
+    /// Creates a new environment with the given configuration.
+    pub fn create_environment_with_config(
+        &mut self,
+        name: &str,
+        config: EnvironmentConfig,
+        sd_handle: TriggerHandle,
+    ) -> Result<Environment> {
+        let mut inner = unlock!(self.inner);
+
+        if inner.environments.contains_key(name) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(name) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        Ok(inner.insert_environment(name, sd_handle, None, config))
+    }
+
+    /// Creates a new environment that processes effects by priority.
+    ///
+    /// Effects with a higher priority are broadcast first, effects with the same
+    /// priority in the order they were submitted. Effects submitted without a
+    /// priority get the lowest priority.
+    pub fn create_priority_queue_environment(
+        &mut self,
+        name: &str,
+        sd_handle: TriggerHandle,
+    ) -> Result<Environment> {
+        let mut inner = unlock!(self.inner);
+
+        if inner.environments.contains_key(name) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(name) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        let queue = shared_mut!(PriorityQueue::default());
+
+        Ok(inner.insert_environment(
+            name,
+            sd_handle,
+            Some(queue),
+            EnvironmentConfig::default(),
+        ))
+    }
+
+    /// Creates `n` replicas of an environment, named after the logical name with
+    /// their index appended, e.g. "X-0" to "X-2". Effects submitted under the logical
+    /// name are distributed to the replicas round robin. Entities join and affect
+    /// the replicas by their own names.
+    pub fn create_replicated_environment(
+        &mut self,
+        name: &str,
+        n: usize,
+        sd_handle: TriggerHandle,
+    ) -> Result<Vec<Environment>> {
+        let mut inner = unlock!(self.inner);
+
+        if n == 0 {
+            return Err(Error::App("An environment needs at least one replica."));
+        }
+        if inner.environments.contains_key(name) || inner.replicas.contains_key(name) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(name) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        let names = (0..n).map(|i| format!("{}-{}", name, i)).collect::<Vec<_>>();
+        if names.iter().any(|replica| {
+            inner.environments.contains_key(replica)
+                || inner.aliases.contains_key(replica)
+        }) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+
+        let replicas = names
+            .iter()
+            .map(|replica| {
+                let sd_handle = TriggerHandle(sd_handle.0.clone());
+                inner.insert_environment(
+                    replica,
+                    sd_handle,
+                    None,
+                    EnvironmentConfig::default(),
+                )
+            })
+            .collect();
+        inner.replicas.insert(name.into(), Replicas { names, next: AtomicUsize::new(0) });
+
+        Ok(replicas)
+    }
+
+    /// Returns the environment with the given name, or creates it if it doesn't exist
+    /// yet.
+    pub fn ensure_environment(
+        &mut self,
+        name: &str,
+        sd_handle: TriggerHandle,
+    ) -> Result<Environment> {
+        self.get_or_create_environment(name, sd_handle).map(|(env, _)| env)
+    }
+
+    /// Returns the environment with the given name, or creates it if it doesn't exist
+    /// yet. The returned flag tells whether the environment was created.
+    pub(crate) fn get_or_create_environment(
+        &mut self,
+        name: &str,
+        sd_handle: TriggerHandle,
+    ) -> Result<(Environment, bool)> {
+        let mut inner = unlock!(self.inner);
+
+        // Lookup and creation happen under the same lock, so concurrent callers can't
+        // both create the environment
+        if let Some(conn) = inner.environments.get(inner.resolve(name)) {
+            return Ok((conn.environment.clone(), false));
+        }
+
+        let env =
+            inner.insert_environment(name, sd_handle, None, EnvironmentConfig::default());
+
+        Ok((env, true))
+    }
+
+    /// Delete an environment.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    ///
+    /// let x = sv.create_environment("X", sd.get_listener()).unwrap();
+    ///
+    /// sv.delete_environment(x.name()).unwrap();
+    /// ```
+    pub fn delete_environment(&mut self, env_name: &str) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        let env_name = inner.resolve(env_name).to_string();
+        match inner.environments.remove(&env_name) {
+            Some(env_conn) => {
+                // Stop copying effects into this environment
+                for conn in inner.environments.values_mut() {
+                    conn.clones.retain(|clone| *clone != env_name);
+                    conn.environment.remove_mirror(&env_name);
+                }
+                inner.aliases.retain(|_, canonical| *canonical != env_name);
+                for replicas in inner.replicas.values_mut() {
+                    replicas.names.retain(|replica| *replica != env_name);
+                }
+                inner.replicas.retain(|_, replicas| !replicas.names.is_empty());
+                for ns in inner.namespaces.values_mut() {
+                    ns.environments.retain(|env| *env != env_name);
+                }
+
+                // Let all entities affecting this environment forget about it right
+                // away, joined ones are informed by the sig-term
+                for conn in inner.entities.values() {
+                    conn.entity.lose_affected_environment(&env_name);
+                }
+
+                // Inform subscribed entities that this environment is going to be dropped
+                env_conn.environment.send_sig_term()?;
+                Ok(())
+            }
+            None => Err(Error::App(
+                "There is no environment with that name managed by this supervisor.",
+            )),
+        }
+    }
+
+    /// Removes orphaned entities and empty environments, and reports what was removed.
+    /// An environment with queued effects is only removed, if `force` is set. An
+    /// entity waiting to join or affect an environment that doesn't exist yet isn't
+    /// orphaned.
+    pub fn prune(&mut self, options: PruneOptions) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+        {
+            let inner = unlock!(self.inner);
+            let now = inner.clock.now();
+            let is_old = |created: Instant| match options.older_than {
+                Some(age) => now.duration_since(created) > age,
+                None => true,
+            };
+
+            if options.orphan_entities {
+                report.entities = inner
+                    .entities
+                    .iter()
+                    .filter(|(_, conn)| is_old(conn.created))
+                    .filter(|(_, conn)| {
+                        conn.entity.num_joined() == 0 && conn.entity.num_affected() == 0
+                    })
+                    // Entities still waiting for an environment to be created aren't
+                    // orphaned
+                    .filter(|(uuid, _)| {
+                        !inner
+                            .pending_edges
+                            .iter()
+                            .any(|edge| edge.entity.uuid() == *uuid)
+                    })
+                    .map(|(uuid, _)| uuid.clone())
+                    .collect();
+            }
+
+            if options.empty_environments {
+                report.environments = inner
+                    .environments
+                    .iter()
+                    .filter(|(_, conn)| is_old(conn.created))
+                    .filter(|(_, conn)| {
+                        conn.environment.num_joined_entities() == 0
+                            && conn.environment.num_affecting_entities() == 0
+                    })
+                    .filter(|(_, conn)| {
+                        options.force || conn.environment.num_queued() == 0
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect();
+            }
+        }
+        report.entities.sort();
+        report.environments.sort();
+
+        for uuid in report.entities.iter() {
+            self.delete_entity(uuid)?;
+        }
+        for name in report.environments.iter() {
+            self.delete_environment(name)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Create an entity.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    ///
+    /// sv.create_entity(sd.get_listener()).unwrap();
+    /// ```
+    pub fn create_entity(&mut self, sd_handle: TriggerHandle) -> Result<EntityHost> {
+        let mut inner = unlock!(self.inner);
+
+        Ok(inner.insert_entity(EntityHost::new(sd_handle)))
+    }
+
+    /// Creates an entity that is deleted again once the returned guard is dropped,
+    /// e.g. a temporary entity in a test.
+    pub fn create_scoped_entity(
+        &mut self,
+        sd_handle: TriggerHandle,
+    ) -> Result<ScopedEntity> {
+        let entity = self.create_entity(sd_handle)?;
+
+        Ok(ScopedEntity { supervisor: self.clone(), entity })
+    }
+
+    /// Creates an entity running the core, and lets it join and affect the
+    /// environments the core subscribes to. Join patterns only match environments
+    /// that exist already, other joins and all affects are completed once their
+    /// environment is created.
+    pub fn create_entity_auto(
+        &mut self,
+        core: Box<dyn Entity>,
+        sd_handle: TriggerHandle,
+    ) -> Result<EntityHost> {
+        let subscriptions = core.subscriptions();
+
+        let mut entity = {
+            let mut inner = unlock!(self.inner);
+            let mut entity = EntityHost::new(sd_handle);
+            entity.inject_core(core);
+            inner.insert_entity(entity)
+        };
+
+        let joins = {
+            let inner = unlock!(self.inner);
+            let mut joins = vec![];
+            for pattern in subscriptions.joins.iter() {
+                if pattern.ends_with('*') {
+                    joins.extend(
+                        inner
+                            .environments
+                            .keys()
+                            .filter(|env_name| {
+                                CoreSubscriptions::matches(pattern, env_name)
+                            })
+                            .cloned(),
+                    );
+                } else {
+                    joins.push(pattern.clone());
+                }
+            }
+            joins.sort();
+            joins.dedup();
+
+            joins
+        };
+
+        self.join_environments_deferred(
+            &mut entity,
+            joins.iter().map(String::as_str).collect(),
+        )?;
+        self.affect_environments_deferred(
+            &mut entity,
+            subscriptions.affects.iter().map(String::as_str).collect(),
+        )?;
+
+        Ok(entity)
+    }
+
+    /// Create a source entity, that only emits the effects its core produces on
+    /// ticks into the environments it affects. It can't join any environment.
+    pub fn create_source_entity(
+        &mut self,
+        core: Box<dyn Entity>,
+        sd_handle: TriggerHandle,
+    ) -> Result<EntityHost> {
+        let mut inner = unlock!(self.inner);
+
+        let mut entity = EntityHost::new(sd_handle).into_source();
+        entity.inject_core(core);
+
+        Ok(inner.insert_entity(entity))
+    }
+
+    /// Delete an entity.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// let a = sv.create_entity(sd.get_listener()).unwrap();
+    ///
+    /// sv.delete_entity(a.uuid()).unwrap();
+    /// ```
+    pub fn delete_entity(&mut self, uuid: &str) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        match inner.remove_entity(uuid) {
+            Some(ent_conn) => {
+                // Let a core stuck in a long-running invocation bail out right away,
+                // as the entity's task may not get to run before it returns
+                ent_conn.entity.cancel();
+
+                // Unsubscribe from all environments the entity has joined and
+                ent_conn.entity.send_sig_term()?;
+                Ok(())
+            }
+            None => Err(Error::App(
+                "There is no entity with that uuid managed by this supervisor.",
+            )),
+        }
+    }
+
+    /// Takes the errors the entities of this supervisor reported so far, e.g. every
+    /// time a core failed to start, or a scoped entity couldn't be deleted.
+    pub fn take_errors(&self) -> Vec<Error> {
+        unlock!(self.inner).errors.1.try_iter().collect()
+    }
+
+    /// Retires an entity without dropping work it already took: its intake is paused,
+    /// it processes and emits everything broadcast to it so far, and then it leaves
+    /// all environments and is deleted. If that takes longer than the timeout, the
+    /// entity resumes its intake and stays.
+    pub fn drain_entity(&mut self, uuid: &str, timeout: Duration) -> Result<()> {
+        let mut entity = match unlock!(self.inner).entities.get(uuid) {
+            Some(conn) => conn.entity.clone(),
+            None => {
+                return Err(Error::App(
+                    "There is no entity with that uuid managed by this supervisor.",
+                ))
+            }
+        };
+
+        entity.pause_intake();
+
+        let deadline = Instant::now() + timeout;
+        while !entity.is_drained() {
+            if Instant::now() >= deadline {
+                entity.resume_intake();
+                return Err(Error::App("Timed out waiting for the entity to drain."));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        {
+            let mut inner = unlock!(self.inner);
+            for env_name in entity.joined_environments() {
+                if let Some(conn) = inner.environments.get_mut(&env_name) {
+                    conn.environment.deregister_joining_entity(&mut entity)?;
+                }
+            }
+        }
+
+        self.delete_entity(uuid)
+    }
+
+    /// Lets the specified entity join one or multiple environments.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// let x = sv.create_environment("X", sd.get_listener()).unwrap();
+    /// let mut a = sv.create_entity(sd.get_listener()).unwrap();
+    ///
+    /// sv.join_environments(&mut a, vec![x.name()]).unwrap();
+    /// ```
+    pub fn join_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        if entity.is_source() {
+            return Err(Error::App("A source entity can't join environments."));
+        }
+
+        // Check, if all given environments are known to this supervisor
+        if !environments
+            .iter()
+            .all(|env_name| inner.environments.contains_key(inner.resolve(env_name)))
+        {
+            return Err(Error::App(
+                "At least one of the specified environments is unknown to this supervisor.",
+            ));
+        }
+
+        // Let the entity join all specified environments
+        for env_name in environments.iter() {
+            let env_name = inner.resolve(env_name).to_string();
+            let conn = inner.environments.get_mut(&env_name).unwrap();
+            conn.environment.register_joining_entity(entity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets the specified entity join an environment as told by the options.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::eee::environment::EnvironmentConfig;
+    /// use reee::supervisor::{JoinOptions, Supervisor};
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// let config = EnvironmentConfig::default().history(10);
+    /// let x = sv
+    ///     .create_environment_with_config("X", config, sd.get_listener())
+    ///     .unwrap();
+    /// let mut a = sv.create_entity(sd.get_listener()).unwrap();
+    ///
+    /// let options = JoinOptions::default().backfill(5);
+    /// sv.join_environments_with(&mut a, x.name(), options).unwrap();
+    /// ```
+    pub fn join_environments_with(
+        &mut self,
+        entity: &mut EntityHost,
+        env_name: &str,
+        options: JoinOptions,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        if entity.is_source() {
+            return Err(Error::App("A source entity can't join environments."));
+        }
+
+        let env_name = inner.resolve(env_name).to_string();
+        match inner.environments.get_mut(&env_name) {
+            Some(conn) => conn
+                .environment
+                .register_joining_entity_with_backfill(entity, options.backfill),
+            None => Err(Error::App("No environment with this name available")),
+        }
+    }
+
+    /// Lets the specified entity leave one or multiple environments.
+    pub fn leave_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        // Check, if all given environments are known to this supervisor
+        if !environments
+            .iter()
+            .all(|env_name| inner.environments.contains_key(inner.resolve(env_name)))
+        {
+            return Err(Error::App(
+                "At least one of the specified environments is unknown to this supervisor.",
+            ));
+        }
+
+        // Let the entity leave all specified environments
+        for env_name in environments.iter() {
+            let env_name = inner.resolve(env_name).to_string();
+            let conn = inner.environments.get_mut(&env_name).unwrap();
+            conn.environment.deregister_joining_entity(entity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets the specified entity affect one or multiple environments.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// let x = sv.create_environment("X", sd.get_listener()).unwrap();
+    /// let mut a = sv.create_entity(sd.get_listener()).unwrap();
+    ///
+    /// sv.affect_environments(&mut a, vec![x.name()]).unwrap();
+    /// ```
+    pub fn affect_environments(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        // Check, if all given environments are known to this supervisor
+        if !environments
+            .iter()
+            .all(|env_name| inner.environments.contains_key(inner.resolve(env_name)))
+        {
+            return Err(Error::App(
+                "At least one of the specified environments is unknown to this supervisor.",
+            ));
+        }
+
+        // Let the entity affect all specified environments
+        for env_name in environments.iter() {
+            let env_name = inner.resolve(env_name).to_string();
+            let conn = inner.environments.get_mut(&env_name).unwrap();
+            conn.environment.register_affecting_entity(entity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets the specified entity join one or multiple environments, each right away
+    /// if it exists or as soon as it's created otherwise. Pending joins are dropped
+    /// together with the entity.
+    pub fn join_environments_deferred(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        if entity.is_source() {
+            return Err(Error::App("A source entity can't join environments."));
+        }
+
+        for env_name in environments.iter() {
+            inner.connect_deferred(entity, env_name, Edge::Join)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets the specified entity affect one or multiple environments, each right away
+    /// if it exists or as soon as it's created otherwise. Pending affects are dropped
+    /// together with the entity.
+    pub fn affect_environments_deferred(
+        &mut self,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        for env_name in environments.iter() {
+            inner.connect_deferred(entity, env_name, Edge::Affect)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the uuids of the entities and the names of the environments they'll
+    /// join once those are created, sorted.
+    pub fn pending_joins(&self) -> Vec<(String, String)> {
+        unlock!(self.inner).pending(Edge::Join)
+    }
+
+    /// Returns the uuids of the entities and the names of the environments they'll
+    /// affect once those are created, sorted.
+    pub fn pending_affects(&self) -> Vec<(String, String)> {
+        unlock!(self.inner).pending(Edge::Affect)
+    }
+
+    /*
+    pub fn stop_affecting_environments(
+        &mut self,
+        entity: &mut Entity,
+        environments: Vec<&str>,
+    ) -> Result<(), Error> {
+        //
+    }
+    */
+
+    /// Submit an effect to an enviroment.
+    ///
+    /// # Example
+    /// ```
+    /// use reee::eee::Effect;
+    /// use reee::supervisor::Supervisor;
+    /// use reee::GracefulShutdown;
+    ///
+    /// let sd = GracefulShutdown::new();
+    /// let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+    /// let x = sv.create_environment("X", sd.get_listener()).unwrap();
+    ///
+    /// sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+    /// ```
+    pub fn submit_effect(&mut self, effect: Effect, env_name: &str) -> Result<()> {
+        self.submit_envelope(Envelope::new(effect), env_name)
+    }
+
+    /// Submit an effect to an environment, that is dropped instead of delivered once
+    /// it's older than `max_age`, whether it's about to be broadcast or processed.
+    pub fn submit_effect_with_deadline(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        max_age: Duration,
+    ) -> Result<()> {
+        let deadline = unlock!(self.inner).clock.now() + max_age;
+
+        self.submit_envelope(
+            Envelope { deadline: Some(deadline), ..Envelope::new(effect) },
+            env_name,
+        )
+    }
+
+    /// Returns a handle submitting effects straight into the channel of an
+    /// environment, for producers that can't afford the lookup and the lock of
+    /// `submit_effect`. Fails for environments processing effects by priority or key,
+    /// which don't read their channel.
+    pub fn raw_sender(&self, env_name: &str) -> Result<EnvironmentSender> {
+        let inner = unlock!(self.inner);
+        let env_name = inner.resolve(env_name);
+        let conn = match inner.environments.get(env_name) {
+            Some(conn) => conn,
+            None => return Err(Error::App("No environment with this name available")),
+        };
+        if conn.priority_queue.is_some() || conn.keyed_queue.is_some() {
+            return Err(Error::App("The environment doesn't read from its channel."));
+        }
+
+        Ok(EnvironmentSender {
+            sender: conn.sender.clone(),
+            waker: conn.waker.clone(),
+            alive: Arc::downgrade(&conn.alive),
+            name: env_name.into(),
+            standby: inner.standby.clone(),
+        })
+    }
+
+    /// Submit an effect to an environment and block until every entity that joined
+    /// it has received another effect, or until the timeout has passed.
+    ///
+    /// Only meant for environments without other effects in flight, because the
+    /// entities' counters don't tell which effect they received.
+    pub fn submit_and_wait(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let entities = {
+            let inner = unlock!(self.inner);
+            let env_name = inner.resolve(env_name);
+            inner
+                .entities
+                .values()
+                .filter(|ent_conn| ent_conn.entity.has_joined(env_name))
+                .map(|ent_conn| {
+                    (ent_conn.entity.clone(), ent_conn.entity.num_received_effects())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        self.submit_effect(effect, env_name)?;
+
+        let deadline = Instant::now() + timeout;
+        while entities.iter().any(|(entity, num)| entity.num_received_effects() <= *num) {
+            if Instant::now() >= deadline {
+                return Err(Error::App(
+                    "Timed out waiting for the joined entities to receive the effect.",
+                ));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    /// Submit an effect with a correlation id to an environment. The id is passed on
+    /// to all effects resulting from it.
+    pub fn submit_correlated_effect(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        correlation_id: u64,
+    ) -> Result<()> {
+        self.submit_envelope(
+            Envelope::new(effect).with_correlation_id(correlation_id),
+            env_name,
+        )
+    }
+
+    /// Reads an OS environment variable and submits its value as an ASCII effect to
+    /// an environment. Fails if the variable is unset or its value isn't ASCII.
+    pub fn submit_env_var(&mut self, var_name: &str, env_name: &str) -> Result<()> {
+        let value = match std::env::var(var_name) {
+            Ok(value) => value,
+            Err(_) => return Err(Error::App("The environment variable is unset.")),
+        };
+
+        self.submit_effect(Effect::ascii(value)?, env_name)
+    }
+
+    fn submit_envelope(&mut self, envelope: Envelope, env_name: &str) -> Result<()> {
+        let (standby, copy) = {
+            let inner = unlock!(self.inner);
+            let copy = inner.standby.copy(&envelope, env_name, 0, None);
+
+            inner.submit_envelope(envelope, env_name)?;
+            (inner.standby.clone(), copy)
+        };
+
+        // Only copy the effect to the standby after releasing the lock
+        standby.mirror(copy.into_iter().collect());
+
+        Ok(())
+    }
+
+    /// Submit an effect to an environment and block until the environment has
+    /// broadcast it to its joined entities, or until the timeout has passed. Doesn't
+    /// wait for the entities to process it.
+    pub fn submit_effect_sync(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let (environment, submission, notified, standby, copy) = {
+            let mut inner = unlock!(self.inner);
+            inner.last_submission += 1;
+            let submission = inner.last_submission;
+
+            let env_link = match inner.environments.get(inner.route(env_name)) {
+                Some(env_link) => env_link,
+                None => {
+                    return Err(Error::App("No environment with this name available"))
+                }
+            };
+            inner.check_listeners(env_link)?;
+
+            let environment = env_link.environment.clone();
+            let notified = environment.notify_on_broadcast(submission);
+
+            let envelope =
+                Envelope { submission: Some(submission), ..Envelope::new(effect) };
+            let copy = inner.standby.copy(&envelope, env_name, 0, None);
+            let sent = inner
+                .submit_to_clones(env_link, &envelope, 0)
+                .and_then(|_| env_link.send(envelope, 0));
+            if let Err(e) = sent {
+                environment.cancel_broadcast_notification(submission);
+                return Err(e);
+            }
+
+            (environment, submission, notified, inner.standby.clone(), copy)
+        };
+        standby.mirror(copy.into_iter().collect());
+
+        match notified.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                environment.cancel_broadcast_notification(submission);
+                Err(Error::App(
+                    "Timed out waiting for the environment to broadcast the effect.",
+                ))
+            }
+        }
+    }
+
+    /// Submit effects to several environments, such that either all of them are
+    /// queued or none. All targets are checked before anything is sent, and replicas
+    /// are only picked once all of them passed. Channels to environments are
+    /// unbounded, so there is no capacity to check.
+    pub fn submit_all(&mut self, effects: &[(Effect, &str)]) -> Result<()> {
+        let mut copies = vec![];
+        let inner = unlock!(self.inner);
+
+        // The number of effects going to each name, which picks their replicas
+        let mut routed: HashMap<&str, usize> = HashMap::new();
+
+        let mut targets = vec![];
+        for (effect, env_name) in effects.iter() {
+            let nth = routed.entry(*env_name).or_insert(0);
+            match inner.environments.get(inner.route_nth(env_name, *nth)) {
+                Some(env_link) => {
+                    inner.check_listeners(env_link)?;
+                    targets.push((Envelope::new(effect.clone()), env_link));
+                    *nth += 1;
+                }
+                None => {
+                    return Err(Error::App("No environment with this name available"))
+                }
+            }
+        }
+
+        for (env_name, n) in routed {
+            inner.advance_route(env_name, n);
+        }
+
+        // Sending can't fail from here on: holding the lock keeps every linked
+        // environment, and with it the receiving end of its channel, alive
+        for ((envelope, env_link), (_, env_name)) in targets.into_iter().zip(effects) {
+            copies.extend(inner.standby.copy(&envelope, env_name, 0, None));
+            inner.submit_to_clones(env_link, &envelope, 0)?;
+            env_link.send(envelope, 0)?;
+        }
+
+        let standby = inner.standby.clone();
+        drop(inner);
+        standby.mirror(copies);
+
+        Ok(())
+    }
+
+    /// Submit an effect with a key to an environment created with a keyed
+    /// configuration. Any effect with the same key that is still queued is replaced.
+    /// Clones of the environment get a copy, which is coalesced as well if they are
+    /// keyed.
+    pub fn submit_keyed(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        key: &str,
+    ) -> Result<()> {
+        let inner = unlock!(self.inner);
+        let env_link = match inner.environments.get(inner.resolve(env_name)) {
+            Some(env_link) => env_link,
+            None => return Err(Error::App("No environment with this name available")),
+        };
+        let queue = match env_link.keyed_queue.as_ref() {
+            Some(queue) => queue,
+            None => {
+                return Err(Error::App(
+                    "This environment doesn't coalesce effects by key",
+                ))
+            }
+        };
+
+        inner.check_listeners(env_link)?;
+
+        let envelope = Envelope::new(effect);
+        let copy = inner.standby.copy(&envelope, env_name, 0, Some(key));
+        for clone in env_link.clones.iter() {
+            if let Some(clone_link) = inner.environments.get(clone) {
+                match clone_link.keyed_queue.as_ref() {
+                    Some(clone_queue) => {
+                        clone_link.send_keyed(envelope.clone(), Some(key), clone_queue)?
+                    }
+                    None => clone_link.send(envelope.clone(), 0)?,
+                }
+            }
+        }
+        env_link.send_keyed(envelope, Some(key), queue)?;
+
+        let standby = inner.standby.clone();
+        drop(inner);
+        standby.mirror(copy.into_iter().collect());
+
+        Ok(())
+    }
+
+    /// Submit an effect with a priority to an environment created by
+    /// `create_priority_queue_environment`.
+    pub fn submit_effect_prioritized(
+        &mut self,
+        effect: Effect,
+        env_name: &str,
+        priority: u8,
+    ) -> Result<()> {
+        let (standby, copy) = {
+            let inner = unlock!(self.inner);
+            let env_link = match inner.environments.get(inner.resolve(env_name)) {
+                Some(env_link) => env_link,
+                None => {
+                    return Err(Error::App("No environment with this name available"))
+                }
+            };
+            if env_link.priority_queue.is_none() {
+                return Err(Error::App(
+                    "This environment doesn't process effects by priority",
+                ));
+            }
+
+            inner.check_listeners(env_link)?;
+
+            let envelope = Envelope::new(effect);
+            let copy = inner.standby.copy(&envelope, env_name, priority, None);
+            inner.submit_to_clones(env_link, &envelope, priority)?;
+            env_link.send(envelope, priority)?;
+
+            (inner.standby.clone(), copy)
+        };
+        standby.mirror(copy.into_iter().collect());
+
+        Ok(())
+    }
+
+    /// Lets every effect submitted to the source environment also be submitted to
+    /// the destination environment, so both can be processed independently. Only
+    /// effects submitted through the supervisor are copied, and copies aren't copied
+    /// again.
+    pub fn clone_environment(&mut self, src: &str, dst: &str) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        let src = inner.resolve(src).to_string();
+        let dst = inner.resolve(dst).to_string();
+
+        if src == dst {
+            return Err(Error::App("An environment can't be cloned into itself."));
+        }
+        if !inner.environments.contains_key(&dst) {
+            return Err(Error::App("No environment with this name available"));
+        }
+
+        match inner.environments.get_mut(&src) {
+            Some(env_link) => {
+                if env_link.clones.contains(&dst) {
+                    return Err(Error::App(
+                        "This environment is already cloned into that one.",
+                    ));
+                }
+                env_link.clones.push(dst);
+            }
+            None => return Err(Error::App("No environment with this name available")),
+        }
+
+        Ok(())
+    }
+
+    /// Lets a standby supervisor get a copy of every effect submitted to any
+    /// environment, which is submitted to its environment of the same name. Copies
+    /// are best-effort and aren't mirrored again, failures are only counted. Replaces
+    /// the current standby.
+    pub fn mirror_to(&mut self, standby: Supervisor) -> Result<()> {
+        if Arc::ptr_eq(&self.inner, &standby.inner) {
+            return Err(Error::App("A supervisor can't mirror to itself."));
+        }
+        let slot = Arc::clone(&unlock!(self.inner).standby.supervisor);
+        unlock!(slot).replace(standby);
+
+        Ok(())
+    }
+
+    /// Returns the number of effects the standby supervisor couldn't take.
+    pub fn num_mirror_failures(&self) -> u64 {
+        unlock!(self.inner).standby.num_failures.load(Ordering::Relaxed)
+    }
+
+    /// Lets a standby environment receive a copy of every effect the environment
+    /// broadcasts, including those coming from affecting entities.
+    pub fn mirror(&mut self, env_name: &str, target: MirrorTarget) -> Result<()> {
+        let inner = unlock!(self.inner);
+
+        let MirrorTarget::Local(target) = target;
+        let target = inner.resolve(&target).to_string();
+        let env_name = inner.resolve(env_name);
+
+        let env_link = match inner.environments.get(env_name) {
+            Some(env_link) => env_link,
+            None => return Err(Error::App("No environment with this name available")),
+        };
+        let target_link = match inner.environments.get(&target) {
+            Some(target_link) => target_link,
+            None => return Err(Error::App("No environment with this name available")),
+        };
+
+        if target_link.priority_queue.is_some() {
+            return Err(Error::App("A priority queue environment can't be a mirror."));
+        }
+        if target_link.keyed_queue.is_some() {
+            return Err(Error::App("A keyed environment can't be a mirror."));
+        }
+        if env_link.environment.mirror_targets().contains(&target) {
+            return Err(Error::App(
+                "This environment is already mirrored into that one.",
+            ));
+        }
+
+        // Refuse mirrors that would send effects back to where they came from
+        let mut reachable = vec![target.clone()];
+        while let Some(name) = reachable.pop() {
+            if name == env_name {
+                return Err(Error::App(
+                    "Mirroring into that environment creates a loop.",
+                ));
+            }
+            if let Some(link) = inner.environments.get(&name) {
+                reachable.extend(link.environment.mirror_targets());
+            }
+        }
+
+        env_link.environment.add_mirror(Mirror {
+            target,
+            sender: target_link.sender.clone(),
+            waker: target_link.waker.clone(),
+            num_mirrored: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Stops mirroring an environment into a standby environment. The mirrored
+    /// environment isn't affected.
+    pub fn unmirror(&mut self, env_name: &str, target: &str) -> Result<()> {
+        let inner = unlock!(self.inner);
+        match inner.environments.get(inner.resolve(env_name)) {
+            Some(env_link) => {
+                if !env_link.environment.remove_mirror(target) {
+                    return Err(Error::App(
+                        "This environment isn't mirrored into that one.",
+                    ));
+                }
+            }
+            None => return Err(Error::App("No environment with this name available")),
+        }
+
+        Ok(())
+    }
+
+    /// Appends a function to the transforms the environment applies, in order, to
+    /// every effect it receives before broadcasting it.
+    pub fn add_transform<F>(&mut self, env_name: &str, transform: F) -> Result<()>
+    where
+        F: Fn(Effect) -> Effect + Send + 'static,
+    {
+        let inner = unlock!(self.inner);
+        match inner.environments.get(inner.resolve(env_name)) {
+            Some(env_link) => env_link.environment.add_transform(Box::new(transform)),
+            None => return Err(Error::App("No environment with this name available")),
+        }
+
+        Ok(())
+    }
+
+    /// Removes all transforms of the environment.
+    pub fn clear_transforms(&mut self, env_name: &str) -> Result<()> {
+        let inner = unlock!(self.inner);
+        match inner.environments.get(inner.resolve(env_name)) {
+            Some(env_link) => env_link.environment.clear_transforms(),
+            None => return Err(Error::App("No environment with this name available")),
+        }
+
+        Ok(())
+    }
+
+    /// Returns how far each standby environment is behind the environment.
+    pub fn mirror_status(&self, env_name: &str) -> Result<Vec<MirrorStatus>> {
+        let inner = unlock!(self.inner);
+        match inner.environments.get(inner.resolve(env_name)) {
+            Some(env_link) => Ok(env_link.environment.mirror_status()),
+            None => Err(Error::App("No environment with this name available")),
+        }
+    }
+
+    /// Starts recording the hops of correlated effects.
+    pub fn enable_flow_tracing(&self) {
+        unlock!(self.inner).tracer.enable();
+    }
+
+    /// Lets submitting an effect to an environment nobody joined fail, instead of
+    /// silently dropping the effect.
+    pub fn set_strict_delivery(&mut self, strict: bool) {
+        unlock!(self.inner).strict_delivery = strict;
+    }
+
+    /// Returns the recorded hops of all effects with the given correlation id in the
+    /// order they happened.
+    pub fn trace(&self, correlation_id: u64) -> Vec<TraceStep> {
+        unlock!(self.inner).tracer.steps(correlation_id)
+    }
+
+    /// Returns true, if this supervisor manages an environment with the specified
+    /// name, otherwise false.
+    pub fn has_environment(&self, name: &str) -> bool {
+        let inner = unlock!(self.inner);
+        inner.environments.contains_key(inner.resolve(name))
+    }
+
+    /// Lets an alias stand for an existing environment, so submitting, joining,
+    /// affecting and lookups under the alias use that environment.
+    pub fn alias(&mut self, existing: &str, alias: &str) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        if inner.environments.contains_key(alias) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(alias) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        let existing = inner.resolve(existing).to_string();
+        if !inner.environments.contains_key(&existing) {
+            return Err(Error::App("No environment with this name available"));
+        }
+
+        inner.aliases.insert(alias.into(), existing);
+
+        Ok(())
+    }
+
+    /// Removes an alias. The environment it stood for isn't affected.
+    pub fn remove_alias(&mut self, alias: &str) -> Result<()> {
+        match unlock!(self.inner).aliases.remove(alias) {
+            Some(_) => Ok(()),
+            None => Err(Error::App("There is no alias with that name.")),
+        }
+    }
+
+    /// Returns all aliases together with the name of the environment they stand for,
+    /// sorted by alias.
+    pub fn aliases(&self) -> Vec<(String, String)> {
+        let inner = unlock!(self.inner);
+
+        let mut aliases = inner
+            .aliases
+            .iter()
+            .map(|(alias, name)| (alias.clone(), name.clone()))
+            .collect::<Vec<_>>();
+        aliases.sort();
+
+        aliases
+    }
+
+    /// Creates a namespace, whose tenant only reaches the environments prefixed with
+    /// its name followed by a dot, and those it was granted access to.
+    pub fn create_namespace(
+        &mut self,
+        name: &str,
+        limits: NamespaceLimits,
+    ) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+
+        if name.is_empty() || name.contains('.') {
+            return Err(Error::App("A namespace name must not be empty or contain dots."));
+        }
+        if inner.namespaces.contains_key(name) {
+            return Err(Error::App("Namespace with that name already exists."));
+        }
+
+        let now = inner.clock.now();
+        inner.namespaces.insert(
+            name.into(),
+            Namespace {
+                limits,
+                environments: vec![],
+                grants: vec![],
+                entities: vec![],
+                num_submitted: 0,
+                num_throttled: 0,
+                window_start: now,
+                num_in_window: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns true, if this supervisor has a namespace with the specified name.
+    pub fn has_namespace(&self, name: &str) -> bool {
+        unlock!(self.inner).namespaces.contains_key(name)
+    }
+
+    /// Lets the tenant of a namespace reach an environment outside of it under its
+    /// full name.
+    pub fn grant(&mut self, namespace: &str, env_name: &str) -> Result<()> {
+        let mut inner = unlock!(self.inner);
+        match inner.namespaces.get_mut(namespace) {
+            Some(ns) => {
+                if !ns.grants.iter().any(|grant| grant == env_name) {
+                    ns.grants.push(env_name.into());
+                }
+                Ok(())
+            }
+            None => Err(Error::App("There is no namespace with that name.")),
+        }
+    }
+
+    /// Creates an environment with a custom configuration within a namespace, unless
+    /// the namespace has as many environments as it may have.
+    pub fn create_namespaced_environment(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        config: EnvironmentConfig,
+        sd_handle: TriggerHandle,
+    ) -> Result<Environment> {
+        let mut inner = unlock!(self.inner);
+        let ns = match inner.namespaces.get(namespace) {
+            Some(ns) => ns,
+            None => return Err(Error::App("There is no namespace with that name.")),
+        };
+
+        if let Some(max) = ns.limits.max_environments {
+            if ns.environments.len() >= max {
+                return Err(Error::App("The namespace has too many environments."));
+            }
+        }
+
+        let name = format!("{}.{}", namespace, name);
+        if inner.environments.contains_key(&name) {
+            return Err(Error::App("Environment with that name already exists."));
+        }
+        if inner.aliases.contains_key(&name) {
+            return Err(Error::App("An alias with that name already exists."));
+        }
+
+        let env = inner.insert_environment(&name, sd_handle, None, config);
+        inner.namespaces.get_mut(namespace).unwrap().environments.push(name);
+
+        Ok(env)
+    }
+
+    /// Creates an entity within a namespace.
+    pub fn create_namespaced_entity(
+        &mut self,
+        namespace: &str,
+        sd_handle: TriggerHandle,
+    ) -> Result<EntityHost> {
+        let mut inner = unlock!(self.inner);
+        if !inner.namespaces.contains_key(namespace) {
+            return Err(Error::App("There is no namespace with that name."));
+        }
+
+        let entity = inner.insert_entity(EntityHost::new(sd_handle));
+        inner.namespaces.get_mut(namespace).unwrap().entities.push(entity.uuid().into());
+
+        Ok(entity)
+    }
+
+    /// Lets an entity of a namespace join environments of the namespace, or those it
+    /// was granted access to.
+    pub fn join_namespaced_environments(
+        &mut self,
+        namespace: &str,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let names = {
+            let inner = unlock!(self.inner);
+            inner.check_owner(namespace, entity)?;
+            environments
+                .iter()
+                .map(|env_name| inner.qualify(namespace, env_name))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        self.join_environments(entity, names.iter().map(String::as_str).collect())
+    }
+
+    /// Lets an entity of a namespace affect environments of the namespace, or those
+    /// it was granted access to.
+    pub fn affect_namespaced_environments(
+        &mut self,
+        namespace: &str,
+        entity: &mut EntityHost,
+        environments: Vec<&str>,
+    ) -> Result<()> {
+        let names = {
+            let inner = unlock!(self.inner);
+            inner.check_owner(namespace, entity)?;
+            environments
+                .iter()
+                .map(|env_name| inner.qualify(namespace, env_name))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        self.affect_environments(entity, names.iter().map(String::as_str).collect())
+    }
+
+    /// Submits an effect to an environment of a namespace, or one it was granted
+    /// access to, unless the namespace exceeds its effects per second.
+    pub fn submit_namespaced_effect(
+        &mut self,
+        namespace: &str,
+        effect: Effect,
+        env_name: &str,
+    ) -> Result<()> {
+        let env_name = {
+            let mut inner = unlock!(self.inner);
+            let env_name = inner.qualify(namespace, env_name)?;
+            if !inner.environments.contains_key(inner.resolve(&env_name))
+                && !inner.replicas.contains_key(&env_name)
+            {
+                return Err(Error::App("No environment with this name available"));
+            }
+            inner.admit(namespace)?;
+
+            env_name
+        };
+
+        self.submit_envelope(Envelope::new(effect), &env_name)
+    }
+
+    /// Returns true, if this supervisor manages an entity with the specified uuid,
+    /// otherwise false.
+    pub fn has_entity(&self, uuid: &str) -> bool {
+        let inner = unlock!(self.inner);
+        inner.entities.contains_key(uuid)
+    }
+
+    /// Returns the uuids of all entities stuck in a poll for longer than the
+    /// threshold, sorted.
+    pub fn stalled_entities(&self, threshold: Duration) -> Vec<String> {
+        let inner = unlock!(self.inner);
+
+        let mut stalled = inner
+            .entities
+            .iter()
+            .filter(|(_, conn)| conn.entity.is_stalled(threshold))
+            .map(|(uuid, _)| uuid.clone())
+            .collect::<Vec<_>>();
+        stalled.sort();
+
+        stalled
+    }
+
+    /// Returns the uuids of all entities whose core has been running for longer than
+    /// their processing timeout, sorted.
+    pub fn timed_out_entities(&self) -> Vec<String> {
+        let inner = unlock!(self.inner);
+
+        let mut timed_out = inner
+            .entities
+            .iter()
+            .filter(|(_, conn)| conn.entity.is_timed_out())
+            .map(|(uuid, _)| uuid.clone())
+            .collect::<Vec<_>>();
+        timed_out.sort();
+
+        timed_out
+    }
+
+    /// Cancels the invocations of the cores that exceed their processing timeout.
+    /// This doesn't depend on the supervisor being polled, so it can be called from
+    /// a thread watching over a runtime whose workers are blocked by those cores.
+    pub(crate) fn cancel_timed_out_entities(&self) {
+        for EntityConnection { entity, .. } in unlock!(self.inner).entities.values() {
+            entity.cancel_if_timed_out();
+        }
+    }
+
+    /// Spawns a thread that cancels the invocations exceeding their processing
+    /// timeout on every timer tick, until it's told to stop or the supervisor is
+    /// gone. Unlike the supervisor's own timer, it keeps watching while the workers
+    /// of the runtime are blocked.
+    pub(crate) fn spawn_watchdog(&self, stop: Arc<AtomicBool>) {
+        let inner = Arc::downgrade(&self.inner);
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(TIMER_RESOLUTION_MS));
+
+                match inner.upgrade() {
+                    Some(inner) => Supervisor { inner }.cancel_timed_out_entities(),
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Tells the cores of all entities to bail out of long-running invocations,
+    /// because the entities are about to end.
+    pub(crate) fn cancel_entities(&self) {
+        for EntityConnection { entity, .. } in unlock!(self.inner).entities.values() {
+            entity.cancel();
+        }
+    }
+
+    /// Returns a snapshot of the counters of all supervised environments and entities.
+    pub fn metrics(&self) -> SupervisorMetrics {
+        let inner = unlock!(self.inner);
+
+        SupervisorMetrics {
+            environments: inner.environment_metrics(),
+            entities: inner.entity_metrics(),
+            throughput: inner.throughput.current(),
+        }
+    }
+
+    /// Returns how often each environment and entity was woken up and polled, and
+    /// how many effects each poll received.
+    pub fn runtime_stats(&self) -> RuntimeStats {
+        let inner = unlock!(self.inner);
+
+        RuntimeStats {
+            environments: inner
+                .environments
+                .iter()
+                .map(|(name, conn)| (name.clone(), conn.environment.poll_stats()))
+                .collect(),
+            entities: inner
+                .entities
+                .iter()
+                .map(|(uuid, conn)| (uuid.clone(), conn.entity.poll_stats()))
+                .collect(),
+        }
+    }
+
+    /// Returns the number of effects all environments dropped for exceeding their hop
+    /// limit.
+    pub fn num_loop_dropped(&self) -> u64 {
+        let inner = unlock!(self.inner);
+
+        inner.environments.values().map(|conn| conn.environment.num_loop_dropped()).sum()
+    }
+
+    /// Returns a snapshot of the counters of the environments and entities of a
+    /// namespace, and of the effects submitted into it.
+    pub fn namespace_metrics(&self, namespace: &str) -> Result<NamespaceMetrics> {
+        let inner = unlock!(self.inner);
+        let ns = match inner.namespaces.get(namespace) {
+            Some(ns) => ns,
+            None => return Err(Error::App("There is no namespace with that name.")),
+        };
+
+        Ok(NamespaceMetrics {
+            name: namespace.into(),
+            environments: inner
+                .environment_metrics()
+                .into_iter()
+                .filter(|env| ns.environments.contains(&env.name))
+                .collect(),
+            entities: inner
+                .entity_metrics()
+                .into_iter()
+                .filter(|ent| ns.entities.contains(&ent.uuid))
+                .collect(),
+            num_submitted_effects: ns.num_submitted,
+            num_throttled_effects: ns.num_throttled,
+        })
+    }
+
+    /// Returns the number of effects broadcast by all environments, in total and per
+    /// second. The rates are updated on every timer tick.
+    pub fn throughput(&self) -> Throughput {
+        unlock!(self.inner).throughput.current()
+    }
+
+    /// Returns the number of supervised environments.
+    pub fn num_environments(&self) -> usize {
+        let inner = unlock!(self.inner);
+        inner.environments.len()
+    }
+
+    /// Returns the number of supervised entities.
+    pub fn num_entities(&self) -> usize {
+        let inner = unlock!(self.inner);
+        inner.entities.len()
+    }
+}
+
+impl Future for Supervisor {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        let mut inner = unlock!(self.inner);
+
+        // Check for shutdown signal
+        if let Ok(Async::Ready(Some(true))) = inner.shutdown_listener.0.poll() {
+            println!("Supervisor received sig-term");
+
+            // Let cores stuck in a long-running invocation bail out, so the entities
+            // can end
+            for EntityConnection { entity, .. } in inner.entities.values() {
+                entity.cancel();
+            }
+
+            // End this future
+            return Ok(Async::Ready(()));
+        }
+
+        // Wake all ticking or batching entities, so they can check if their next tick
+        // or batch is due, and cancel invocations exceeding the processing timeout
+        let mut ticked = false;
+        while let Ok(Async::Ready(Some(_))) = inner.timer.poll() {
+            for EntityConnection { entity, .. } in inner.entities.values() {
+                if entity.wants_timer() {
+                    entity.wake();
+                }
+                entity.cancel_if_timed_out();
+            }
+            ticked = true;
+        }
+
+        // Remove the entities whose core decided to terminate them
+        let terminated = inner
+            .entities
+            .iter()
+            .filter(|(_, conn)| conn.entity.is_terminated())
+            .map(|(uuid, _)| uuid.clone())
+            .collect::<Vec<_>>();
+        for uuid in terminated {
+            if let Some(ent_conn) = inner.remove_entity(&uuid) {
+                if let Err(e) = ent_conn.entity.send_sig_term() {
+                    println!("Failed to remove entity {}: {:?}", &uuid[0..5], e);
+                }
+            }
+        }
+
+        // Update the throughput rates
+        if ticked {
+            let now = inner.clock.now();
+            let total = inner.num_broadcast.load(Ordering::Relaxed);
+            inner.throughput.sample(now, total);
+        }
+
+        // otherwise go to sleep
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::shutdown::GracefulShutdown;
+
+    use std::sync::atomic::AtomicUsize;
+
+    use crossbeam_channel::{unbounded, Receiver, Sender};
+    use futures::executor::{self, Notify};
+    use tokio::runtime::Runtime;
+
+    /// Records all effects it processes.
+    struct Recorder(Arc<Mutex<Vec<Effect>>>);
+
+    impl Entity for Recorder {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            unlock!(self.0).push(effect.clone());
+            effect
+        }
+    }
+
+    /// Holds on to every effect until it's woken up, then passes it on.
+    struct Sleeper {
+        asleep: Sender<()>,
+        wake_up: Receiver<()>,
+    }
+
+    impl Sleeper {
+        /// Returns the core, a receiver telling when it fell asleep, and a sender
+        /// waking it up.
+        fn new() -> (Self, Receiver<()>, Sender<()>) {
+            let (asleep, asleep_rx) = unbounded();
+            let (wake_up_tx, wake_up) = unbounded();
+
+            (Self { asleep, wake_up }, asleep_rx, wake_up_tx)
+        }
+    }
+
+    impl Entity for Sleeper {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            self.asleep.send(()).unwrap();
+            self.wake_up.recv().unwrap();
+            effect
+        }
+    }
+
+    /// Counts the wake-ups of tasks that are polled step by step on the test thread.
+    struct Wakeups(AtomicUsize);
+
+    impl Wakeups {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(AtomicUsize::new(0)))
+        }
+
+        fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Notify for Wakeups {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Consumes prices and produces signals.
+    struct Signaler(&'static str);
+
+    impl Entity for Signaler {
+        fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+            effect
+        }
+
+        fn subscriptions(&self) -> CoreSubscriptions {
+            CoreSubscriptions {
+                joins: vec![self.0.into()],
+                affects: vec!["signals".into()],
+            }
+        }
+    }
+
+    /// Waits until the condition holds, or fails the test after a few seconds.
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for the condition");
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn delete_scoped_entities_at_the_end_of_their_scope() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+
+        {
+            let mut a = sv.create_scoped_entity(sd.get_listener()).unwrap();
+            runtime.spawn(a.clone().map_err(|_| ()));
+            sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+            assert_eq!(1, sv.num_entities());
+            assert!(a.has_joined(x.name()));
+        }
+        assert_eq!(0, sv.num_entities());
+        assert_eq!(0, x.num_joined_entities());
+
+        // Deleting it before its scope ends is fine, too
+        let a = sv.create_scoped_entity(sd.get_listener()).unwrap();
+        sv.delete_entity(a.uuid()).unwrap();
+        drop(a);
+        assert_eq!(0, sv.num_entities());
+    }
+
+    #[test]
     fn create_two_different_environments() {
-        let mut sv = Supervisor::new().unwrap();
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        sv.create_environment("X", sd.get_listener()).unwrap();
+        sv.create_environment("Y", sd.get_listener()).unwrap();
+
+        assert_eq!(2, sv.num_environments());
+    }
+
+    // Cannot create the same environment twice
+    #[should_panic]
+    #[test]
+    fn forbid_creating_the_same_environment_twice() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        sv.create_environment("X", sd.get_listener()).unwrap();
+        sv.create_environment("X", sd.get_listener()).unwrap();
+    }
+
+    #[test]
+    fn create_and_delete_environment() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        assert_eq!(1, sv.num_environments());
+
+        sv.delete_environment(x.name()).unwrap();
+        assert_eq!(0, sv.num_environments());
+    }
+
+    #[test]
+    fn has_environment_and_entity() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        assert!(!sv.has_environment("X"));
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let a = sv.create_entity(sd.get_listener()).unwrap();
+
+        assert!(sv.has_environment(x.name()));
+        assert!(sv.has_entity(a.uuid()));
+        assert!(!sv.has_entity("unknown"));
+
+        sv.delete_environment(x.name()).unwrap();
+        sv.delete_entity(a.uuid()).unwrap();
+
+        assert!(!sv.has_environment("X"));
+        assert!(!sv.has_entity(a.uuid()));
+    }
+
+    #[test]
+    fn drop_effects_exceeding_their_deadline() {
+        use crate::clock::ManualClock;
+
+        let sd = GracefulShutdown::new();
+        let clock = ManualClock::new();
+        let mut sv = Supervisor::new(sd.get_listener())
+            .unwrap()
+            .with_clock(shared!(clock.clone()));
+        let mut runtime = Runtime::new().unwrap();
+        let max_age = Duration::from_millis(50);
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // Expires before the environment broadcasts it
+        sv.submit_effect_with_deadline(Effect::from("stale"), x.name(), max_age).unwrap();
+        clock.advance(Duration::from_millis(100));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| x.num_expired() > 0);
+        assert_eq!(1, x.num_expired());
+
+        // Expires after it was broadcast, but before the entity takes it
+        a.pause_intake();
+        sv.submit_effect_with_deadline(Effect::from("late"), x.name(), max_age).unwrap();
+        wait_until(|| x.num_broadcast() > 0);
+        clock.advance(Duration::from_millis(100));
+        a.resume_intake();
+
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(1, a.num_expired_effects());
+        assert!(unlock!(received).is_empty());
+
+        // Fresh effects and those without a deadline are delivered
+        sv.submit_effect_with_deadline(Effect::from("fresh"), x.name(), max_age).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        clock.advance(Duration::from_secs(60));
+        sv.submit_effect(Effect::from("timeless"), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(1, x.num_expired());
+        assert_eq!(1, a.num_expired_effects());
+        assert_eq!(
+            vec![Effect::from("fresh"), Effect::from("timeless")],
+            *unlock!(received)
+        );
+    }
+
+    #[test]
+    fn expired_effects_leave_no_trace() {
+        use crate::clock::ManualClock;
+
+        let sd = GracefulShutdown::new();
+        let clock = ManualClock::new();
+        let mut sv = Supervisor::new(sd.get_listener())
+            .unwrap()
+            .with_clock(shared!(clock.clone()));
+        let mut runtime = Runtime::new().unwrap();
+        let max_age = Duration::from_millis(50);
+
+        let config = EnvironmentConfig::default().sequenced(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // The task of Z isn't spawned, so the mirrored effects stay in its channel
+        sv.create_environment("Z", sd.get_listener()).unwrap();
+        sv.mirror("X", MirrorTarget::Local("Z".into())).unwrap();
+
+        sv.submit_effect(Effect::from("first"), x.name()).unwrap();
+        sv.submit_effect_with_deadline(Effect::from("stale"), x.name(), max_age).unwrap();
+        clock.advance(Duration::from_millis(100));
+        sv.submit_effect(Effect::from("last"), x.name()).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        // The stale effect neither took a sequence number nor was mirrored
+        assert_eq!(1, x.num_expired());
+        assert_eq!(2, a.num_received_effects());
+        assert_eq!(0, a.gaps_detected());
+        assert_eq!(2, x.last_sequence());
+        assert_eq!(2, sv.mirror_status("X").unwrap()[0].num_mirrored);
+
+        // A submission waiting for a dropped effect is told so right away
+        let notified = x.notify_on_broadcast(42);
+        let envelope = Envelope {
+            submission: Some(42),
+            deadline: Some(clock.now()),
+            ..Envelope::new(Effect::from("late"))
+        };
+        clock.advance(Duration::from_millis(1));
+        sv.submit_envelope(envelope, x.name()).unwrap();
+
+        assert!(notified.recv_timeout(Duration::from_secs(5)).unwrap().is_err());
+        assert_eq!(2, x.num_expired());
+    }
+
+    #[test]
+    fn prune_orphans_and_empty_environments() {
+        use crate::clock::ManualClock;
+
+        let sd = GracefulShutdown::new();
+        let clock = ManualClock::new();
+        let mut sv = Supervisor::new(sd.get_listener())
+            .unwrap()
+            .with_clock(shared!(clock.clone()));
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let b = sv.create_entity(sd.get_listener()).unwrap();
+        let mut c = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments_deferred(&mut c, vec!["W"]).unwrap();
+
+        clock.advance(Duration::from_secs(10));
+        let z = sv.create_environment("Z", sd.get_listener()).unwrap();
+        sv.submit_effect(Effect::from("queued"), z.name()).unwrap();
+
+        // Nothing is old enough
+        let options = PruneOptions {
+            orphan_entities: true,
+            empty_environments: true,
+            older_than: Some(Duration::from_secs(60)),
+            ..PruneOptions::default()
+        };
+        assert_eq!(PruneReport::default(), sv.prune(options).unwrap());
+
+        // Only orphaned entities
+        let options = PruneOptions { orphan_entities: true, ..PruneOptions::default() };
+        assert_eq!(vec![b.uuid().to_string()], sv.prune(options).unwrap().entities);
+        assert!(sv.has_entity(a.uuid()));
+        assert!(sv.has_entity(c.uuid()));
+        assert_eq!(3, sv.num_environments());
+
+        // Empty environments, but not those with queued effects
+        let options =
+            PruneOptions { empty_environments: true, ..PruneOptions::default() };
+        assert_eq!(vec![y.name().to_string()], sv.prune(options).unwrap().environments);
+        assert!(sv.has_environment(x.name()));
+        assert!(sv.has_environment(z.name()));
+
+        let options = PruneOptions {
+            empty_environments: true,
+            force: true,
+            ..PruneOptions::default()
+        };
+        assert_eq!(vec![z.name().to_string()], sv.prune(options).unwrap().environments);
+        assert_eq!(1, sv.num_environments());
+    }
+
+    #[test]
+    fn mirror_effects_to_standby_supervisor() {
+        let sd = GracefulShutdown::new();
+        let mut primary = Supervisor::new(sd.get_listener()).unwrap();
+        let mut standby = Supervisor::new(sd.get_listener()).unwrap();
+
+        let queued = |sv: &Supervisor| {
+            sv.metrics()
+                .environments
+                .iter()
+                .map(|env| env.num_queued_effects)
+                .collect::<Vec<_>>()
+        };
+
+        primary.create_environment("X", sd.get_listener()).unwrap();
+        primary.create_environment("Y", sd.get_listener()).unwrap();
+        standby.create_environment("X", sd.get_listener()).unwrap();
+
+        assert!(primary.mirror_to(primary.clone()).is_err());
+        primary.mirror_to(standby.clone()).unwrap();
+
+        primary.submit_effect(Effect::from("a"), "X").unwrap();
+        primary.submit_correlated_effect(Effect::from("b"), "X", 1).unwrap();
+        assert_eq!(vec![2, 0], queued(&primary));
+        assert_eq!(vec![2], queued(&standby));
+        assert_eq!(0, primary.num_mirror_failures());
+
+        // The standby has no such environment
+        primary.submit_effect(Effect::from("c"), "Y").unwrap();
+        assert_eq!(vec![2, 1], queued(&primary));
+        assert_eq!(1, primary.num_mirror_failures());
+
+        // Copies aren't mirrored back
+        standby.mirror_to(primary.clone()).unwrap();
+        standby.submit_effect(Effect::from("d"), "X").unwrap();
+        assert_eq!(vec![3, 1], queued(&primary));
+        assert_eq!(vec![3], queued(&standby));
+    }
+
+    #[test]
+    fn mirror_effects_from_every_submission_path() {
+        let sd = GracefulShutdown::new();
+        let mut primary = Supervisor::new(sd.get_listener()).unwrap();
+        let mut standby = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let queued = |sv: &Supervisor, name: &str| {
+            sv.metrics()
+                .environments
+                .iter()
+                .find(|env| env.name == name)
+                .map(|env| env.num_queued_effects)
+                .unwrap()
+        };
+
+        let keyed = EnvironmentConfig::default().keyed(true);
+        let x = primary.create_environment("X", sd.get_listener()).unwrap();
+        primary
+            .create_environment_with_config("K", keyed.clone(), sd.get_listener())
+            .unwrap();
+        primary.create_priority_queue_environment("P", sd.get_listener()).unwrap();
+        standby.create_environment("X", sd.get_listener()).unwrap();
+        standby.create_environment_with_config("K", keyed, sd.get_listener()).unwrap();
+        standby.create_priority_queue_environment("P", sd.get_listener()).unwrap();
+        primary.mirror_to(standby.clone()).unwrap();
+
+        // Only the primary broadcasts, so the standby keeps all copies queued
+        let mut a = primary.create_entity(sd.get_listener()).unwrap();
+        primary.join_environments(&mut a, vec![x.name()]).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        let timeout = Duration::from_secs(5);
+        primary.submit_effect_sync(Effect::from("sync"), "X", timeout).unwrap();
+        assert_eq!(1, queued(&standby, "X"));
+
+        primary.submit_all(&[(Effect::from("all"), "X")]).unwrap();
+        assert_eq!(2, queued(&standby, "X"));
+
+        primary.raw_sender("X").unwrap().send(Effect::from("raw")).unwrap();
+        assert_eq!(3, queued(&standby, "X"));
+
+        primary.submit_keyed(Effect::from("A1"), "K", "A").unwrap();
+        primary.submit_keyed(Effect::from("A2"), "K", "A").unwrap();
+        assert_eq!(1, queued(&standby, "K"));
+
+        primary.submit_effect_prioritized(Effect::from("urgent"), "P", 9).unwrap();
+        assert_eq!(1, queued(&standby, "P"));
+
+        assert_eq!(0, primary.num_mirror_failures());
+    }
+
+    #[test]
+    fn deleting_an_environment_ends_affecting_it() {
+        use crate::eee::StartContext;
+
+        /// Records its start and the environments it lost.
+        struct Affector(Arc<Mutex<Vec<String>>>);
+
+        impl Entity for Affector {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn on_start(&mut self, _: &StartContext) -> Result<()> {
+                unlock!(self.0).push(String::from("started"));
+                Ok(())
+            }
+
+            fn on_affect_lost(&mut self, env: &str) {
+                unlock!(self.0).push(env.into());
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let lost = shared_mut!(vec![]);
+        a.inject_core(Box::new(Affector(Arc::clone(&lost))));
+        sv.affect_environments(&mut a, vec![x.name(), y.name()]).unwrap();
+
+        runtime.spawn(a.clone().map_err(|_| ()));
+        wait_until(|| !unlock!(lost).is_empty());
+
+        sv.delete_environment(y.name()).unwrap();
+
+        assert!(!a.is_affecting("Y"));
+        assert!(a.is_affecting("X"));
+        assert_eq!(vec![String::from("X")], a.affected_environments());
+        assert!(sv.submit_effect(Effect::from("lost"), "Y").is_err());
+
+        // The core is told during the next poll of its entity
+        wait_until(|| unlock!(lost).len() > 1);
+        assert_eq!(vec!["started", "Y"], *unlock!(lost));
+        assert_eq!(1, a.num_affected());
+    }
+
+    #[test]
+    fn delete_environment_while_a_core_is_busy() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let (sleeper, asleep, wake_up) = Sleeper::new();
+        a.inject_core(Box::new(sleeper));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from("block"), x.name()).unwrap();
+        asleep.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Deleting the environment doesn't wait for the core
+        let (done, deleted) = unbounded();
+        let mut deleting = sv.clone();
+        thread::spawn(move || done.send(deleting.delete_environment("Y").is_ok()));
+        assert_eq!(Ok(true), deleted.recv_timeout(Duration::from_secs(5)));
+        assert!(sv.create_environment("Z", sd.get_listener()).is_ok());
+
+        wake_up.send(()).unwrap();
+        wait_until(|| a.num_affected() == 0);
+    }
+
+    #[test]
+    fn ensure_environment_creates_only_once() {
+        let sd = GracefulShutdown::new();
+        let sv = Supervisor::new(sd.get_listener()).unwrap();
+        let num_created = Arc::new(AtomicUsize::new(0));
+
+        let handles = (0..8)
+            .map(|_| {
+                let mut sv = sv.clone();
+                let sd_handle = sd.get_listener();
+                let num_created = Arc::clone(&num_created);
+
+                thread::spawn(move || {
+                    let (env, created) =
+                        sv.get_or_create_environment("X", sd_handle).unwrap();
+                    if created {
+                        num_created.fetch_add(1, Ordering::SeqCst);
+                    }
+                    env
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            assert_eq!("X", handle.join().unwrap().name());
+        }
+
+        assert_eq!(1, num_created.load(Ordering::SeqCst));
+        assert_eq!(1, sv.num_environments());
+    }
+
+    #[test]
+    fn ensure_environment_returns_existing_one() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        sv.create_environment("X", sd.get_listener()).unwrap();
+        let x = sv.ensure_environment("X", sd.get_listener()).unwrap();
+
+        assert_eq!("X", x.name());
+        assert_eq!(1, sv.num_environments());
+    }
+
+    #[test]
+    fn loopback_direct_behaves_like_broadcasting() {
+        let run = |config: EnvironmentConfig| {
+            let sd = GracefulShutdown::new();
+            let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+            let mut runtime = Runtime::new().unwrap();
+
+            let x = sv
+                .create_environment_with_config("X", config, sd.get_listener())
+                .unwrap();
+            let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+            let received = shared_mut!(vec![]);
+            a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+            sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+            runtime.spawn(x.clone().map_err(|_| ()));
+            runtime.spawn(a.clone().map_err(|_| ()));
+
+            for i in 0..100_u64 {
+                sv.submit_effect(Effect::from(i), x.name()).unwrap();
+            }
+            a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+            let received = unlock!(received).clone();
+            (received, a.num_received_effects(), x.num_delivered_directly())
+        };
+
+        let (broadcast, num_broadcast, num_direct) = run(EnvironmentConfig::default());
+        assert_eq!((0..100_u64).map(Effect::from).collect::<Vec<_>>(), broadcast);
+        assert_eq!(100, num_broadcast);
+        assert_eq!(0, num_direct);
+
+        let config = EnvironmentConfig::default().loopback_direct(true);
+        let (direct, num_received, num_direct) = run(config);
+        assert_eq!(broadcast, direct);
+        assert_eq!(num_broadcast, num_received);
+        assert_eq!(100, num_direct);
+    }
+
+    #[test]
+    fn loopback_direct_falls_back_when_another_entity_joins() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().loopback_direct(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received_a = shared_mut!(vec![]);
+        let received_b = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received_a))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&received_b))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        for i in 0..200_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+            if i == 100 {
+                sv.join_environments(&mut b, vec![x.name()]).unwrap();
+            }
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        let received_a = unlock!(received_a).clone();
+        let received_b = unlock!(received_b).clone();
+
+        // The first entity misses nothing, the second one everything broadcast after
+        // it joined, and both keep the order
+        assert_eq!((0..200_u64).map(Effect::from).collect::<Vec<_>>(), received_a);
+        assert!(!received_b.is_empty());
+        assert_eq!(&received_a[200 - received_b.len()..], &received_b[..]);
+        assert!(x.num_delivered_directly() < 200);
+    }
+
+    #[test]
+    fn replay_history_to_all_joined_entities() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().history(10);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received_a = shared_mut!(vec![]);
+        let received_b = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received_a))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&received_b))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        let effects = (0..3_u64).map(Effect::from).collect::<Vec<_>>();
+        for effect in effects.iter() {
+            sv.submit_effect(effect.clone(), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+        assert_eq!(3, x.replay_all().unwrap());
+
+        // A new effect comes after the replayed ones
+        sv.submit_effect(Effect::from(3_u64), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        let mut replayed = effects.clone();
+        replayed.push(Effect::from(3_u64));
+        assert_eq!(replayed, *unlock!(received_b));
+        assert_eq!([&effects[..], &replayed[..]].concat(), *unlock!(received_a));
+
+        // Replays aren't kept or counted again
+        assert_eq!(replayed, x.history());
+        assert_eq!(4, x.num_received_effects());
+
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        assert!(y.replay_all().is_err());
+    }
+
+    #[test]
+    fn discard_pending_effects_selectively() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().loopback_direct(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name(), y.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        a.pause_intake();
+        for i in 0..10_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        wait_until(|| a.pending_count(x.name()).unwrap() == 10);
+        assert_eq!(10, a.pending_count(x.name()).unwrap());
+
+        let garbage =
+            [0_u64, 3, 6, 9].iter().map(|&i| Effect::from(i)).collect::<Vec<_>>();
+        let num = a.discard_pending(x.name(), |effect| garbage.contains(effect)).unwrap();
+        assert_eq!(4, num);
+        assert_eq!(6, a.pending_count(x.name()).unwrap());
+        assert_eq!(4, a.num_discarded_effects());
+
+        a.resume_intake();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        let survivors = [1_u64, 2, 4, 5, 7, 8].iter().map(|&i| Effect::from(i));
+        assert_eq!(survivors.collect::<Vec<_>>(), *unlock!(received));
+        assert_eq!(0, a.pending_count(x.name()).unwrap());
+
+        // Broadcast effects aren't in a mailbox of the entity
+        assert!(a.discard_pending(y.name(), |_| true).is_err());
+        assert!(a.pending_count("unknown").is_err());
+    }
+
+    #[test]
+    fn receive_effects_submitted_before_the_first_join() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        sv.set_strict_delivery(true);
+
+        let config = EnvironmentConfig::default().buffer_until_first_join(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        for i in 0..3_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        assert_eq!(0, x.num_received_effects());
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(3, a.num_received_effects());
+
+        // Only the first join releases the effects, later ones start out empty
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+        sv.submit_effect(Effect::from(3_u64), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(4, a.num_received_effects());
+        assert_eq!(1, b.num_received_effects());
+    }
+
+    #[test]
+    fn receive_effects_submitted_while_joining() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().buffer_until_first_join(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        let submitter = {
+            let mut sv = sv.clone();
+            thread::spawn(move || {
+                for i in 0..100_u64 {
+                    sv.submit_effect(Effect::from(i), "X").unwrap();
+                }
+            })
+        };
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        submitter.join().unwrap();
+
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(100, a.num_received_effects());
+    }
+
+    #[test]
+    fn receive_effects_submitted_right_after_joining() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        for i in 0..100_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(100, a.num_received_effects());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn route_outputs_by_regex() {
+        use crate::eee::RegexRouterCore;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let numbers = sv.create_environment("numbers", sd.get_listener()).unwrap();
+        let letters = sv.create_environment("letters", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        let mut c = sv.create_entity(sd.get_listener()).unwrap();
+
+        let router = RegexRouterCore::new()
+            .route(r"^\d+$", "numbers")
+            .unwrap()
+            .route(r"^[a-z]+$", "letters")
+            .unwrap();
+        a.inject_core(Box::new(router));
+        b.buffer_effects(true);
+        c.buffer_effects(true);
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![numbers.name(), letters.name()]).unwrap();
+        sv.join_environments(&mut b, vec![numbers.name()]).unwrap();
+        sv.join_environments(&mut c, vec![letters.name()]).unwrap();
+
+        for future in [x, numbers, letters] {
+            runtime.spawn(future.map_err(|_| ()));
+        }
+        for entity in [a.clone(), b.clone(), c.clone()] {
+            runtime.spawn(entity.map_err(|_| ()));
+        }
+
+        sv.submit_effect(Effect::ascii("123").unwrap(), "X").unwrap();
+        sv.submit_effect(Effect::ascii("ABC").unwrap(), "X").unwrap();
+        sv.submit_effect(Effect::ascii("abc").unwrap(), "X").unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Some(Effect::ascii("123").unwrap()), b.recv(timeout).unwrap());
+        assert_eq!(Some(Effect::ascii("abc").unwrap()), c.recv(timeout).unwrap());
+
+        // The unmatched text isn't emitted at all
+        a.wait_until_idle(timeout).unwrap();
+        assert_eq!(3, a.num_received_effects());
+        assert_eq!(2, a.num_emitted_effects());
+
+        let timeout = Duration::from_millis(50);
+        assert_eq!(None, b.recv(timeout).unwrap());
+        assert_eq!(None, c.recv(timeout).unwrap());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn refuse_to_route_to_unaffected_environments() {
+        use crate::eee::RegexRouterCore;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let numbers = sv.create_environment("numbers", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let router = RegexRouterCore::new()
+            .route(r"^\d+$", "numbers")
+            .unwrap()
+            .route(r"^[a-z]+$", "letters")
+            .unwrap();
+        a.inject_core(Box::new(router));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![numbers.name()]).unwrap();
+
+        runtime.spawn(x.map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| a.is_terminated());
+        assert!(a.take_start_error().is_some());
+        match sv.take_errors().pop() {
+            Some(Error::StartFailed { uuid, .. }) => assert_eq!(a.uuid(), uuid),
+            _ => panic!("expected the router to fail to start"),
+        }
+    }
+
+    #[test]
+    fn drain_entity_before_deleting_it() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let processed = shared_mut!(vec![]);
+        let forwarded = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&processed))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&forwarded))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        for future in [x.clone(), y] {
+            runtime.spawn(future.map_err(|_| ()));
+        }
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        // Build up a backlog of broadcast effects, before the entity is first polled
+        let timeout = Duration::from_secs(5);
+        for i in 0..10_u64 {
+            sv.submit_effect_sync(Effect::from(i), x.name(), timeout).unwrap();
+        }
+        assert!(unlock!(processed).is_empty());
+
+        runtime.spawn(a.clone().map_err(|_| ()));
+        sv.drain_entity(a.uuid(), timeout).unwrap();
+
+        let all = (0..10_u64).map(Effect::from).collect::<Vec<_>>();
+        assert_eq!(all, *unlock!(processed));
+        assert!(!sv.has_entity(a.uuid()));
+        assert!(!a.has_joined(x.name()));
+        assert_eq!(0, x.num_joined_entities());
+
+        b.wait_until_idle(timeout).unwrap();
+        assert_eq!(all, *unlock!(forwarded));
+    }
+
+    #[test]
+    fn higher_priority_effects_are_delivered_first() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_priority_queue_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        sv.submit_effect_prioritized(Effect::from("low"), x.name(), 1).unwrap();
+        sv.submit_effect(Effect::from("lowest"), x.name()).unwrap();
+        sv.submit_effect_prioritized(Effect::from("high"), x.name(), 9).unwrap();
+
+        // Only start processing after all effects were queued
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| unlock!(received).len() == 3);
+
+        assert_eq!(
+            vec![Effect::from("high"), Effect::from("low"), Effect::from("lowest")],
+            *unlock!(received)
+        );
+    }
+
+    #[test]
+    fn tell_cores_about_joined_environments() {
+        use crate::constants::BROADCAST_BUFFER_SIZE;
+        use crate::eee::{EnvInfo, StartContext};
+
+        /// Records what it is told about joined environments.
+        struct Joiner(Arc<Mutex<Vec<EnvInfo>>>, Arc<Mutex<Option<EnvInfo>>>);
+
+        impl Entity for Joiner {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn on_start(&mut self, ctx: &StartContext) -> Result<()> {
+                *unlock!(self.1) = ctx.env_info("X").cloned();
+                Ok(())
+            }
+
+            fn on_join(&mut self, info: &EnvInfo) {
+                unlock!(self.0).push(info.clone());
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().sequenced(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_priority_queue_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let joins = shared_mut!(vec![]);
+        let started = shared_mut!(None);
+        a.inject_core(Box::new(Joiner(Arc::clone(&joins), Arc::clone(&started))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        // Join another environment after the core was started
+        wait_until(|| !unlock!(joins).is_empty());
+        sv.join_environments(&mut a, vec![y.name()]).unwrap();
+        wait_until(|| unlock!(joins).len() == 2);
+
+        let info_x = EnvInfo {
+            name: String::from("X"),
+            buffer_size: BROADCAST_BUFFER_SIZE,
+            sequenced: true,
+            keyed: false,
+            prioritized: false,
+            auto_unbatching: false,
+        };
+        let info_y = EnvInfo {
+            name: String::from("Y"),
+            sequenced: false,
+            prioritized: true,
+            ..info_x.clone()
+        };
+        assert_eq!(vec![info_x.clone(), info_y.clone()], *unlock!(joins));
+        assert_eq!(Some(info_x), *unlock!(started));
+        assert_eq!(Some(info_y), a.env_info("Y"));
+        assert_eq!(None, a.env_info("Z"));
+    }
+
+    #[test]
+    fn start_core_before_processing() {
+        use crate::eee::StartContext;
+
+        /// Remembers the start context and refuses to start if told so.
+        struct Starter(Arc<Mutex<Vec<String>>>, bool);
+
+        impl Entity for Starter {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                unlock!(self.0).push(String::from("processed"));
+                effect
+            }
+
+            fn on_start(&mut self, ctx: &StartContext) -> Result<()> {
+                unlock!(self.0).push(ctx.joined_environments().join(","));
+                unlock!(self.0).push(ctx.affected_environments().join(","));
+                if self.1 {
+                    Ok(())
+                } else {
+                    Err(Error::App("no model"))
+                }
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let started = shared_mut!(vec![]);
+        let failed = shared_mut!(vec![]);
+        a.inject_core(Box::new(Starter(Arc::clone(&started), true)));
+        b.inject_core(Box::new(Starter(Arc::clone(&failed), false)));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        wait_until(|| unlock!(started).len() == 2 && b.is_terminated());
+        sv.submit_effect(Effect::from(1_u8), x.name()).unwrap();
+        wait_until(|| unlock!(started).len() == 3);
+
+        assert_eq!(vec!["X", "Y", "processed"], *unlock!(started));
+        assert!(a.take_start_error().is_none());
+
+        // The failed entity never processes anything and left its environments
+        assert_eq!(vec!["X", ""], *unlock!(failed));
+        assert!(b.take_start_error().is_some());
+        assert_eq!(0, b.num_joined());
+    }
+
+    #[test]
+    fn route_effects_by_environment() {
+        use crate::eee::RouteTarget;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let control = sv.create_environment("Control", sd.get_listener()).unwrap();
+        let data = sv.create_environment("Data", sd.get_listener()).unwrap();
+        let noise = sv.create_environment("Noise", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let processed = shared_mut!(vec![]);
+        let commands = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&processed))));
+
+        let cmds = Arc::clone(&commands);
+        a.route(
+            "Control",
+            RouteTarget::Callback(Box::new(move |e| unlock!(cmds).push(e))),
+        );
+        a.route("Noise", RouteTarget::Ignore);
+
+        sv.join_environments(&mut a, vec!["Control", "Data", "Noise"]).unwrap();
+
+        runtime.spawn(control.clone().map_err(|_| ()));
+        runtime.spawn(data.clone().map_err(|_| ()));
+        runtime.spawn(noise.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from("pause"), "Control").unwrap();
+        sv.submit_effect(Effect::from(1_u8), "Data").unwrap();
+        sv.submit_effect(Effect::from("static"), "Noise").unwrap();
+        sv.submit_effect(Effect::from(2_u8), "Data").unwrap();
+
+        wait_until(|| a.num_received_effects() == 4);
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(vec![Effect::from(1_u8), Effect::from(2_u8)], *unlock!(processed));
+        assert_eq!(vec![Effect::from("pause")], *unlock!(commands));
+        assert_eq!(4, a.num_received_effects());
+    }
+
+    #[test]
+    fn deliver_barrier_after_prior_effects_were_acked() {
+        /// Logs every effect and barrier it sees.
+        struct Logger(&'static str, Arc<Mutex<Vec<String>>>);
+
+        impl Entity for Logger {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                unlock!(self.1).push(format!("{} {:?}", self.0, effect));
+                effect
+            }
+
+            fn on_barrier(&mut self, id: u64) {
+                unlock!(self.1).push(format!("{} barrier {}", self.0, id));
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let log = shared_mut!(vec![]);
+        a.inject_core(Box::new(Logger("a", Arc::clone(&log))));
+        b.inject_core(Box::new(Logger("b", Arc::clone(&log))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from(1_u8), x.name()).unwrap();
+        sv.submit_effect(Effect::from(2_u8), x.name()).unwrap();
+        sv.submit_effect(Effect::Barrier(7), x.name()).unwrap();
+        sv.submit_effect(Effect::from(3_u8), x.name()).unwrap();
+
+        // 'a' didn't acknowledge anything yet, so the barrier and everything after it
+        // is held back
+        wait_until(|| x.num_received_effects() == 3 && unlock!(log).len() == 2);
+        assert_eq!(vec!["b U8(1)", "b U8(2)"], *unlock!(log));
+
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| unlock!(log).len() == 8);
+
+        let log = unlock!(log);
+        assert_eq!(8, log.len());
+        assert_eq!(vec!["b U8(1)", "b U8(2)", "a U8(1)", "a U8(2)"], log[0..4]);
+
+        // Each entity sees the barrier before the effects submitted after it
+        let position = |entry: &str| log.iter().position(|e| e == entry).unwrap();
+        assert!(position("a barrier 7") < position("a U8(3)"));
+        assert!(position("b barrier 7") < position("b U8(3)"));
+    }
+
+    #[test]
+    fn strict_delivery_requires_listeners() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        sv.submit_effect(Effect::from("dropped"), x.name()).unwrap();
+
+        sv.set_strict_delivery(true);
+        match sv.submit_effect(Effect::from("hello"), x.name()) {
+            Err(Error::App(msg)) => assert_eq!("no listeners", msg),
+            _ => panic!("expected an error"),
+        }
+
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+    }
+
+    #[test]
+    fn submit_dependent_effects_in_order() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // Nothing is broadcast, as long as the environment isn't running
+        let timeout = Duration::from_millis(50);
+        assert!(sv.submit_effect_sync(Effect::from(0_u8), x.name(), timeout).is_err());
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        let timeout = Duration::from_secs(5);
+        for i in 1..=20_u8 {
+            sv.submit_effect(Effect::from("load"), x.name()).unwrap();
+            sv.submit_effect_sync(Effect::from(i), x.name(), timeout).unwrap();
+            sv.submit_effect(Effect::from("load"), x.name()).unwrap();
+        }
+
+        wait_until(|| unlock!(received).len() == 61);
+
+        let numbers = unlock!(received)
+            .iter()
+            .filter_map(|effect| match effect {
+                Effect::U8(i) => Some(*i),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!((0..=20).collect::<Vec<_>>(), numbers);
+    }
+
+    #[test]
+    fn use_environments_by_alias() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        sv.create_environment("Y", sd.get_listener()).unwrap();
+        sv.alias("X", "OldX").unwrap();
+        sv.alias("OldX", "OlderX").unwrap();
+        assert!(sv.alias("Z", "OldZ").is_err());
+        assert!(sv.alias("X", "Y").is_err());
+        match sv.create_environment("OldX", sd.get_listener()) {
+            Err(Error::App(msg)) => {
+                assert_eq!("An alias with that name already exists.", msg)
+            }
+            _ => panic!("expected an error"),
+        }
+
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec!["OldX"]).unwrap();
+        assert!(sv.has_environment("OlderX"));
+        assert_eq!(vec![a.uuid()], x.joined_entities());
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from("hello"), "OldX").unwrap();
+        sv.submit_effect(Effect::from("world"), "OlderX").unwrap();
+        wait_until(|| a.num_received_effects() == 2);
+        assert_eq!(2, x.num_received_effects());
+        assert_eq!(2, a.num_received_effects());
+
+        sv.remove_alias("OlderX").unwrap();
+        assert!(sv.remove_alias("OlderX").is_err());
+        assert!(!sv.has_environment("OlderX"));
+        assert_eq!(vec![(String::from("OldX"), String::from("X"))], sv.aliases());
+
+        // Deleting under an alias deletes the environment and all its aliases
+        sv.delete_environment("OldX").unwrap();
+        assert!(!sv.has_environment("X"));
+        assert!(sv.aliases().is_empty());
+        sv.create_environment("OldX", sd.get_listener()).unwrap();
+    }
+
+    #[test]
+    fn join_and_affect_concurrently_without_deadlock() {
+        use std::sync::mpsc;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+
+        let (done_tx, done_rx) = mpsc::channel();
+        for _ in 0..4 {
+            let mut sv = sv.clone();
+            let listeners = (0..10).map(|_| sd.get_listener()).collect::<Vec<_>>();
+            let executor = runtime.executor();
+            let done_tx = done_tx.clone();
+
+            thread::spawn(move || {
+                for listener in listeners {
+                    let mut a = sv.create_entity(listener).unwrap();
+                    executor.spawn(a.clone().map_err(|_| ()));
+
+                    sv.join_environments(&mut a, vec!["X", "Y"]).unwrap();
+                    sv.affect_environments(&mut a, vec!["X", "Y"]).unwrap();
+                    sv.submit_effect(Effect::from("hello"), "X").unwrap();
+                }
+                done_tx.send(()).unwrap();
+            });
+        }
+
+        for _ in 0..4 {
+            done_rx.recv_timeout(Duration::from_secs(10)).expect("deadlocked");
+        }
+        assert_eq!(40, x.num_affecting_entities());
+        assert_eq!(40, y.num_joined_entities());
+    }
+
+    #[test]
+    fn submit_to_all_environments_or_none() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+
+        let effects = vec![
+            (Effect::from("hello"), x.name()),
+            (Effect::from("world"), y.name()),
+            (Effect::from("lost"), "Z"),
+        ];
+        assert!(sv.submit_all(&effects).is_err());
+
+        let queued = |sv: &Supervisor| {
+            sv.metrics()
+                .environments
+                .iter()
+                .map(|env| env.num_queued_effects)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![0, 0], queued(&sv));
+
+        sv.submit_all(&effects[..2]).unwrap();
+        assert_eq!(vec![1, 1], queued(&sv));
+    }
+
+    #[test]
+    fn pick_no_replicas_for_effects_that_werent_submitted() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        sv.create_replicated_environment("X", 2, sd.get_listener()).unwrap();
+
+        let queued = |sv: &Supervisor| {
+            sv.metrics()
+                .environments
+                .iter()
+                .map(|env| env.num_queued_effects)
+                .collect::<Vec<_>>()
+        };
+
+        let effects = vec![
+            (Effect::from("hello"), "X"),
+            (Effect::from("world"), "X"),
+            (Effect::from("lost"), "Z"),
+        ];
+        assert!(sv.submit_all(&effects).is_err());
+        assert_eq!(vec![0, 0], queued(&sv));
+
+        // The first replica is still next in turn
+        sv.submit_effect(Effect::from("first"), "X").unwrap();
+        assert_eq!(vec![1, 0], queued(&sv));
+
+        sv.submit_all(&effects[..2]).unwrap();
+        assert_eq!(vec![2, 1], queued(&sv));
+    }
+
+    #[test]
+    fn distribute_effects_by_weight() {
+        use crate::eee::Distribution;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let mut x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        let weights = vec![(a.uuid().to_string(), 3), (b.uuid().to_string(), 1)];
+        x.set_distribution(Distribution::Weighted(weights.into_iter().collect()));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        for i in 0..2000_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        wait_until(|| a.num_received_effects() + b.num_received_effects() == 2000);
+
+        // Each effect is received by exactly one entity, about 3 out of 4 by 'a'
+        assert_eq!(2000, a.num_received_effects() + b.num_received_effects());
+        assert!((1350..=1650).contains(&a.num_received_effects()));
+    }
+
+    #[test]
+    fn unbatch_or_pass_batches_through() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().auto_unbatch(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let unbatched = shared_mut!(vec![]);
+        let passed = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&unbatched))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&passed))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        for env in [&x, &y].iter() {
+            runtime.spawn((*env).clone().map_err(|_| ()));
+        }
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        let batch = Effect::batch(vec![
+            Effect::from(1_u8),
+            Effect::batch(vec![Effect::from(2_u8), Effect::from(3_u8)]),
+        ]);
+        sv.submit_effect(batch.clone(), x.name()).unwrap();
+        sv.submit_effect(batch.clone(), y.name()).unwrap();
+
+        wait_until(|| unlock!(unbatched).len() == 3 && unlock!(passed).len() == 1);
+
+        assert_eq!(
+            vec![Effect::from(1_u8), Effect::from(2_u8), Effect::from(3_u8)],
+            *unlock!(unbatched)
+        );
+        assert_eq!(3, x.num_received_effects());
+        assert_eq!(3, a.num_received_effects());
+
+        assert_eq!(vec![batch], *unlock!(passed));
+        assert_eq!(1, y.num_received_effects());
+        assert_eq!(1, b.num_received_effects());
+    }
+
+    #[test]
+    fn coalesce_queued_effects_by_key() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().keyed(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        sv.submit_keyed(Effect::from("A1"), x.name(), "A").unwrap();
+        sv.submit_keyed(Effect::from("A2"), x.name(), "A").unwrap();
+        sv.submit_keyed(Effect::from("B1"), x.name(), "B").unwrap();
+        sv.submit_keyed(Effect::from("A3"), x.name(), "A").unwrap();
+        assert!(sv.submit_keyed(Effect::from("A1"), y.name(), "A").is_err());
+
+        assert_eq!(Some(Effect::from("A3")), x.peek());
+        assert_eq!(2, sv.metrics().environments[0].num_queued_effects);
+
+        // Only start processing after all effects were queued
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| unlock!(received).len() == 2);
+
+        assert_eq!(vec![Effect::from("A3"), Effect::from("B1")], *unlock!(received));
+    }
+
+    #[test]
+    fn introspect_joined_and_affecting_entities() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+        assert_eq!(vec![a.uuid(), b.uuid()], x.joined_entities());
+        assert_eq!(2, x.num_joined_entities());
+        assert_eq!(0, x.num_affecting_entities());
+
+        sv.affect_environments(&mut a, vec![x.name()]).unwrap();
+        assert_eq!(vec![a.uuid()], x.affecting_entities());
+        assert_eq!(1, x.num_affecting_entities());
+
+        sv.leave_environments(&mut a, vec![x.name()]).unwrap();
+        assert_eq!(vec![b.uuid()], x.joined_entities());
+        assert_eq!(vec![a.uuid()], x.affecting_entities());
+
+        sv.delete_entity(a.uuid()).unwrap();
+        assert_eq!(vec![b.uuid()], x.joined_entities());
+        assert!(x.affecting_entities().is_empty());
+
+        sv.delete_entity(b.uuid()).unwrap();
+        assert_eq!(0, x.num_joined_entities());
+    }
+
+    #[test]
+    fn wait_until_entity_is_idle() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let processed = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&processed))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+
+        for i in 0..3_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        assert_eq!(3, a.pending());
+
+        runtime.spawn(a.clone().map_err(|_| ()));
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(3, unlock!(processed).len());
+        assert_eq!(0, a.pending());
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        assert_eq!(None, x.peek());
+
+        sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        sv.submit_effect(Effect::from("world"), x.name()).unwrap();
+
+        assert_eq!(Some(Effect::from("hello")), x.peek());
+        assert_eq!(Some(Effect::from("hello")), x.peek());
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        wait_until(|| unlock!(received).len() == 2);
+
+        assert_eq!(None, x.peek());
+        assert_eq!(2, x.num_received_effects());
+        assert_eq!(
+            vec![Effect::from("hello"), Effect::from("world")],
+            *unlock!(received)
+        );
+    }
+
+    #[test]
+    fn backfill_late_joiners_from_history() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().history(10);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+
+        for i in 1..=10_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        wait_until(|| x.history().len() == 10);
+
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+
+        let options = JoinOptions::default().backfill(5);
+        assert!(sv.join_environments_with(&mut a, y.name(), options.clone()).is_err());
+        sv.join_environments_with(&mut a, x.name(), options).unwrap();
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        for i in 11..=12_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        wait_until(|| unlock!(received).len() == 7);
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        // History first, then live, without duplicates at the seam
+        let expected = (6..=12_u8).map(Effect::from).collect::<Vec<_>>();
+        assert_eq!(expected, *unlock!(received));
+    }
+
+    #[test]
+    fn peek_at_effects_as_they_will_be_broadcast() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let config = EnvironmentConfig::default().history(2);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        sv.add_transform("X", |effect| match effect.as_str() {
+            Some(text) => Effect::from(text.to_uppercase().as_str()),
+            None => effect,
+        })
+        .unwrap();
+
+        sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        assert_eq!(Some(Effect::from("HELLO")), x.peek());
+
+        // Broadcast it, so it can be replayed
+        let mut task = executor::spawn(x.clone());
+        task.poll_future_notify(&Wakeups::new(), 0).unwrap();
+        assert_eq!(None, x.peek());
+
+        // Replays come before anything submitted, and were transformed already
+        sv.submit_effect(Effect::from("world"), x.name()).unwrap();
+        assert_eq!(1, x.replay_all().unwrap());
+        assert_eq!(Some(Effect::from("HELLO")), x.peek());
+    }
+
+    #[test]
+    fn forbid_prioritized_submission_to_fifo_environment() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+
+        assert!(sv.submit_effect_prioritized(Effect::from("high"), x.name(), 9).is_err());
+    }
+
+    #[test]
+    fn submit_two_effects() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![&x.name()]).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        sv.submit_and_wait(Effect::from("hello"), x.name(), timeout).unwrap();
+        sv.submit_and_wait(Effect::from("world"), x.name(), timeout).unwrap();
+
+        assert_eq!(2, x.num_received_effects());
+        assert_eq!(2, a.num_received_effects());
+    }
+
+    #[test]
+    fn submit_env_var() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
-        sv.create_environment("X").unwrap();
-        sv.create_environment("Y").unwrap();
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        a.buffer_effects(true);
 
-        assert_eq!(2, sv.num_environments());
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        std::env::set_var("REEE_TEST_SUBMIT_ENV_VAR", "debug");
+        sv.submit_env_var("REEE_TEST_SUBMIT_ENV_VAR", x.name()).unwrap();
+        assert!(sv.submit_env_var("REEE_TEST_UNSET_ENV_VAR", x.name()).is_err());
+
+        assert_eq!(
+            Some(Effect::ascii("debug").unwrap()),
+            a.recv(Duration::from_secs(5)).unwrap()
+        );
+        assert_eq!(1, x.num_received_effects());
     }
 
-    // Cannot create the same environment twice
-    #[should_panic]
     #[test]
-    fn forbid_creating_the_same_environment_twice() {
-        let mut sv = Supervisor::new().unwrap();
+    fn count_effects_broadcast_without_listeners() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
-        sv.create_environment("X").unwrap();
-        sv.create_environment("X").unwrap();
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from("void"), x.name()).unwrap();
+        wait_until(|| x.num_broadcast_no_listeners() == 1);
+
+        // Effects someone listens to aren't counted
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.submit_effect(Effect::from("heard"), x.name()).unwrap();
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(1, a.num_received_effects());
+        assert_eq!(1, x.num_broadcast_no_listeners());
     }
 
     #[test]
-    fn create_and_delete_environment() {
-        let mut sv = Supervisor::new().unwrap();
+    fn drop_effects_circulating_in_a_feedback_loop() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
-        let x = sv.create_environment("X").unwrap();
-        assert_eq!(1, sv.num_environments());
+        let config = EnvironmentConfig::default().max_hops(3);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
 
-        sv.delete_environment(&x.name()).unwrap();
-        assert_eq!(0, sv.num_environments());
+        let received_a = Arc::new(Mutex::new(vec![]));
+        let received_b = Arc::new(Mutex::new(vec![]));
+        a.inject_core(Box::new(Recorder(Arc::clone(&received_a))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&received_b))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        // X -> a -> Y -> b -> X
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+        sv.affect_environments(&mut b, vec![x.name()]).unwrap();
+
+        sv.submit_effect(Effect::from("echo"), x.name()).unwrap();
+
+        wait_until(|| sv.num_loop_dropped() > 0);
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        // The effect reaches X again after 2 and 4 hops, the second time it's dropped
+        assert_eq!(1, x.num_loop_dropped());
+        assert_eq!(1, sv.num_loop_dropped());
+        assert_eq!(2, unlock!(received_a).len());
+        assert_eq!(2, unlock!(received_b).len());
     }
 
     #[test]
-    fn submit_two_effects() {
-        let mut sv = Supervisor::new().unwrap();
+    fn drop_effects_circulating_through_a_batching_core() {
+        use crate::eee::entity::BatchingCore;
 
-        let x = sv.create_environment("X").unwrap();
-        let mut a = sv.create_entity().unwrap();
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
 
-        sv.join_environments(&mut a, vec![&x.name()]).unwrap();
+        let config = EnvironmentConfig::default().max_hops(3);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
 
-        sv.submit_effect("hello", &x.name()).unwrap();
-        sv.submit_effect("world", &x.name()).unwrap();
+        let received_b = Arc::new(Mutex::new(vec![]));
+        let recorder = Box::new(Recorder(Arc::new(Mutex::new(vec![]))));
+        a.inject_core(Box::new(BatchingCore::new(recorder, 1, Duration::from_secs(1))));
+        b.inject_core(Box::new(Recorder(Arc::clone(&received_b))));
 
-        // Wait a little until the effects have propagated
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
 
-        assert_eq!(2, x.num_received_effects());
+        // X -> a (batching) -> Y -> b -> X
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+        sv.affect_environments(&mut b, vec![x.name()]).unwrap();
+
+        sv.submit_effect(Effect::from("echo"), x.name()).unwrap();
+
+        wait_until(|| sv.num_loop_dropped() > 0);
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+
+        // Batch outputs keep the hops of their inputs, so the loop ends just the same
+        assert_eq!(1, x.num_loop_dropped());
         assert_eq!(2, a.num_received_effects());
+        assert_eq!(2, unlock!(received_b).len());
+    }
+
+    #[test]
+    fn count_polls_and_effects_per_poll() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let notify = Wakeups::new();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let mut env_task = executor::spawn(x.clone());
+        let mut ent_task = executor::spawn(a.clone());
+        let mut step_env = || env_task.poll_future_notify(&notify, 0).unwrap();
+        let mut step_ent = || ent_task.poll_future_notify(&notify, 0).unwrap();
+
+        for batch in [3_u64, 0, 2, 4].iter() {
+            for i in 0..*batch {
+                sv.submit_effect(Effect::from(i), x.name()).unwrap();
+            }
+            step_env();
+            step_ent();
+        }
+        step_ent();
+
+        let env_stats = x.poll_stats();
+        assert_eq!(4, env_stats.num_polls);
+        assert_eq!(1, env_stats.num_spurious_polls);
+        assert_eq!((2, 4), (env_stats.min_per_poll, env_stats.max_per_poll));
+        assert_eq!(3.0, env_stats.avg_per_poll());
+        assert!(env_stats.num_wakes > 0);
+
+        let ent_stats = a.poll_stats();
+        assert_eq!(5, ent_stats.num_polls);
+        assert_eq!(2, ent_stats.num_spurious_polls);
+
+        // The effects of all polls add up to the received effects
+        assert_eq!(x.num_received_effects() as u64, env_stats.num_handled);
+        assert_eq!(a.num_received_effects() as u64, ent_stats.num_handled);
+        assert_eq!(9, ent_stats.num_handled);
+
+        let stats = sv.runtime_stats();
+        assert_eq!(Some(&env_stats), stats.environments.get(x.name()));
+        assert_eq!(Some(&ent_stats), stats.entities.get(a.uuid()));
+        assert_eq!(env_stats, sv.metrics().environments[0].polls);
+    }
+
+    #[test]
+    fn submit_through_raw_sender() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        a.buffer_effects(true);
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let sender = sv.raw_sender(x.name()).unwrap();
+        sender.clone().send(Effect::from("raw")).unwrap();
+        assert_eq!(Some(Effect::from("raw")), a.recv(Duration::from_secs(5)).unwrap());
+
+        // The sender turns inert once the environment is deleted
+        sv.delete_environment(x.name()).unwrap();
+        assert!(sender.is_closed());
+        match sender.send(Effect::from("lost")) {
+            Err(Error::ChannelClosed) => (),
+            _ => panic!("expected the channel to be closed"),
+        }
+
+        let queue = sv.create_priority_queue_environment("Q", sd.get_listener()).unwrap();
+        assert!(sv.raw_sender(queue.name()).is_err());
+        assert!(sv.raw_sender("unknown").is_err());
+    }
+
+    #[test]
+    fn deliver_everything_sent_through_raw_senders() {
+        const NUM_EFFECTS: u64 = 10_000;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        // Raw senders and submitted effects end up in the same queue
+        let sender = sv.raw_sender(x.name()).unwrap();
+        let senders = (0..2)
+            .map(|_| {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    for i in 0..NUM_EFFECTS {
+                        sender.send(Effect::from(i)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 0..NUM_EFFECTS {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        for sender in senders {
+            sender.join().unwrap();
+        }
+
+        a.wait_until_idle(Duration::from_secs(30)).unwrap();
+        assert_eq!(3 * NUM_EFFECTS as usize, x.num_received_effects());
+        assert_eq!(3 * NUM_EFFECTS as usize, a.num_received_effects());
+
+        // Every effect made it, none twice
+        let mut received = unlock!(received).clone();
+        received.sort();
+        let expected =
+            (0..NUM_EFFECTS).flat_map(|i| vec![Effect::from(i); 3]).collect::<Vec<_>>();
+        assert_eq!(expected, received);
+    }
+
+    #[test]
+    fn coalesce_notifies_until_the_next_poll() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let wakeups = Wakeups::new();
+
+        // Nobody joins, so nothing is held back for an entity that isn't driven
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+
+        let mut task = executor::spawn(x.clone());
+        let mut step = || task.poll_future_notify(&wakeups, 0).unwrap();
+
+        for _ in 0..3 {
+            step();
+            let num_notifies = x.num_notifies();
+            let num_wakeups = wakeups.count();
+
+            // Without a poll in between, only the first notification gets through
+            for i in 0..100 {
+                sv.submit_effect(Effect::from(i as u64), x.name()).unwrap();
+            }
+            assert_eq!(num_notifies + 1, x.num_notifies());
+            assert_eq!(num_wakeups + 1, wakeups.count());
+        }
+
+        // Nothing got lost by the coalescing
+        step();
+        assert_eq!(300, x.num_received_effects());
+    }
+
+    #[test]
+    fn hold_back_effects_while_the_broadcast_bus_is_full() {
+        use crate::constants::BROADCAST_BUFFER_SIZE;
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        // Polls are driven step by step on this thread, so a blocking broadcast
+        // would never return
+        let env_wakeups = Wakeups::new();
+        let ent_wakeups = Wakeups::new();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        a.inject_core(Box::new(Recorder(Arc::clone(&received))));
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let mut env_task = executor::spawn(x.clone());
+        let mut ent_task = executor::spawn(a.clone());
+
+        let num_effects = 5 * BROADCAST_BUFFER_SIZE as u64;
+        for i in 0..num_effects {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        // The environment only passes on what the entity can take, and holds back
+        // the next effect instead of taking in any more
+        env_task.poll_future_notify(&env_wakeups, 0).unwrap();
+        assert!(x.num_received_effects() < num_effects as usize);
+        assert!(x.num_broadcast() < num_effects);
+        assert!(unlock!(received).is_empty());
+
+        // Each time the entity drained its mailbox, the environment is woken to pass
+        // on more of what it held back
+        let mut num_rounds = 0;
+        while unlock!(received).len() < num_effects as usize {
+            assert!(num_rounds < num_effects, "the held back effects got stuck");
+            num_rounds += 1;
+
+            let num_env_wakeups = env_wakeups.count();
+            ent_task.poll_future_notify(&ent_wakeups, 0).unwrap();
+            if x.num_broadcast() < num_effects {
+                assert!(env_wakeups.count() > num_env_wakeups);
+            }
+            env_task.poll_future_notify(&env_wakeups, 0).unwrap();
+        }
+
+        // Nothing got lost or reordered while it was held back
+        let expected = (0..num_effects).map(Effect::from).collect::<Vec<_>>();
+        assert_eq!(expected, *unlock!(received));
+    }
+
+    #[test]
+    fn join_while_effects_are_submitted() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        b.inject_core(Box::new(Recorder(Arc::clone(&received))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        let (halfway, submitting) = unbounded();
+        let submitter = {
+            let mut sv = sv.clone();
+            thread::spawn(move || {
+                for i in 0..2000_u64 {
+                    sv.submit_effect(Effect::from(i), "X").unwrap();
+                    if i == 1000 {
+                        halfway.send(()).unwrap();
+                    }
+                }
+            })
+        };
+
+        submitting.recv_timeout(Duration::from_secs(5)).unwrap();
+        sv.join_environments(&mut b, vec![x.name()]).unwrap();
+
+        submitter.join().unwrap();
+        wait_until(|| unlock!(received).last() == Some(&Effect::from(1999_u64)));
+        wait_until(|| a.num_received_effects() == 2000);
+
+        // B must have received every effect from some point on, without gaps or
+        // duplicates
+        let received = unlock!(received);
+        let first = match received.first() {
+            Some(Effect::U64(first)) => *first,
+            _ => panic!("expected to receive effects after joining"),
+        };
+        let expected = (first..2000).map(Effect::from).collect::<Vec<_>>();
+        assert_eq!(expected, *received);
+    }
+
+    #[test]
+    fn forbid_joining_a_dropped_environment() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+
+        let mut x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        sv.delete_environment("X").unwrap();
+
+        assert!(x.register_joining_entity(&mut a).is_err());
+        assert!(!a.has_joined("X"));
+    }
+
+    #[test]
+    fn detect_gap_after_rejoining() {
+        use crate::eee::environment::EnvironmentConfig;
+        use crate::eee::GapDetected;
+
+        /// Records all gaps it is told about.
+        struct GapRecorder(Arc<Mutex<Vec<GapDetected>>>);
+
+        impl Entity for GapRecorder {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn on_gap(&mut self, gap: &GapDetected) {
+                unlock!(self.0).push(gap.clone());
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let config = EnvironmentConfig::default().sequenced(true);
+        let x =
+            sv.create_environment_with_config("X", config, sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+
+        let gaps = shared_mut!(vec![]);
+        a.inject_core(Box::new(GapRecorder(Arc::clone(&gaps))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        let submit = |sv: &mut Supervisor, n| {
+            let num_received = x.num_received_effects() + n;
+            for _ in 0..n {
+                let timeout = Duration::from_secs(5);
+                sv.submit_and_wait(Effect::from("hello"), "X", timeout).unwrap();
+            }
+            wait_until(|| x.num_received_effects() == num_received);
+        };
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        submit(&mut sv, 3);
+
+        sv.leave_environments(&mut a, vec![x.name()]).unwrap();
+        assert!(!a.has_joined(x.name()));
+        submit(&mut sv, 2);
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        submit(&mut sv, 2);
+
+        assert_eq!(5, a.num_received_effects());
+        assert_eq!(1, a.gaps_detected());
+        assert_eq!(vec![GapDetected { env: "X".into(), from: 4, to: 5 }], *unlock!(gaps));
+    }
+
+    #[test]
+    fn trace_correlated_effect_through_pipeline() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        sv.enable_flow_tracing();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let z = sv.create_environment("Z", sd.get_listener()).unwrap();
+
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        a.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+        b.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+
+        for future in [x.clone(), y.clone(), z.clone()] {
+            runtime.spawn(future.map_err(|_| ()));
+        }
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        // X -> A -> Y -> B -> Z
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+        sv.affect_environments(&mut b, vec![z.name()]).unwrap();
+
+        sv.submit_effect(Effect::from("uncorrelated"), x.name()).unwrap();
+        sv.submit_correlated_effect(Effect::from("hello"), x.name(), 7).unwrap();
+
+        wait_until(|| z.num_received_effects() == 2);
+        assert_eq!(
+            vec![
+                TraceStep::Received { environment: "X".into() },
+                TraceStep::Processed { entity: a.uuid().into(), environment: "X".into() },
+                TraceStep::Emitted { entity: a.uuid().into() },
+                TraceStep::Received { environment: "Y".into() },
+                TraceStep::Processed { entity: b.uuid().into(), environment: "Y".into() },
+                TraceStep::Emitted { entity: b.uuid().into() },
+                TraceStep::Received { environment: "Z".into() },
+            ],
+            sv.trace(7)
+        );
+        assert!(sv.trace(8).is_empty());
+    }
+
+    #[test]
+    fn clone_environment_for_ab_processing() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        assert!(sv.clone_environment("X", "X").is_err());
+        assert!(sv.clone_environment("X", "Z").is_err());
+        sv.clone_environment("X", "Y").unwrap();
+        assert!(sv.clone_environment("X", "Y").is_err());
+
+        sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        sv.submit_effect(Effect::from("world"), y.name()).unwrap();
+
+        wait_until(|| a.num_received_effects() == 1 && b.num_received_effects() == 2);
+
+        assert_eq!(1, x.num_received_effects());
+        assert_eq!(2, y.num_received_effects());
+        assert_eq!(1, a.num_received_effects());
+        assert_eq!(2, b.num_received_effects());
+    }
+
+    #[test]
+    fn mirror_into_standby_environment() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+
+        let received = shared_mut!(vec![]);
+        b.inject_core(Box::new(Recorder(Arc::clone(&received))));
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        sv.mirror("X", MirrorTarget::Local("Y".into())).unwrap();
+        assert!(sv.mirror("Y", MirrorTarget::Local("X".into())).is_err());
+
+        // The task of Z isn't spawned, so it never takes in any effect
+        sv.create_environment("Z", sd.get_listener()).unwrap();
+        sv.mirror("X", MirrorTarget::Local("Z".into())).unwrap();
+
+        for i in 0..5_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        wait_until(|| a.num_received_effects() == 5 && unlock!(received).len() == 5);
+
+        assert_eq!((0..5_u8).map(Effect::from).collect::<Vec<_>>(), *unlock!(received));
+        assert_eq!(
+            vec![
+                MirrorStatus { target: "Y".into(), num_mirrored: 5, lag: 0 },
+                MirrorStatus { target: "Z".into(), num_mirrored: 5, lag: 5 },
+            ],
+            sv.mirror_status("X").unwrap()
+        );
+
+        // Breaking a mirror leaves the primary untouched
+        sv.unmirror("X", "Y").unwrap();
+        let timeout = Duration::from_secs(5);
+        sv.submit_and_wait(Effect::from(5_u8), x.name(), timeout).unwrap();
+
+        assert_eq!(6, a.num_received_effects());
+        assert_eq!(5, b.num_received_effects());
+        assert_eq!(1, sv.mirror_status("X").unwrap().len());
+    }
+
+    #[test]
+    fn transform_effects_in_order() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        a.buffer_effects(true);
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        sv.add_transform("X", |effect| match effect.as_str() {
+            Some(text) => Effect::from(text.trim()),
+            None => effect,
+        })
+        .unwrap();
+        sv.add_transform("X", |effect| match effect.as_str() {
+            Some(text) => Effect::from(text.to_uppercase().as_str()),
+            None => effect,
+        })
+        .unwrap();
+        // Sees the length of the trimmed text
+        sv.add_transform("X", |effect| match effect.as_str() {
+            Some(text) => Effect::from(format!("{}:{}", text, text.len()).as_str()),
+            None => effect,
+        })
+        .unwrap();
+        assert!(sv.add_transform("Y", |effect| effect).is_err());
+
+        let timeout = Duration::from_secs(5);
+        sv.submit_effect(Effect::from("  hello "), x.name()).unwrap();
+        assert_eq!(Some(Effect::from("HELLO:5")), a.recv(timeout).unwrap());
+
+        sv.clear_transforms("X").unwrap();
+        sv.submit_effect(Effect::from("  hello "), x.name()).unwrap();
+        assert_eq!(Some(Effect::from("  hello ")), a.recv(timeout).unwrap());
+    }
+
+    #[test]
+    fn connect_entities_to_environments_created_later() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        let mut c = sv.create_entity(sd.get_listener()).unwrap();
+        a.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+        b.buffer_effects(true);
+
+        // X exists already, so a joins it right away
+        sv.join_environments_deferred(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments_deferred(&mut a, vec!["Y"]).unwrap();
+        sv.join_environments_deferred(&mut b, vec!["Y"]).unwrap();
+        sv.affect_environments_deferred(&mut c, vec!["Z"]).unwrap();
+
+        assert_eq!(1, a.num_joined());
+        let uuid = |entity: &EntityHost| entity.uuid().to_string();
+        assert_eq!(vec![(uuid(&b), "Y".to_string())], sv.pending_joins());
+        let mut expected = vec![(uuid(&a), "Y".to_string()), (uuid(&c), "Z".to_string())];
+        expected.sort();
+        assert_eq!(expected, sv.pending_affects());
+
+        // Deleting an entity drops its pending edges
+        sv.delete_entity(c.uuid()).unwrap();
+        sv.create_environment("Z", sd.get_listener()).unwrap();
+        assert_eq!(0, c.num_affected());
+
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        assert!(sv.pending_joins().is_empty());
+        assert!(sv.pending_affects().is_empty());
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        sv.submit_effect(Effect::from("hello"), x.name()).unwrap();
+        assert_eq!(Some(Effect::from("hello")), b.recv(Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn distribute_effects_to_replicas() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let replicas =
+            sv.create_replicated_environment("X", 3, sd.get_listener()).unwrap();
+        assert_eq!(
+            vec!["X-0", "X-1", "X-2"],
+            replicas.iter().map(|env| env.name()).collect::<Vec<_>>()
+        );
+        assert!(sv.create_replicated_environment("X", 2, sd.get_listener()).is_err());
+        assert!(sv.create_environment("X-1", sd.get_listener()).is_err());
+
+        let mut entities = vec![];
+        for env in replicas.iter() {
+            let mut entity = sv.create_entity(sd.get_listener()).unwrap();
+            sv.join_environments(&mut entity, vec![env.name()]).unwrap();
+
+            runtime.spawn(env.clone().map_err(|_| ()));
+            runtime.spawn(entity.clone().map_err(|_| ()));
+            entities.push(entity);
+        }
+
+        for i in 0..9_u8 {
+            sv.submit_effect(Effect::from(i), "X").unwrap();
+        }
+
+        for (env, entity) in replicas.iter().zip(entities.iter()) {
+            entity.wait_until_idle(Duration::from_secs(5)).unwrap();
+            assert_eq!(3, env.num_received_effects());
+            assert_eq!(3, entity.num_received_effects());
+        }
+
+        // A deleted replica doesn't get any effects anymore
+        sv.delete_environment("X-2").unwrap();
+        for i in 0..4_u8 {
+            sv.submit_effect(Effect::from(i), "X").unwrap();
+        }
+        for entity in entities[..2].iter() {
+            entity.wait_until_idle(Duration::from_secs(5)).unwrap();
+            assert_eq!(5, entity.num_received_effects());
+        }
+    }
+
+    #[test]
+    fn reduce_a_burst_of_effects() {
+        use crate::eee::entity::ReduceCore;
+
+        /// Sums up numbers.
+        struct Sum;
+
+        impl Entity for Sum {
+            fn process_effect(&mut self, effect: Effect, _: &str) -> Effect {
+                effect
+            }
+
+            fn reduce(&mut self, acc: Effect, next: Effect) -> Effect {
+                match (acc, next) {
+                    (Effect::U64(acc), Effect::U64(next)) => Effect::from(acc + next),
+                    (_, next) => next,
+                }
+            }
+        }
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        a.inject_core(Box::new(ReduceCore::new(Box::new(Sum))));
+        b.buffer_effects(true);
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        // Let the whole burst be broadcast before the entity is first polled
+        for i in 1..=10_u64 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        wait_until(|| x.num_received_effects() == 10);
+        runtime.spawn(a.clone().map_err(|_| ()));
+
+        assert_eq!(Some(Effect::from(55_u64)), b.recv(Duration::from_secs(5)).unwrap());
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(10, a.num_received_effects());
+        assert_eq!(None, b.recv(Duration::from_millis(50)).unwrap());
+    }
+
+    #[test]
+    fn wire_entities_by_core_subscriptions() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let eur = sv.create_environment("prices.eur", sd.get_listener()).unwrap();
+        let usd = sv.create_environment("prices.usd", sd.get_listener()).unwrap();
+        sv.create_environment("news", sd.get_listener()).unwrap();
+
+        let signaler = Box::new(Signaler("prices.*"));
+        let a = sv.create_entity_auto(signaler, sd.get_listener()).unwrap();
+        let mut joined = a.joined_environments();
+        joined.sort();
+        assert_eq!(vec!["prices.eur", "prices.usd"], joined);
+        assert_eq!(vec![(a.uuid().to_string(), "signals".into())], sv.pending_affects());
+
+        let signals = sv.create_environment("signals", sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        b.buffer_effects(true);
+        sv.join_environments(&mut b, vec![signals.name()]).unwrap();
+
+        for env in [eur, usd, signals] {
+            runtime.spawn(env.map_err(|_| ()));
+        }
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        let timeout = Duration::from_secs(5);
+        sv.submit_effect(Effect::from("1.09"), "prices.eur").unwrap();
+        assert_eq!(Some(Effect::from("1.09")), b.recv(timeout).unwrap());
+        sv.submit_effect(Effect::from("0.92"), "prices.usd").unwrap();
+        assert_eq!(Some(Effect::from("0.92")), b.recv(timeout).unwrap());
+    }
+
+    #[test]
+    fn wire_wrapped_cores_by_their_subscriptions() {
+        use crate::eee::entity::{BatchingCore, ReduceCore};
+
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        sv.create_environment("prices", sd.get_listener()).unwrap();
+        sv.create_environment("signals", sd.get_listener()).unwrap();
+
+        let window = Duration::from_secs(1);
+        let cores: Vec<Box<dyn Entity>> = vec![
+            Box::new(BatchingCore::new(Box::new(Signaler("prices")), 5, window)),
+            Box::new(ReduceCore::new(Box::new(Signaler("prices")))),
+        ];
+        for core in cores {
+            let a = sv.create_entity_auto(core, sd.get_listener()).unwrap();
+            assert_eq!(vec!["prices"], a.joined_environments());
+            assert!(a.is_affecting("signals"));
+        }
+    }
+
+    #[test]
+    fn count_emitted_effects() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        a.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+
+        for i in 0..20_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        b.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(20, a.num_received_effects());
+        assert_eq!(20, a.num_emitted_effects());
+        assert_eq!(20, b.num_received_effects());
+        assert_eq!(0, b.num_emitted_effects());
+
+        let metrics = sv.metrics();
+        let a_metrics = metrics.entities.iter().find(|ent| ent.uuid == a.uuid()).unwrap();
+        assert_eq!(20, a_metrics.num_emitted_effects);
+    }
+
+    #[test]
+    fn emit_buffered_outputs_once_affecting() {
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
+        let y = sv.create_environment("Y", sd.get_listener()).unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
+        let mut c = sv.create_entity(sd.get_listener()).unwrap();
+        a.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+        a.buffer_outputs(5);
+        b.buffer_effects(true);
+        c.inject_core(Box::new(Recorder(shared_mut!(vec![]))));
+        c.buffer_outputs(2);
+
+        sv.join_environments(&mut a, vec![x.name()]).unwrap();
+        sv.join_environments(&mut b, vec![y.name()]).unwrap();
+        sv.join_environments(&mut c, vec![x.name()]).unwrap();
+
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(y.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
+        runtime.spawn(c.clone().map_err(|_| ()));
+
+        for i in 0..5_u8 {
+            sv.submit_effect(Effect::from(i), x.name()).unwrap();
+        }
+        a.wait_until_idle(Duration::from_secs(5)).unwrap();
+        c.wait_until_idle(Duration::from_secs(5)).unwrap();
+        assert_eq!(5, a.num_buffered_outputs());
+        assert_eq!(0, a.num_dropped_outputs());
+        assert_eq!(2, c.num_buffered_outputs());
+        assert_eq!(3, c.num_dropped_outputs());
+
+        sv.affect_environments(&mut a, vec![y.name()]).unwrap();
+        sv.submit_effect(Effect::from(5_u8), x.name()).unwrap();
+
+        for i in 0..6_u8 {
+            assert_eq!(Some(Effect::from(i)), b.recv(Duration::from_secs(5)).unwrap());
+        }
+        assert_eq!(None, b.recv(Duration::from_millis(100)).unwrap());
+        assert_eq!(0, a.num_buffered_outputs());
+        assert_eq!(6, a.num_emitted_effects());
     }
 
     #[test]
     fn submit_many_effects_to_two_entities() {
-        let mut sv = Supervisor::new().unwrap();
+        let sd = GracefulShutdown::new();
+        let mut sv = Supervisor::new(sd.get_listener()).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let x = sv.create_environment("X", sd.get_listener()).unwrap();
 
-        let x = sv.create_environment("X").unwrap();
+        let mut a = sv.create_entity(sd.get_listener()).unwrap();
+        let mut b = sv.create_entity(sd.get_listener()).unwrap();
 
-        let mut a = sv.create_entity().unwrap();
-        let mut b = sv.create_entity().unwrap();
+        runtime.spawn(x.clone().map_err(|_| ()));
+        runtime.spawn(a.clone().map_err(|_| ()));
+        runtime.spawn(b.clone().map_err(|_| ()));
 
         sv.join_environments(&mut a, vec![&x.name()]).unwrap();
         sv.join_environments(&mut b, vec![&x.name()]).unwrap();
 
         for i in 0..729 {
-            sv.submit_effect(&i.to_string(), &x.name()).unwrap();
+            sv.submit_effect(Effect::from(i.to_string()), x.name()).unwrap();
         }
 
-        // Wait a little until the effects have propagated
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        wait_until(|| a.num_received_effects() == 729 && b.num_received_effects() == 729);
 
         assert_eq!(729, x.num_received_effects());
         assert_eq!(729, a.num_received_effects());
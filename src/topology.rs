@@ -0,0 +1,324 @@
+//! Topologies of environments and entities, checked before they are built.
+
+use crate::eee::entity::StartContext;
+use crate::eee::Entity;
+use crate::errors::Result;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Creates an entity core, or fails if the core can't be constructed.
+pub type CoreFactory = Box<dyn Fn() -> Result<Box<dyn Entity>> + Send>;
+
+/// The environments and entities a node is supposed to run.
+#[derive(Clone, Debug, Default)]
+pub struct TopologySpec {
+    /// The names of the environments to create
+    pub environments: Vec<String>,
+    /// The entities to create
+    pub entities: Vec<EntitySpec>,
+    /// Whether effects may circulate, because an entity affects an environment it
+    /// receives its effects from, directly or through other entities
+    pub allow_cycles: bool,
+    /// The most environments the topology may have
+    pub max_environments: Option<usize>,
+    /// The most entities the topology may have
+    pub max_entities: Option<usize>,
+    /// Whether every core is constructed and started in a sandbox to catch cores
+    /// failing to do so
+    pub start_cores: bool,
+}
+
+/// An entity of a topology.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EntitySpec {
+    /// The name the entity is referred to by in diagnostics
+    pub name: String,
+    /// The name of the registered core the entity runs
+    pub core: String,
+    /// The names of the environments the entity joins
+    pub joins: Vec<String>,
+    /// The names of the environments the entity affects
+    pub affects: Vec<String>,
+}
+
+/// A problem found in a topology.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Diagnostic {
+    /// An environment is named more than once, or exists already.
+    DuplicateEnvironment(String),
+
+    /// An entity is named more than once.
+    DuplicateEntity(String),
+
+    /// An entity runs a core that wasn't registered.
+    UnknownCore {
+        /// The name of the entity
+        entity: String,
+        /// The name of the core
+        core: String,
+    },
+
+    /// An entity joins an environment that doesn't exist.
+    DanglingJoin {
+        /// The name of the entity
+        entity: String,
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// An entity affects an environment that doesn't exist.
+    DanglingAffect {
+        /// The name of the entity
+        entity: String,
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// Effects can circulate through the environments, from the first one back to
+    /// it.
+    Cycle(Vec<String>),
+
+    /// The topology has more environments than allowed.
+    TooManyEnvironments {
+        /// The number of environments
+        count: usize,
+        /// The most environments allowed
+        max: usize,
+    },
+
+    /// The topology has more entities than allowed.
+    TooManyEntities {
+        /// The number of entities
+        count: usize,
+        /// The most entities allowed
+        max: usize,
+    },
+
+    /// The core of an entity couldn't be constructed or started.
+    CoreFailed {
+        /// The name of the entity
+        entity: String,
+        /// Why it failed
+        reason: String,
+    },
+
+    /// No entity joins an environment, so nobody receives its effects.
+    NoConsumers(String),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::DuplicateEnvironment(env) => {
+                write!(f, "environment '{}' is defined twice", env)
+            }
+            Diagnostic::DuplicateEntity(entity) => {
+                write!(f, "entity '{}' is defined twice", entity)
+            }
+            Diagnostic::UnknownCore { entity, core } => {
+                write!(f, "entity '{}' runs unknown core '{}'", entity, core)
+            }
+            Diagnostic::DanglingJoin { entity, environment } => {
+                write!(
+                    f,
+                    "entity '{}' joins unknown environment '{}'",
+                    entity, environment
+                )
+            }
+            Diagnostic::DanglingAffect { entity, environment } => {
+                write!(
+                    f,
+                    "entity '{}' affects unknown environment '{}'",
+                    entity, environment
+                )
+            }
+            Diagnostic::Cycle(path) => {
+                write!(f, "effects circulate: {}", path.join(" -> "))
+            }
+            Diagnostic::TooManyEnvironments { count, max } => {
+                write!(f, "{} environments exceed the limit of {}", count, max)
+            }
+            Diagnostic::TooManyEntities { count, max } => {
+                write!(f, "{} entities exceed the limit of {}", count, max)
+            }
+            Diagnostic::CoreFailed { entity, reason } => {
+                write!(f, "the core of entity '{}' failed: {}", entity, reason)
+            }
+            Diagnostic::NoConsumers(env) => {
+                write!(f, "environment '{}' has no consumers", env)
+            }
+        }
+    }
+}
+
+/// The outcome of validating a topology.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidationReport {
+    /// Problems that keep the topology from being built as specified
+    pub errors: Vec<Diagnostic>,
+    /// Oddities that don't keep the topology from being built
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    /// Returns true, if the topology has no errors.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Validates a topology against the registered cores and the environments that
+/// exist already, without creating anything.
+pub(crate) fn validate<F>(
+    spec: &TopologySpec,
+    cores: &HashMap<String, CoreFactory>,
+    exists: F,
+) -> ValidationReport
+where
+    F: Fn(&str) -> bool,
+{
+    let mut report = ValidationReport::default();
+
+    let mut environments = HashSet::new();
+    for env in spec.environments.iter() {
+        if !environments.insert(env.as_str()) || exists(env.as_str()) {
+            report.errors.push(Diagnostic::DuplicateEnvironment(env.clone()));
+        }
+    }
+    let known = |env: &str| environments.contains(env) || exists(env);
+
+    let mut entities = HashSet::new();
+    for entity in spec.entities.iter() {
+        if !entities.insert(entity.name.as_str()) {
+            report.errors.push(Diagnostic::DuplicateEntity(entity.name.clone()));
+        }
+    }
+
+    if let Some(max) = spec.max_environments {
+        if environments.len() > max {
+            let count = environments.len();
+            report.errors.push(Diagnostic::TooManyEnvironments { count, max });
+        }
+    }
+    if let Some(max) = spec.max_entities {
+        if spec.entities.len() > max {
+            let count = spec.entities.len();
+            report.errors.push(Diagnostic::TooManyEntities { count, max });
+        }
+    }
+
+    // Effects flow from every environment an entity joins to every one it affects
+    let mut edges = BTreeMap::<&str, BTreeSet<&str>>::new();
+    for entity in spec.entities.iter() {
+        match cores.get(&entity.core) {
+            Some(factory) if spec.start_cores => {
+                if let Err(reason) = start_in_sandbox(factory, entity) {
+                    let entity = entity.name.clone();
+                    report.errors.push(Diagnostic::CoreFailed { entity, reason });
+                }
+            }
+            Some(_) => (),
+            None => report.errors.push(Diagnostic::UnknownCore {
+                entity: entity.name.clone(),
+                core: entity.core.clone(),
+            }),
+        }
+
+        for env in entity.joins.iter().filter(|env| !known(env.as_str())) {
+            report.errors.push(Diagnostic::DanglingJoin {
+                entity: entity.name.clone(),
+                environment: env.clone(),
+            });
+        }
+        for env in entity.affects.iter().filter(|env| !known(env.as_str())) {
+            report.errors.push(Diagnostic::DanglingAffect {
+                entity: entity.name.clone(),
+                environment: env.clone(),
+            });
+        }
+
+        for from in entity.joins.iter().filter(|env| known(env.as_str())) {
+            edges.entry(from.as_str()).or_default().extend(
+                entity
+                    .affects
+                    .iter()
+                    .filter(|env| known(env.as_str()))
+                    .map(String::as_str),
+            );
+        }
+    }
+
+    if !spec.allow_cycles {
+        report.errors.extend(find_cycles(&edges).into_iter().map(Diagnostic::Cycle));
+    }
+
+    for env in spec.environments.iter() {
+        if !spec.entities.iter().any(|entity| entity.joins.contains(env)) {
+            report.warnings.push(Diagnostic::NoConsumers(env.clone()));
+        }
+    }
+
+    report
+}
+
+/// Constructs the core of an entity and starts it, without a hosting entity. The
+/// core is dropped right away.
+fn start_in_sandbox(
+    factory: &CoreFactory,
+    entity: &EntitySpec,
+) -> std::result::Result<(), String> {
+    let ctx = StartContext::detached(entity.joins.clone(), entity.affects.clone());
+
+    let started = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut core = factory()?;
+        core.on_start(&ctx)
+    }));
+
+    match started {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("{:?}", e)),
+        Err(_) => Err(String::from("panicked")),
+    }
+}
+
+/// Returns the paths along which effects get back to an environment they passed.
+fn find_cycles<'a>(edges: &BTreeMap<&'a str, BTreeSet<&'a str>>) -> Vec<Vec<String>> {
+    let mut cycles = vec![];
+    let mut done = HashSet::new();
+
+    for &env in edges.keys() {
+        visit(env, edges, &mut vec![], &mut done, &mut cycles);
+    }
+
+    cycles
+}
+
+fn visit<'a>(
+    env: &'a str,
+    edges: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    if done.contains(env) {
+        return;
+    }
+
+    if let Some(start) = path.iter().position(|&passed| passed == env) {
+        let mut cycle =
+            path[start..].iter().map(|env| env.to_string()).collect::<Vec<_>>();
+        cycle.push(env.to_string());
+        cycles.push(cycle);
+        return;
+    }
+
+    path.push(env);
+    for &next in edges.get(env).into_iter().flatten() {
+        visit(next, edges, path, done, cycles);
+    }
+    path.pop();
+
+    done.insert(env);
+}
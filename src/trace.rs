@@ -0,0 +1,63 @@
+//! Flow tracing of correlated effects.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// A hop of a correlated effect on its way through environments and entities.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TraceStep {
+    /// An environment received the effect.
+    Received {
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// An entity processed the effect it received from an environment.
+    Processed {
+        /// The uuid of the entity
+        entity: String,
+        /// The name of the environment
+        environment: String,
+    },
+
+    /// An entity emitted the resulting effect to its affected environments.
+    Emitted {
+        /// The uuid of the entity
+        entity: String,
+    },
+}
+
+/// Records the hops of correlated effects while enabled.
+#[derive(Default)]
+pub(crate) struct FlowTracer {
+    /// Whether hops are recorded
+    enabled: AtomicBool,
+
+    /// The recorded hops by correlation id
+    steps: Mutex<HashMap<u64, Vec<TraceStep>>>,
+}
+
+impl FlowTracer {
+    /// Starts recording hops.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Records a hop of an effect, if tracing is enabled and the effect is correlated.
+    pub fn record<F>(&self, correlation_id: Option<u64>, step: F)
+    where
+        F: FnOnce() -> TraceStep,
+    {
+        if let Some(id) = correlation_id {
+            if self.enabled.load(Ordering::Relaxed) {
+                unlock!(self.steps).entry(id).or_insert_with(Vec::new).push(step());
+            }
+        }
+    }
+
+    /// Returns the recorded hops of effects with the given correlation id.
+    pub fn steps(&self, correlation_id: u64) -> Vec<TraceStep> {
+        unlock!(self.steps).get(&correlation_id).cloned().unwrap_or_default()
+    }
+}
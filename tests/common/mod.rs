@@ -1,15 +1,15 @@
-use ::reee::eee::entity::Entity;
+use ::reee::eee::entity::EntityHost;
 use ::reee::eee::environment::Environment;
-use ::reee::supervisor::Supervisor;
+use ::reee::node::Node;
 
 #[macro_use]
 pub mod macros;
 
-/// Creates a supervisor, and environment X, and an entity
-pub fn get_supervisor_environment_entity() -> (Supervisor, Environment, Entity) {
-    let mut sv = Supervisor::new().unwrap();
-    let x = sv.create_environment("X").unwrap();
-    let mut a = sv.create_entity().unwrap();
-    sv.join_environments(&mut a, vec![&x.name()]).unwrap();
-    (sv, x, a)
+/// Creates a node, and environment X, and an entity
+pub fn get_node_environment_entity() -> (Node, Environment, EntityHost) {
+    let mut node = Node::new().unwrap();
+    let x = node.create_environment("X").unwrap();
+    let mut a = node.create_entity().unwrap();
+    node.join_environments(&mut a, vec![&x.name()]).unwrap();
+    (node, x, a)
 }
@@ -1,4 +1,4 @@
-use ::reee::supervisor::Supervisor;
+use ::reee::eee::Effect;
 
 #[macro_use]
 mod common;
@@ -8,13 +8,17 @@ use crate::common::*;
 #[test]
 fn pipe() {
     //
-    let mut sv = Supervisor::new().unwrap();
+    let (mut node, x, mut a) = get_node_environment_entity();
 
-    let x = sv.create_environment("X").unwrap();
-    let y = sv.create_environment("Y").unwrap();
-    let mut a = sv.create_entity().unwrap();
-    sv.join_environments(&mut a, vec![&x.name()]).unwrap();
-    sv.affect_environments(&mut a, vec![&y.name()]).unwrap();
+    let y = node.create_environment("Y").unwrap();
+    node.affect_environments(&mut a, vec![&y.name()]).unwrap();
 
-    sv.submit_effect("hello", &x.name()).unwrap();
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+
+    // Wait a little until the effect has propagated
+    sleep!(100);
+
+    assert_eq!(1, x.num_received_effects());
+    assert_eq!(1, a.num_received_effects());
+    assert_eq!(1, y.num_received_effects());
 }
@@ -0,0 +1,127 @@
+#![cfg(feature = "tracing")]
+
+use ::reee::eee::Effect;
+
+#[macro_use]
+mod common;
+
+use crate::common::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+thread_local! {
+    static CURRENT: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A subscriber that records every span and event as a line of text.
+struct Capture {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, String>>,
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.0, " {:?}", value).unwrap();
+        } else {
+            write!(self.0, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut fields = Fields(span.metadata().name().to_string());
+        span.record(&mut fields);
+
+        self.lines.lock().unwrap().push(format!("span {}", fields.0));
+        self.spans.lock().unwrap().insert(id, fields.0);
+
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let parent = CURRENT.with(|current| current.borrow().last().cloned());
+        let parent = match parent {
+            Some(id) => self.spans.lock().unwrap()[&id].clone(),
+            None => String::from("<none>"),
+        };
+
+        let mut fields = Fields(String::new());
+        event.record(&mut fields);
+
+        self.lines.lock().unwrap().push(format!("event {} >{}", parent, fields.0));
+    }
+
+    fn enter(&self, span: &Id) {
+        CURRENT.with(|current| current.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, _: &Id) {
+        CURRENT.with(|current| current.borrow_mut().pop());
+    }
+}
+
+#[test]
+fn spans_of_one_effect_through_a_pipe() {
+    let lines = Arc::new(Mutex::new(vec![]));
+    let capture = Capture {
+        next_id: AtomicU64::new(0),
+        spans: Mutex::new(HashMap::new()),
+        lines: Arc::clone(&lines),
+    };
+    tracing::subscriber::set_global_default(capture).unwrap();
+
+    let (mut node, x, mut a) = get_node_environment_entity();
+    let y = node.create_environment("Y").unwrap();
+    node.affect_environments(&mut a, vec![y.name()]).unwrap();
+
+    node.submit_effect(Effect::from("hello"), x.name()).unwrap();
+
+    // Wait a little until the effect has propagated
+    sleep!(100);
+
+    node.shutdown().unwrap();
+
+    let lines = lines.lock().unwrap();
+    let position = |expected: &str| {
+        lines
+            .iter()
+            .position(|line| line == expected)
+            .unwrap_or_else(|| panic!("missing '{}' in {:#?}", expected, lines))
+    };
+
+    let x_received = position(
+        "event env.broadcast env=X > effect received source=\"supervisor\" size=5",
+    );
+    let a_processed =
+        position(&format!("span entity.process env=X entity={} seq=1 size=5", a.uuid()));
+    let y_received = position(&format!(
+        "event env.broadcast env=Y > effect received source=\"entity\" entity={} \
+         size=0",
+        a.uuid()
+    ));
+
+    assert!(x_received < a_processed);
+    assert!(a_processed < y_received);
+}